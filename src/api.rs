@@ -0,0 +1,404 @@
+use crate::chain;
+use crate::error::SkaniError;
+use crate::file_io;
+use crate::params::*;
+use crate::regression;
+use crate::screen;
+use crate::seeding;
+use crate::types::*;
+use gbdt::gradient_boost::GBDT;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+
+///Sketching presets, mirroring the --fast, --medium, --slow and
+///--small-genomes command line options.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Preset {
+    #[default]
+    Default,
+    Fast,
+    Medium,
+    Slow,
+    SmallGenomes,
+}
+
+impl Preset {
+    ///Returns (c, marker_c) for the preset.
+    pub fn c_and_marker_c(&self) -> (usize, usize) {
+        let m = MARKER_C_DEFAULT.parse::<usize>().unwrap();
+        match self {
+            Preset::Default => (DEFAULT_C.parse::<usize>().unwrap(), m),
+            Preset::Fast => (FAST_C, m),
+            Preset::Medium => (MEDIUM_C, m),
+            Preset::Slow => (SLOW_C, m),
+            Preset::SmallGenomes => (SLOW_C, SMALL_M),
+        }
+    }
+
+    pub fn sketch_params(&self) -> SketchParams {
+        let (c, m) = self.c_and_marker_c();
+        let k = DEFAULT_K.parse::<usize>().unwrap();
+        SketchParams::new(m, c, k, false, false).unwrap()
+    }
+}
+
+///Builder for an `AniCalculator`. Defaults are the same as `skani dist`.
+///Identity and aligned fraction values are fractions in [0,1], not percentages.
+pub struct AniCalculatorBuilder {
+    preset: Preset,
+    c: Option<usize>,
+    marker_c: Option<usize>,
+    screen_val: Option<f64>,
+    min_aligned_frac: Option<f64>,
+    learned_ani: Option<bool>,
+    robust: bool,
+    median: bool,
+    rescue_small: Option<bool>,
+    threads: Option<usize>,
+}
+
+impl Default for AniCalculatorBuilder {
+    fn default() -> Self {
+        AniCalculatorBuilder {
+            preset: Preset::Default,
+            c: None,
+            marker_c: None,
+            screen_val: None,
+            min_aligned_frac: None,
+            learned_ani: None,
+            robust: false,
+            median: false,
+            rescue_small: None,
+            threads: None,
+        }
+    }
+}
+
+impl AniCalculatorBuilder {
+    pub fn new() -> AniCalculatorBuilder {
+        AniCalculatorBuilder::default()
+    }
+
+    pub fn preset(mut self, preset: Preset) -> Self {
+        self.preset = preset;
+        self
+    }
+
+    ///Overrides the compression factor of the preset.
+    pub fn c(mut self, c: usize) -> Self {
+        self.c = Some(c);
+        self
+    }
+
+    ///Overrides the marker compression factor of the preset.
+    pub fn marker_c(mut self, marker_c: usize) -> Self {
+        self.marker_c = Some(marker_c);
+        self
+    }
+
+    ///Identity cutoff for marker screening. 0 disables screening.
+    pub fn screen_val(mut self, screen_val: f64) -> Self {
+        self.screen_val = Some(screen_val);
+        self
+    }
+
+    pub fn min_aligned_frac(mut self, min_aligned_frac: f64) -> Self {
+        self.min_aligned_frac = Some(min_aligned_frac);
+        self
+    }
+
+    ///Force the regression model on or off. By default it is used
+    ///whenever `skani dist` would use it.
+    pub fn learned_ani(mut self, learned_ani: bool) -> Self {
+        self.learned_ani = Some(learned_ani);
+        self
+    }
+
+    pub fn robust(mut self, robust: bool) -> Self {
+        self.robust = robust;
+        self
+    }
+
+    pub fn median(mut self, median: bool) -> Self {
+        self.median = median;
+        self
+    }
+
+    ///Let genomes with very few markers always pass the screen. Off for the
+    ///small-genomes preset, on otherwise.
+    pub fn rescue_small(mut self, rescue_small: bool) -> Self {
+        self.rescue_small = Some(rescue_small);
+        self
+    }
+
+    ///Run `sketch` and `ani_many` in their own pool with this many threads
+    ///instead of the caller's current rayon pool.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    pub fn build(self) -> Result<AniCalculator, SkaniError> {
+        let (preset_c, preset_m) = self.preset.c_and_marker_c();
+        let c = self.c.unwrap_or(preset_c);
+        let marker_c = self.marker_c.unwrap_or(preset_m);
+        let k = DEFAULT_K.parse::<usize>().unwrap();
+        let sketch_params = SketchParams::new(marker_c, c, k, false, false)?;
+
+        let screen_val = self.screen_val.unwrap_or(SEARCH_ANI_CUTOFF_DEFAULT);
+        if !(0. ..=1.).contains(&screen_val) {
+            return Err(SkaniError::InvalidParameter(format!(
+                "screen value {} is not in [0,1]",
+                screen_val
+            )));
+        }
+        let min_aligned_frac = self
+            .min_aligned_frac
+            .unwrap_or(D_FRAC_COVER_CUTOFF.parse::<f64>().unwrap() / 100.);
+        if min_aligned_frac > 1. {
+            return Err(SkaniError::InvalidParameter(format!(
+                "minimum aligned fraction {} is > 1",
+                min_aligned_frac
+            )));
+        }
+        let learned_ani = self
+            .learned_ani
+            .unwrap_or_else(|| regression::use_learned_ani(c, false, false, self.median));
+        let model = regression::get_model(c, learned_ani);
+        let rescue_small = self
+            .rescue_small
+            .unwrap_or(self.preset != Preset::SmallGenomes);
+        let pool = match self.threads {
+            Some(threads) => Some(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()?,
+            ),
+            None => None,
+        };
+
+        Ok(AniCalculator {
+            sketch_params,
+            screen_val,
+            min_aligned_frac,
+            robust: self.robust,
+            median: self.median,
+            rescue_small,
+            model,
+            pool,
+        })
+    }
+}
+
+///Sketches in-memory genomes and computes ANI between them without touching the file system.
+pub struct AniCalculator {
+    sketch_params: SketchParams,
+    screen_val: f64,
+    min_aligned_frac: f64,
+    robust: bool,
+    median: bool,
+    rescue_small: bool,
+    model: Option<GBDT>,
+    pool: Option<ThreadPool>,
+}
+
+impl AniCalculator {
+    pub fn builder() -> AniCalculatorBuilder {
+        AniCalculatorBuilder::new()
+    }
+
+    pub fn sketch_params(&self) -> &SketchParams {
+        &self.sketch_params
+    }
+
+    ///Sketches one genome given as `(contig name, sequence)` records. Contigs
+    ///shorter than 500 bp are skipped, as for fasta inputs. Contigs are seeded
+    ///on separate threads.
+    pub fn sketch<'a, I>(&self, name: &str, records: I) -> Result<Sketch, SkaniError>
+    where
+        I: IntoIterator<Item = (&'a str, &'a [u8])>,
+    {
+        let mut new_sketch = Sketch::new(
+            self.sketch_params.marker_c,
+            self.sketch_params.c,
+            self.sketch_params.k,
+            name.to_string(),
+            self.sketch_params.use_aa,
+        );
+        let mut j = 0;
+        let mut contigs = vec![];
+        for (contig, seq) in records {
+            if seq.len() >= MIN_LENGTH_CONTIG {
+                new_sketch.contigs.push(contig.to_string());
+                new_sketch.contig_lengths.push(seq.len() as GnPosition);
+                new_sketch.total_sequence_length += seq.len();
+                new_sketch.masked_length += seeding::masked_bases(seq, &self.sketch_params);
+                new_sketch.ambiguous_bases += seeding::ambiguous_bases(seq);
+                contigs.push((j, seq));
+                j += 1;
+            }
+        }
+        let seed = |sketch: &mut Sketch| {
+            file_io::seed_contigs_parallel(&contigs, &self.sketch_params, sketch, true, SEED_WINDOW_LENGTH)
+        };
+        match &self.pool {
+            Some(pool) => pool.install(|| seed(&mut new_sketch)),
+            None => seed(&mut new_sketch),
+        }
+        new_sketch.repetitive_kmers = seeding::get_repetitive_kmers(&new_sketch);
+        if j == 0 {
+            return Err(SkaniError::NoInputs(format!(
+                "Genome {} consists of only contigs < {} bp.",
+                name, MIN_LENGTH_CONTIG
+            )));
+        }
+        Ok(new_sketch)
+    }
+
+    ///Errors unless `sketch` was sketched with the calculator's parameters:
+    ///the same sampling rates, seeds, seed table and masking.
+    fn check_compatible(&self, sketch: &Sketch) -> Result<(), SkaniError> {
+        let params = &self.sketch_params;
+        let seed_weight = if sketch.seed_weight == 0 { sketch.k } else { sketch.seed_weight };
+        let seed64 = sketch.kmer_seeds_k64.is_some();
+        let sync_s = if params.use_syncs { params.sync_s } else { 0 };
+        let closed_syncs = params.use_syncs && params.closed_syncs;
+        let mut mismatches = vec![];
+        if sketch.c != params.c || sketch.marker_c != params.marker_c {
+            mismatches.push(format!(
+                "c = {}, m = {} (calculator: c = {}, m = {})",
+                sketch.c, sketch.marker_c, params.c, params.marker_c
+            ));
+        }
+        if sketch.k != params.k || seed_weight != params.seed_weight() {
+            mismatches.push(format!(
+                "k = {}, seed weight = {} (calculator: k = {}, seed weight = {})",
+                sketch.k, seed_weight, params.k, params.seed_weight()
+            ));
+        }
+        if sketch.seed_pattern != params.seed_pattern {
+            mismatches.push(format!(
+                "seed pattern = {:?} (calculator: {:?})",
+                sketch.seed_pattern, params.seed_pattern
+            ));
+        }
+        if sketch.amino_acid != params.use_aa {
+            mismatches.push(format!("amino acid = {} (calculator: {})", sketch.amino_acid, params.use_aa));
+        }
+        if (seed64 && !params.use_seed64) || (sketch.kmer_seeds_k.is_some() && params.use_seed64) {
+            mismatches.push(format!("64-bit seeds = {} (calculator: {})", seed64, params.use_seed64));
+        }
+        if sketch.sync_s != sync_s || sketch.closed_syncs != closed_syncs {
+            mismatches.push(format!(
+                "syncmer s = {}, closed = {} (calculator: s = {}, closed = {})",
+                sketch.sync_s, sketch.closed_syncs, sync_s, closed_syncs
+            ));
+        }
+        if sketch.mask_lowercase != params.mask_lowercase || sketch.dust_level != params.dust_level {
+            mismatches.push(format!(
+                "lowercase masking = {}, DUST level = {:?} (calculator: {}, {:?})",
+                sketch.mask_lowercase, sketch.dust_level, params.mask_lowercase, params.dust_level
+            ));
+        }
+        if !mismatches.is_empty() {
+            return Err(SkaniError::SketchParamMismatch(format!(
+                "{}: {}",
+                sketch.file_name,
+                mismatches.join("; ")
+            )));
+        }
+        Ok(())
+    }
+
+    fn chain(&self, query: &Sketch, reference: &Sketch) -> Option<AniEstResult> {
+        let map_params = chain::map_params_from_options(
+            reference,
+            self.sketch_params.use_aa,
+            self.min_aligned_frac,
+            self.robust,
+            self.median,
+            &self.model,
+        );
+        let ani_res = chain::chain_seeds(reference, query, map_params);
+        if ani_res.ani > 0.1 {
+            Some(ani_res)
+        } else {
+            None
+        }
+    }
+
+    ///ANI between a query and a reference. Returns `None` if the pair does
+    ///not pass the marker screen or the aligned fraction is too low.
+    pub fn ani(&self, query: &Sketch, reference: &Sketch) -> Result<Option<AniEstResult>, SkaniError> {
+        self.check_compatible(query)?;
+        self.check_compatible(reference)?;
+        if !screen::check_markers_quickly(query, reference, self.screen_val, self.rescue_small) {
+            return Ok(None);
+        }
+        Ok(self.chain(query, reference))
+    }
+
+    ///ANI for every query against every reference that passes the screen.
+    ///Results are ordered by query, then by reference.
+    pub fn ani_many(
+        &self,
+        queries: &[Sketch],
+        references: &[Sketch],
+    ) -> Result<Vec<AniEstResult>, SkaniError> {
+        match &self.pool {
+            Some(pool) => pool.install(|| self.ani_many_inner(queries, references)),
+            None => self.ani_many_inner(queries, references),
+        }
+    }
+
+    fn ani_many_inner(
+        &self,
+        queries: &[Sketch],
+        references: &[Sketch],
+    ) -> Result<Vec<AniEstResult>, SkaniError> {
+        for sketch in queries.iter().chain(references.iter()) {
+            self.check_compatible(sketch)?;
+        }
+        let use_index = queries.len() > FULL_INDEX_THRESH;
+        let kmer_to_sketch = if use_index {
+            screen::kmer_to_sketch_from_refs(references)
+        } else {
+            KmerToSketch::default()
+        };
+        let anis = queries
+            .par_iter()
+            .flat_map_iter(|query| {
+                let mut refs_to_try;
+                if use_index && self.screen_val > 0. {
+                    refs_to_try = screen::screen_refs(
+                        self.screen_val,
+                        &kmer_to_sketch,
+                        query,
+                        &self.sketch_params,
+                        references,
+                        self.rescue_small,
+                    )
+                    .into_iter()
+                    .collect::<Vec<usize>>();
+                    refs_to_try.sort();
+                } else {
+                    refs_to_try = (0..references.len())
+                        .filter(|i| {
+                            screen::check_markers_quickly(
+                                query,
+                                &references[*i],
+                                self.screen_val,
+                                self.rescue_small,
+                            )
+                        })
+                        .collect();
+                }
+                refs_to_try
+                    .into_par_iter()
+                    .filter_map(|i| self.chain(query, &references[i]))
+                    .collect::<Vec<AniEstResult>>()
+            })
+            .collect();
+        Ok(anis)
+    }
+}
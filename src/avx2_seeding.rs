@@ -1,6 +1,6 @@
-use smallvec::SmallVec;
 use std::arch::x86_64::*;
 use crate::params::*;
+use crate::seeding::is_syncmer;
 use crate::types::*;
 
 #[inline]
@@ -38,35 +38,49 @@ pub unsafe fn avx2_fmh_seeds(
     new_sketch: &mut Sketch,
     seed: bool,
 ) {
-    if seed && new_sketch.kmer_seeds_k.is_none() {
-        new_sketch.kmer_seeds_k = Some(KmerSeeds::default());
+    if seed {
+        new_sketch.init_seeds(sketch_params);
     }
     let marker_k = K_MARKER_DNA;
     let kmer_seeds_k = &mut new_sketch.kmer_seeds_k;
+    let kmer_seeds_k64 = &mut new_sketch.kmer_seeds_k64;
     let marker_seeds = &mut new_sketch.marker_seeds;
     let k = sketch_params.k;
     let c = sketch_params.c;
     let marker_c = sketch_params.marker_c;
-    let len = (string.len() - marker_k + 1) / 4;
-    let string1 = &string[0..len + marker_k - 1];
-    let string2 = &string[len..2 * len + marker_k - 1];
-    let string3 = &string[2 * len..3 * len + marker_k - 1];
-    let string4 = &string[3 * len..4 * len + marker_k - 1];
     if k > marker_k {
         panic!("Value of k > {} for DNA; not allowed.", marker_k);
     }
     if string.len() < 2 * marker_k {
         return;
     }
+    let len = (string.len() - marker_k + 1) / 4;
+    let string1 = &string[0..len + marker_k - 1];
+    let string2 = &string[len..2 * len + marker_k - 1];
+    let string3 = &string[2 * len..3 * len + marker_k - 1];
+    let string4 = &string[3 * len..4 * len + marker_k - 1];
 
     let mut rolling_kmer_f_marker = _mm256_set_epi64x(0, 0, 0, 0);
     let mut rolling_kmer_r_marker = _mm256_set_epi64x(0, 0, 0, 0);
     let rev_sub = _mm256_set_epi64x(3, 3, 3, 3);
+    //Seeds and markers resume once they no longer overlap a non-ACGT (or,
+    //with mask_lowercase, soft-masked) base.
+    let mut resume_inds = [0,0,0,0];
+    let mut marker_resume_inds = [0,0,0,0];
+    let mask_lowercase = sketch_params.mask_lowercase;
     for i in 0..marker_k - 1 {
         let ascii_rep_1 = string1[i] as usize;
         let ascii_rep_2 = string2[i] as usize;
         let ascii_rep_3 = string3[i] as usize;
         let ascii_rep_4 = string4[i] as usize;
+        //Bases before the first seed of a lane block the seeds after them, as
+        //they would if the lanes were one string.
+        for (j, ascii_rep) in [ascii_rep_1, ascii_rep_2, ascii_rep_3, ascii_rep_4].into_iter().enumerate(){
+            if BYTE_IS_AMBIGUOUS[ascii_rep] || (mask_lowercase && (ascii_rep as u8).is_ascii_lowercase()){
+                resume_inds[j] = i + k;
+                marker_resume_inds[j] = i + marker_k;
+            }
+        }
         let nuc_f1 = BYTE_TO_SEQ[ascii_rep_1] as i64;
         let nuc_f2 = BYTE_TO_SEQ[ascii_rep_2] as i64;
         let nuc_f3 = BYTE_TO_SEQ[ascii_rep_3] as i64;
@@ -82,7 +96,7 @@ pub unsafe fn avx2_fmh_seeds(
         rolling_kmer_r_marker = _mm256_or_si256(rolling_kmer_r_marker, shift_nuc_r);
     }
 
-    let seed_mask = (MarkerBits::MAX >> (std::mem::size_of::<MarkerBits>() * 8 - 2 * k)) as i64;
+    let seed_mask = sketch_params.seed_mask() as i64;
     let mm256_seed_mask = _mm256_set_epi64x(seed_mask, seed_mask, seed_mask, seed_mask);
     let marker_mask =
         (MarkerBits::MAX >> (std::mem::size_of::<MarkerBits>() * 8 - 2 * marker_k)) as i64;
@@ -92,7 +106,8 @@ pub unsafe fn avx2_fmh_seeds(
     //    dbg!((u64::MAX / (c as u64)) as i64);
     let threshold = i64::MIN + (u64::MAX / (c as u64)) as i64;
     let _threshold_marker = i64::MIN + (u64::MAX / marker_c as u64) as i64;
-    let threshold_unsigned = u64::MAX / c as u64;
+    let threshold_unsigned = sketch_params.seed_threshold();
+    let use_syncs = sketch_params.use_syncs;
     let threshold_marker_unsigned = u64::MAX / marker_c as u64;
     let _cmp_thresh = _mm256_set_epi64x(threshold, threshold, threshold, threshold);
 
@@ -106,7 +121,6 @@ pub unsafe fn avx2_fmh_seeds(
 
     //dbg!(KmerEnc::print_string(u64::from_le_bytes(_mm256_extract_epi64(rolling_kmer_f_marker,0).to_le_bytes()), 21));
 
-    let mut resume_inds = [0,0,0,0];
     for i in marker_k-1..(len + marker_k - 1) {
 
         let ascii_rep_1 = string1[i] as usize;
@@ -114,17 +128,11 @@ pub unsafe fn avx2_fmh_seeds(
         let ascii_rep_3 = string3[i] as usize;
         let ascii_rep_4 = string4[i] as usize;
 
-        if ascii_rep_1 == ASCII_N{
-            resume_inds[0] = i + marker_k;
-        }
-        if ascii_rep_2 == ASCII_N{
-            resume_inds[1] = i + marker_k;
-        }
-        if ascii_rep_3 == ASCII_N{
-            resume_inds[2] = i + marker_k;
-        }
-        if ascii_rep_4 == ASCII_N{
-            resume_inds[3] = i + marker_k;
+        for (j, ascii_rep) in [ascii_rep_1, ascii_rep_2, ascii_rep_3, ascii_rep_4].into_iter().enumerate(){
+            if BYTE_IS_AMBIGUOUS[ascii_rep] || (mask_lowercase && (ascii_rep as u8).is_ascii_lowercase()){
+                resume_inds[j] = i + k;
+                marker_resume_inds[j] = i + marker_k;
+            }
         }
 
 
@@ -180,17 +188,19 @@ pub unsafe fn avx2_fmh_seeds(
             //            if m1 !={
             if v1 < threshold_unsigned && resume_inds[0] <= i {
                 const IND: i32 = 0;
-                let kmer_seeds = &mut kmer_seeds_k.as_mut().unwrap();
-                let kmer_positions = kmer_seeds
-                    .entry(canonical_seeds[IND as usize] as SeedBits)
-                    .or_insert(SmallVec::<[SeedPosition; SMALL_VEC_SIZE]>::new());
-                //                    .or_insert(vec![]);
-                kmer_positions.push(SeedPosition {
-                    pos: i as GnPosition,
-                    canonical: canonical[IND as usize],
-                    contig_index,
-                    phase: 0,
-                });
+                if !use_syncs || is_syncmer(canonical_seeds[IND as usize] as u64, sketch_params) {
+                    push_seed(
+                        kmer_seeds_k,
+                        kmer_seeds_k64,
+                        canonical_seeds[IND as usize] as SeedBits64,
+                        SeedPosition {
+                            pos: i as GnPosition,
+                            canonical: canonical[IND as usize],
+                            contig_index,
+                            phase: 0,
+                        },
+                    );
+                }
                 let canonical_marker = _mm256_extract_epi64(compare_marker, IND) != 0;
                 let canonical_kmer_marker;
                 if canonical_marker {
@@ -199,24 +209,26 @@ pub unsafe fn avx2_fmh_seeds(
                     canonical_kmer_marker = _mm256_extract_epi64(rolling_kmer_r_marker, IND);
                 };
                 //                if _mm256_extract_epi64(hash_256, IND) < threshold_marker {
-                if v1 < threshold_marker_unsigned {
+                if v1 < threshold_marker_unsigned && marker_resume_inds[0] <= i {
                     marker_seeds.insert(canonical_kmer_marker as u64);
                 }
             }
             //            if m2 != 0 {
             if v2 < threshold_unsigned && resume_inds[1] <= i {
                 const IND: i32 = 1;
-                let kmer_seeds = &mut kmer_seeds_k.as_mut().unwrap();
-                let kmer_positions = kmer_seeds
-                    .entry(canonical_seeds[IND as usize] as SeedBits)
-                    .or_insert(SmallVec::<[SeedPosition; SMALL_VEC_SIZE]>::new());
-                //                    .or_insert(vec![]);
-                kmer_positions.push(SeedPosition {
-                    pos: i as GnPosition + (len as i32 * IND) as GnPosition,
-                    canonical: canonical[IND as usize],
-                    contig_index,
-                    phase: 0,
-                });
+                if !use_syncs || is_syncmer(canonical_seeds[IND as usize] as u64, sketch_params) {
+                    push_seed(
+                        kmer_seeds_k,
+                        kmer_seeds_k64,
+                        canonical_seeds[IND as usize] as SeedBits64,
+                        SeedPosition {
+                            pos: i as GnPosition + (len as i32 * IND) as GnPosition,
+                            canonical: canonical[IND as usize],
+                            contig_index,
+                            phase: 0,
+                        },
+                    );
+                }
                 let canonical_marker = _mm256_extract_epi64(compare_marker, IND) != 0;
                 let canonical_kmer_marker;
                 if canonical_marker {
@@ -225,24 +237,26 @@ pub unsafe fn avx2_fmh_seeds(
                     canonical_kmer_marker = _mm256_extract_epi64(rolling_kmer_r_marker, IND);
                 };
                 //                if _mm256_extract_epi64(hash_256, IND) < threshold_marker {
-                if v2 < threshold_marker_unsigned {
+                if v2 < threshold_marker_unsigned && marker_resume_inds[1] <= i {
                     marker_seeds.insert(canonical_kmer_marker as u64);
                 }
             }
             //            if m3 != 0 {
             if v3 < threshold_unsigned && resume_inds[2] <= i{
                 const IND: i32 = 2;
-                let kmer_seeds = &mut kmer_seeds_k.as_mut().unwrap();
-                let kmer_positions = kmer_seeds
-                    .entry(canonical_seeds[IND as usize] as SeedBits)
-                    .or_insert(SmallVec::<[SeedPosition; SMALL_VEC_SIZE]>::new());
-                //                    .or_insert(vec![]);
-                kmer_positions.push(SeedPosition {
-                    canonical: canonical[IND as usize],
-                    pos: i as GnPosition + (len as i32 * IND) as GnPosition,
-                    contig_index,
-                    phase: 0,
-                });
+                if !use_syncs || is_syncmer(canonical_seeds[IND as usize] as u64, sketch_params) {
+                    push_seed(
+                        kmer_seeds_k,
+                        kmer_seeds_k64,
+                        canonical_seeds[IND as usize] as SeedBits64,
+                        SeedPosition {
+                            canonical: canonical[IND as usize],
+                            pos: i as GnPosition + (len as i32 * IND) as GnPosition,
+                            contig_index,
+                            phase: 0,
+                        },
+                    );
+                }
                 let canonical_marker = _mm256_extract_epi64(compare_marker, IND) != 0;
                 let canonical_kmer_marker;
                 if canonical_marker {
@@ -251,24 +265,26 @@ pub unsafe fn avx2_fmh_seeds(
                     canonical_kmer_marker = _mm256_extract_epi64(rolling_kmer_r_marker, IND);
                 };
                 //                if _mm256_extract_epi64(hash_256, IND) < threshold_marker {
-                if v3 < threshold_marker_unsigned {
+                if v3 < threshold_marker_unsigned && marker_resume_inds[2] <= i {
                     marker_seeds.insert(canonical_kmer_marker as u64);
                 }
             }
             //            if m4 != 0 {
             if v4 < threshold_unsigned && resume_inds[3] <= i{
                 const IND: i32 = 3;
-                let kmer_seeds = &mut kmer_seeds_k.as_mut().unwrap();
-                let kmer_positions = kmer_seeds
-                    .entry(canonical_seeds[IND as usize] as SeedBits)
-                    .or_insert(SmallVec::<[SeedPosition; SMALL_VEC_SIZE]>::new());
-                //                    .or_insert(vec![]);
-                kmer_positions.push(SeedPosition {
-                    pos: i as GnPosition + (len as i32 * IND) as GnPosition,
-                    canonical: canonical[IND as usize],
-                    contig_index,
-                    phase: 0,
-                });
+                if !use_syncs || is_syncmer(canonical_seeds[IND as usize] as u64, sketch_params) {
+                    push_seed(
+                        kmer_seeds_k,
+                        kmer_seeds_k64,
+                        canonical_seeds[IND as usize] as SeedBits64,
+                        SeedPosition {
+                            pos: i as GnPosition + (len as i32 * IND) as GnPosition,
+                            canonical: canonical[IND as usize],
+                            contig_index,
+                            phase: 0,
+                        },
+                    );
+                }
                 let canonical_marker = _mm256_extract_epi64(compare_marker, IND) != 0;
                 let canonical_kmer_marker;
                 if canonical_marker {
@@ -277,10 +293,65 @@ pub unsafe fn avx2_fmh_seeds(
                     canonical_kmer_marker = _mm256_extract_epi64(rolling_kmer_r_marker, IND);
                 };
                 //                if _mm256_extract_epi64(hash_256, IND) < threshold_marker {
-                if v4 < threshold_marker_unsigned {
+                if v4 < threshold_marker_unsigned && marker_resume_inds[3] <= i {
                     marker_seeds.insert(canonical_kmer_marker as u64);
                 }
             }
         }
     }
+
+    //The last few positions do not fill a lane; they are seeded from the
+    //k-mers of the last lane so that every position of the string is seeded.
+    let mut rolling_kmer_f_marker = _mm256_extract_epi64(rolling_kmer_f_marker, 3) as u64;
+    let mut rolling_kmer_r_marker = _mm256_extract_epi64(rolling_kmer_r_marker, 3) as u64;
+    let mut resume_ind = resume_inds[3] + 3 * len;
+    let mut marker_resume_ind = marker_resume_inds[3] + 3 * len;
+    for (i, &ascii_rep) in string.iter().enumerate().skip(4 * len + marker_k - 1) {
+        let ascii_rep = ascii_rep as usize;
+        if BYTE_IS_AMBIGUOUS[ascii_rep] || (mask_lowercase && (ascii_rep as u8).is_ascii_lowercase()){
+            resume_ind = i + k;
+            marker_resume_ind = i + marker_k;
+        }
+        let nuc_f = BYTE_TO_SEQ[ascii_rep];
+        let nuc_r = 3 - nuc_f;
+        rolling_kmer_f_marker <<= 2;
+        rolling_kmer_f_marker |= nuc_f;
+        rolling_kmer_f_marker &= marker_mask as u64;
+        rolling_kmer_r_marker >>= 2;
+        rolling_kmer_r_marker &= rev_marker_mask as u64;
+        rolling_kmer_r_marker |= nuc_r << (2 * (marker_k - 1));
+
+        let rolling_kmer_f_seed = rolling_kmer_f_marker & seed_mask as u64;
+        let rolling_kmer_r_seed = rolling_kmer_r_marker & seed_mask as u64;
+        let canonical = rolling_kmer_f_seed < rolling_kmer_r_seed;
+        let canonical_seed = if canonical {
+            rolling_kmer_f_seed
+        } else {
+            rolling_kmer_r_seed
+        };
+        let hash = mm_hash64(canonical_seed);
+        if hash < threshold_unsigned && resume_ind <= i {
+            if !use_syncs || is_syncmer(canonical_seed, sketch_params) {
+                push_seed(
+                    kmer_seeds_k,
+                    kmer_seeds_k64,
+                    canonical_seed,
+                    SeedPosition {
+                        pos: i as GnPosition,
+                        canonical,
+                        contig_index,
+                        phase: 0,
+                    },
+                );
+            }
+            if hash < threshold_marker_unsigned && marker_resume_ind <= i {
+                let canonical_kmer_marker = if rolling_kmer_f_marker < rolling_kmer_r_marker {
+                    rolling_kmer_f_marker
+                } else {
+                    rolling_kmer_r_marker
+                };
+                marker_seeds.insert(canonical_kmer_marker);
+            }
+        }
+    }
 }
@@ -0,0 +1,145 @@
+use crate::error::SkaniError;
+use crate::params::*;
+use crate::types::*;
+use fxhash::FxHashMap;
+use smallvec::SmallVec;
+use std::collections::BTreeMap;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+///Approximate memory used by a loaded sketch in bytes, counting its hash tables and contig names.
+pub fn sketch_mem_bytes(sketch: &Sketch) -> usize {
+    let seeds = sketch.kmer_seeds_k.as_ref().map(|x| x.capacity()).unwrap_or(0);
+    let seeds64 = sketch.kmer_seeds_k64.as_ref().map(|x| x.capacity()).unwrap_or(0);
+    let position_bytes = size_of::<SmallVec<[SeedPosition; SMALL_VEC_SIZE]>>() + 1;
+    let seed_bytes = seeds * (size_of::<SeedBits>() + position_bytes)
+        + seeds64 * (size_of::<SeedBits64>() + position_bytes);
+    let marker_bytes = sketch.marker_seeds.capacity() * (size_of::<MarkerBits>() + 1);
+    let contig_bytes = sketch
+        .contigs
+        .iter()
+        .map(|x| x.len() + size_of::<String>())
+        .sum::<usize>()
+        + sketch.contig_lengths.len() * size_of::<GnPosition>();
+    size_of::<Sketch>() + sketch.file_name.len() + seed_bytes + marker_bytes + contig_bytes
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+struct CacheEntry {
+    sketch: Arc<Sketch>,
+    bytes: usize,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    entries: FxHashMap<String, CacheEntry>,
+    //Names by time of last use; the first one is evicted next.
+    lru: BTreeMap<u64, String>,
+    clock: u64,
+    bytes: usize,
+}
+
+///Reference sketches shared by all threads of a search. Holds at most
+///`max_bytes` (by `sketch_mem_bytes`) and evicts the least recently used
+///sketch first. A cache with `max_bytes` = 0 keeps nothing and loads every time.
+pub struct RefCache {
+    max_bytes: usize,
+    inner: Mutex<CacheInner>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    evictions: AtomicUsize,
+}
+
+impl RefCache {
+    pub fn new(max_bytes: usize) -> RefCache {
+        RefCache {
+            max_bytes,
+            inner: Mutex::new(CacheInner::default()),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+            evictions: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    ///Returns the sketch named `name`, calling `load` if it is not cached. The
+    ///lock is not held while loading, so two threads missing the same sketch
+    ///at the same time may both load it.
+    pub fn get_or_load<F>(&self, name: &str, load: F) -> Result<Arc<Sketch>, SkaniError>
+    where
+        F: FnOnce() -> Result<Sketch, SkaniError>,
+    {
+        if let Some(sketch) = self.get(name) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(sketch);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let sketch = Arc::new(load()?);
+        Ok(self.insert(name, sketch))
+    }
+
+    fn get(&self, name: &str) -> Option<Arc<Sketch>> {
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+        let entry = inner.entries.get_mut(name)?;
+        inner.clock += 1;
+        inner.lru.remove(&entry.last_used);
+        entry.last_used = inner.clock;
+        inner.lru.insert(entry.last_used, name.to_string());
+        Some(entry.sketch.clone())
+    }
+
+    fn insert(&self, name: &str, sketch: Arc<Sketch>) -> Arc<Sketch> {
+        let bytes = sketch_mem_bytes(&sketch);
+        if bytes > self.max_bytes {
+            return sketch;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(entry) = inner.entries.get(name) {
+            return entry.sketch.clone();
+        }
+        while inner.bytes + bytes > self.max_bytes {
+            let (_, oldest) = inner.lru.pop_first().unwrap();
+            let evicted = inner.entries.remove(&oldest).unwrap();
+            inner.bytes -= evicted.bytes;
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+        inner.clock += 1;
+        let last_used = inner.clock;
+        inner.lru.insert(last_used, name.to_string());
+        inner.entries.insert(
+            name.to_string(),
+            CacheEntry {
+                sketch: sketch.clone(),
+                bytes,
+                last_used,
+            },
+        );
+        inner.bytes += bytes;
+        sketch
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entries: inner.entries.len(),
+            bytes: inner.bytes,
+        }
+    }
+}
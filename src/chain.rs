@@ -7,6 +7,9 @@ use crate::regression;
 use fxhash::FxHashMap;
 use log::*;
 use partitions::*;
+use smallvec::SmallVec;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
 use std::mem;
 extern crate interval;
 use gcollections::ops::set::*;
@@ -90,6 +93,25 @@ pub fn map_params_from_sketch <'a>(
     amino_acid: bool,
     command_params: &CommandParams,
     model_opt: &'a Option<GBDT>
+) -> MapParams<'a> {
+    map_params_from_options(
+        ref_sketch,
+        amino_acid,
+        command_params.min_aligned_frac,
+        command_params.robust,
+        command_params.median,
+        model_opt,
+    )
+}
+
+///Same as `map_params_from_sketch`, but without needing a full `CommandParams`.
+pub fn map_params_from_options <'a>(
+    ref_sketch: &Sketch,
+    amino_acid: bool,
+    min_aligned_frac: f64,
+    robust: bool,
+    median: bool,
+    model_opt: &'a Option<GBDT>
 ) -> MapParams<'a> {
     let max_gap_length = if amino_acid{D_MAX_GAP_LENGTH_AAI} else {D_MAX_GAP_LENGTH};
     let anchor_score = if amino_acid{D_ANCHOR_SCORE_AAI} else {D_ANCHOR_SCORE_ANI};
@@ -97,7 +119,7 @@ pub fn map_params_from_sketch <'a>(
     let min_length_cover = if amino_acid{MIN_LENGTH_COVER_AAI} else {MIN_LENGTH_COVER};
     let fragment_length = fragment_length_formula(ref_sketch.total_sequence_length, amino_acid);
     let length_cutoff = fragment_length;
-    let mut frac_cover_cutoff = command_params.min_aligned_frac;
+    let mut frac_cover_cutoff = min_aligned_frac;
     if frac_cover_cutoff < 0.{
         if amino_acid {
             frac_cover_cutoff = D_FRAC_COVER_CUTOFF_AA.parse::<f64>().unwrap()/100.;
@@ -111,6 +133,7 @@ pub fn map_params_from_sketch <'a>(
     let min_score = min_anchors as f64 * anchor_score * 0.75;
 //    let min_score = 0.;
     let k = ref_sketch.k;
+    let seed_weight = if ref_sketch.seed_weight == 0 { k } else { ref_sketch.seed_weight };
     let model;
     if let Some(m) = model_opt{
         model = Some(m);
@@ -130,11 +153,12 @@ pub fn map_params_from_sketch <'a>(
         k,
         amino_acid,
         min_score,
-        robust: command_params.robust,
-        median: command_params.median,
+        robust,
+        median,
         bp_chain_band,
         min_length_cover,
-        model
+        model,
+        seed_weight,
     }
 }
 
@@ -175,7 +199,9 @@ fn calculate_ani(
     map_params: &MapParams,
     switched: bool,
 ) -> AniEstResult {
-    let k = map_params.k;
+    //Identity per base is the seed identity to the power of one over the
+    //number of bases a seed actually compares.
+    let k = map_params.seed_weight;
     let mut ani_ests = vec![];
     let c = ref_sketch.c as GnPosition;
     let sensitive_af;
@@ -239,11 +265,11 @@ fn calculate_ani(
             all_intervals = all_intervals.union(&vec![(start, stop)].to_interval_set());
             //interval_vec.insert(int_insert, i);
             if sensitive_af{
-                total_query_bases +=  int.query_range_len() - int.overlap + 2 * c + k as GnPosition;
-                total_ref_range +=  int.query_range_len() - int.overlap + 2 * c + k as GnPosition;
+                total_query_bases +=  int.query_range_len() - int.overlap + 2 * c + map_params.k as GnPosition;
+                total_ref_range +=  int.query_range_len() - int.overlap + 2 * c + map_params.k as GnPosition;
             }
 
-            avg_chain_int_len += int.query_range_len() - int.overlap + 2 * c + k as GnPosition;
+            avg_chain_int_len += int.query_range_len() - int.overlap + 2 * c + map_params.k as GnPosition;
             num_chains += 1;
         }
 
@@ -473,11 +499,11 @@ fn calculate_ani(
     let std = ci_std.2;
     let covered_query = f64::min(
         1.,
-        total_query_bases as f64 / query_sketch.total_sequence_length as f64,
+        total_query_bases as f64 / query_sketch.unmasked_length().max(1) as f64,
     );
     let covered_ref = f64::min(
         1.,
-        total_ref_range as f64 / ref_sketch.total_sequence_length as f64,
+        total_ref_range as f64 / ref_sketch.unmasked_length().max(1) as f64,
     );
     
     let q_string = &query_sketch.file_name;
@@ -540,6 +566,9 @@ fn calculate_ani(
         std: std as f32,
         avg_chain_int_len,
         total_bases_covered: total_query_bases,
+        ambiguous_bases_q: query_sketch.ambiguous_bases as u32,
+        ambiguous_bases_r: ref_sketch.ambiguous_bases as u32,
+        effective_coverage: 0.,
     }
 }
 
@@ -592,14 +621,79 @@ pub fn score_anchors(anchor_curr: &Anchor, anchor_past: &Anchor, map_params: &Ma
 
 
 
+///Anchors between the seeds shared by the query and reference seed tables.
+///Seeds occurring more than `repet_cutoff` times in either table are masked;
+///every query seed position that is not masked is also added to
+///`query_positions_all`. Returns the number of query seeds with a hit and
+///the number of masked seeds.
+fn seed_anchors<K: Eq + Hash, S: BuildHasher>(
+    kmer_seeds_ref: &HashMap<K, SmallVec<[SeedPosition; SMALL_VEC_SIZE]>, S>,
+    kmer_seeds_query: &HashMap<K, SmallVec<[SeedPosition; SMALL_VEC_SIZE]>, S>,
+    repet_cutoff: usize,
+    query_positions_all: &mut [Vec<GnPosition>],
+    anchors: &mut Vec<Anchor>,
+) -> (usize, usize) {
+    let mut query_kmers_with_hits = 0;
+    let mut masked_seeds = 0;
+    for (canon_kmer, query_pos) in kmer_seeds_query.iter() {
+        if query_pos.len() > repet_cutoff{
+            masked_seeds += 1;
+            continue;
+        }
+        let contains = kmer_seeds_ref.contains_key(canon_kmer);
+
+        if !contains {
+            for qpos in query_pos.iter() {
+                query_positions_all[qpos.contig_index as usize].push(qpos.pos);
+            }
+        } else {
+            let ref_pos = &kmer_seeds_ref[canon_kmer];
+
+            if ref_pos.len() > repet_cutoff{
+                masked_seeds += 1;
+                continue;
+            }
+
+            for qpos in query_pos.iter() {
+                query_positions_all[qpos.contig_index as usize].push(qpos.pos);
+            }
+
+            query_kmers_with_hits += 1;
+            for qpos in query_pos {
+                for rpos in ref_pos {
+                    anchors.push(Anchor::new(
+                        &(rpos.pos, rpos.contig_index),
+                        &(qpos.pos, qpos.contig_index),
+                        rpos.phase,
+                        qpos.phase,
+                        rpos.canonical != qpos.canonical,
+                    ));
+                }
+            }
+        }
+    }
+    (query_kmers_with_hits, masked_seeds)
+}
+
+///Repetitive seed cutoff for a pair of sketches: the smaller of their
+///per-genome cutoffs, or the fixed BP_CHAIN_BAND/c band for sketches
+///without one.
+pub fn pair_repetitive_cutoff(ref_sketch: &Sketch, query_sketch: &Sketch, map_params: &MapParams) -> usize {
+    [ref_sketch.repetitive_kmers, query_sketch.repetitive_kmers]
+        .into_iter()
+        .filter(|x| *x > 0)
+        .min()
+        .unwrap_or(map_params.index_chain_band)
+}
+
 fn get_anchors(
     ref_sketch: &Sketch,
     query_sketch: &Sketch,
     map_params: &MapParams,
 ) -> (AnchorChunks, bool) {
-    let k = map_params.k;
-    let kmer_seeds_ref;
-    let kmer_seeds_query;
+    let k = map_params.seed_weight;
+    let seeds_ref_sketch;
+    let seeds_query_sketch;
     let mut query_positions_all;
     let switched;
     if ref_sketch.contig_lengths.is_empty() || query_sketch.contig_lengths.is_empty(){
@@ -636,55 +730,50 @@ fn get_anchors(
     if switch_qr(mean_ctg_len_r,mean_ctg_len_q, query_length_markers_proxy, ref_length_markers_proxy, &query_sketch.file_name, &ref_sketch.file_name){
         switched = true;
 
-        kmer_seeds_ref = query_sketch.kmer_seeds_k.as_ref().unwrap();
-        kmer_seeds_query = ref_sketch.kmer_seeds_k.as_ref().unwrap();
+        seeds_ref_sketch = query_sketch;
+        seeds_query_sketch = ref_sketch;
         query_positions_all = vec![vec![]; ref_sketch.contigs.len()];
     } else {
         switched = false;
 
-        kmer_seeds_ref = ref_sketch.kmer_seeds_k.as_ref().unwrap();
-        kmer_seeds_query = query_sketch.kmer_seeds_k.as_ref().unwrap();
+        seeds_ref_sketch = ref_sketch;
+        seeds_query_sketch = query_sketch;
         query_positions_all = vec![vec![]; query_sketch.contigs.len()];
     }
     //    let kmer_seeds_ref = &ref_sketch.kmer_seeds_k[k];
     //    let kmer_seeds_query = &query_sketch.kmer_seeds_k[k];
     let mut anchors = vec![];
-    let mut query_kmers_with_hits = 0;
-    for (canon_kmer, query_pos) in kmer_seeds_query.iter() {
-        if query_pos.len() > map_params.index_chain_band{
-            continue;
-        }
-        let contains = kmer_seeds_ref.contains_key(canon_kmer);
-
-        if !contains {
-            for qpos in query_pos.iter() {
-                query_positions_all[qpos.contig_index as usize].push(qpos.pos);
-            }
-        } else {
-            let ref_pos = &kmer_seeds_ref[canon_kmer];
-
-            if ref_pos.len() > map_params.index_chain_band{
-                continue;
-            }
-
-            for qpos in query_pos.iter() {
-                query_positions_all[qpos.contig_index as usize].push(qpos.pos);
-            }
-
-            query_kmers_with_hits += 1;
-            for qpos in query_pos {
-                for rpos in ref_pos {
-                    anchors.push(Anchor::new(
-                        &(rpos.pos, rpos.contig_index),
-                        &(qpos.pos, qpos.contig_index),
-                        rpos.phase,
-                        qpos.phase,
-                        rpos.canonical != qpos.canonical,
-                    ));
-                }
-            }
+    let repet_cutoff = pair_repetitive_cutoff(ref_sketch, query_sketch, map_params);
+    let (query_kmers_with_hits, masked_seeds) = match (
+        &seeds_ref_sketch.kmer_seeds_k64,
+        &seeds_query_sketch.kmer_seeds_k64,
+        &seeds_ref_sketch.kmer_seeds_k,
+        &seeds_query_sketch.kmer_seeds_k,
+    ) {
+        (Some(kmer_seeds_ref), Some(kmer_seeds_query), _, _) => seed_anchors(
+            kmer_seeds_ref,
+            kmer_seeds_query,
+            repet_cutoff,
+            &mut query_positions_all,
+            &mut anchors,
+        ),
+        (None, None, Some(kmer_seeds_ref), Some(kmer_seeds_query)) => seed_anchors(
+            kmer_seeds_ref,
+            kmer_seeds_query,
+            repet_cutoff,
+            &mut query_positions_all,
+            &mut anchors,
+        ),
+        _ => {
+            warn!(
+                "{} and {} have different seed tables (k or 64-bit seeds differ) or no seeds; no anchors found.",
+                &ref_sketch.file_name, &query_sketch.file_name
+            );
+            return (AnchorChunks::default(), true);
         }
-    }
+    };
+    let num_seeds_ref = seeds_ref_sketch.num_seeds().unwrap_or(0);
+    let num_seeds_query = seeds_query_sketch.num_seeds().unwrap_or(0);
     if anchors.is_empty() {
         debug!(
             "no anchors found for {}, {}",
@@ -697,13 +786,15 @@ fn get_anchors(
         query_position_vec.sort_unstable();
     }
     debug!(
-        "Ref seeds len {}, Query seeds len {}, Anchors {}, Seeds hit query {}, Est {}, Ref_file {}, Query_file {}",
-        kmer_seeds_ref.len(),
-        kmer_seeds_query.len(),
+        "Ref seeds len {}, Query seeds len {}, Anchors {}, Seeds hit query {}, Repetitive seed cutoff {}, Masked seeds {}, Est {}, Ref_file {}, Query_file {}",
+        num_seeds_ref,
+        num_seeds_query,
         anchors.len(),
         query_kmers_with_hits,
+        repet_cutoff,
+        masked_seeds,
         f64::powf(
-            (query_kmers_with_hits as f64) / (kmer_seeds_query.len() as f64),
+            (query_kmers_with_hits as f64) / (num_seeds_query as f64),
             1. / (k as f64)
         ),
         ref_sketch.file_name,
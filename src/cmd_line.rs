@@ -24,7 +24,12 @@ pub const H_FULL_MAT: &str = "Output full matrix instead of lower-triangular mat
 
 pub const KEEP_REFS: &str = "keep-refs";
 pub const CMD_KEEP_REFS: &str = "keep-refs";
-pub const H_KEEP_REFS: &str = "Keep reference sketches in memory if the sketch passes the marker filter. Takes more memory but is much faster when querying many similar sequences.";
+pub const H_KEEP_REFS: &str = "Keep reference sketches in memory if the sketch passes the marker filter. Takes more memory but is much faster when querying many similar sequences. Least recently used sketches are dropped beyond --cache-mem.";
+
+pub const CACHE_MEM: &str = "cache-mem";
+pub const CMD_CACHE_MEM: &str = "cache-mem";
+pub const H_CACHE_MEM: &str = "Memory in GB for reference sketches kept in memory between queries; implies --keep-refs.\t[default: 4 with --keep-refs, otherwise 0]";
+pub const DEFAULT_CACHE_MEM: &str = "4";
 
 pub const C_FACTOR: &str = "c";
 pub const CMD_C_FACTOR: &str = "c";
@@ -60,6 +65,8 @@ pub const H_MODE_MEDIUM: &str = "Medium skani mode; 2x slower and more memory. M
 pub const MARKER_C: &str = "marker_c";
 pub const CMD_MARKER_C: char = 'm';
 pub const H_MARKER_C: &str = "Marker k-mer compression factor. Markers are used for filtering. Consider decreasing to ~200-300 if working with small genomes (e.g. plasmids or viruses). \t[default: 1000]";
+pub const H_RESKETCH_C: &str = "New compression factor; must be at least the current c. Seeds are nested, so the result equals sketching the fastas with this c.\t[default: current c]";
+pub const H_RESKETCH_MARKER_C: &str = "New marker compression factor; must be at least the current marker c and c.\t[default: max(current marker c, c)]";
 
 pub const DETAIL_OUT: &str = "detailed";
 pub const CMD_DETAIL_OUT: &str = "detailed";
@@ -77,7 +84,75 @@ pub const FAST_SMALL: &str = "faster-small";
 pub const CMD_FAST_SMALL: &str = "faster-small";
 pub const H_FAST_SMALL: &str = "Filter genomes with < 20 marker k-mers more aggressively. Much faster for many small genomes but may miss some comparisons.";
 
+pub const JSON_OUT: &str = "json";
+pub const CMD_JSON_OUT: &str = "json";
+pub const H_JSON_OUT: &str = "Output JSON instead of human-readable text.";
+
 pub const DIAG: &str = "diagonal";
 pub const CMD_DIAG: &str = "diagonal";
 pub const H_DIAG: &str = "Output the diagonal of the ANI matrix (i.e. self-self comparisons) for both dense and sparse matrices.";
 
+
+pub const PACKED: &str = "packed";
+pub const CMD_PACKED: &str = "packed";
+pub const H_PACKED: &str = "Write all sketches into one data file (sketches.db) with an index (sketches.idx) instead of one .sketch file per genome. Faster to copy and to search on network file systems.";
+
+pub const APPEND: &str = "append";
+pub const CMD_APPEND: &str = "append";
+pub const H_APPEND: &str = "Add genomes to an existing sketch folder instead of creating a new one. Sketch parameters must match the folder's markers.bin; genomes already in the folder are skipped.";
+
+pub const MARKER_INDEX: &str = "marker-index";
+pub const CMD_MARKER_INDEX: &str = "marker-index";
+pub const H_MARKER_INDEX: &str = "Also write the marker k-mer index (markers.index) to the folder. `skani search` loads it instead of building it when the full index is used (> 100 queries or --qi).";
+
+pub const GROUPS: &str = "groups";
+pub const CMD_GROUPS: &str = "groups";
+pub const H_GROUPS: &str = "TSV file with lines genome_name<TAB>fasta. All fastas with the same genome name are sketched as one genome, reported as genome_name.";
+
+pub const REF_GROUPS: &str = "reference groups";
+pub const CMD_REF_GROUPS: &str = "rg";
+pub const H_REF_GROUPS: &str = "TSV file with lines genome_name<TAB>fasta for the REFERENCES. All fastas with the same genome name are sketched as one genome, reported as genome_name.";
+
+pub const QUERY_GROUPS: &str = "query groups";
+pub const CMD_QUERY_GROUPS: &str = "qg";
+pub const H_QUERY_GROUPS: &str = "TSV file with lines genome_name<TAB>fasta for the QUERIES. All fastas with the same genome name are sketched as one genome, reported as genome_name.";
+
+pub const READS_Q: &str = "reads";
+pub const CMD_READS_Q: &str = "reads";
+pub const H_READS_Q: &str = "Queries are sequencing reads (fastq/fasta), one sample per file or per genome name with --qg. Sampled k-mers are counted across reads and compared to references by containment without chaining, e.g. to check strain identity before assembly. Adds an Effective_coverage column; estimates below ~3x coverage are unreliable.";
+
+pub const MIN_KMER_COUNT: &str = "min-count";
+pub const CMD_MIN_KMER_COUNT: &str = "min-count";
+pub const H_MIN_KMER_COUNT: &str = "Discard sampled k-mers seen fewer times than this in the reads of a sample; removes most k-mers with sequencing errors.\t[default: 2]";
+
+pub const SPLIT_REGEX: &str = "split-regex";
+pub const CMD_SPLIT_REGEX: &str = "split-regex";
+pub const H_SPLIT_REGEX: &str = "Split multi-genome fastas into genomes: records whose ID gives the same first capture group (or match) of this regex form one genome, reported by that name. Records that do not match form one genome named after the file.";
+
+pub const SPLIT_SEP: &str = "split-sep";
+pub const CMD_SPLIT_SEP: &str = "split-sep";
+pub const H_SPLIT_SEP: &str = "Split multi-genome fastas into genomes: records whose ID is the same before the last occurrence of this token (e.g. '|' or '_') form one genome, reported by that name. Records without the token form one genome named after the file.";
+
+pub const SYNCMERS: &str = "syncmers";
+pub const CMD_SYNCMERS: &str = "syncmers";
+pub const H_SYNCMERS: &str = "Only use k-mers that are open or closed syncmers as seeds. Syncmers are chosen by their own sequence and are spread more evenly along genomes, which can improve AF and ANI for distant (80-90% ANI) genomes. The seed density stays about 1/c.";
+
+pub const SYNC_S: &str = "sync-s";
+pub const CMD_SYNC_S: &str = "sync-s";
+pub const H_SYNC_S: &str = "s-mer length for --syncmers; must be smaller than k.\t[default: k - 4]";
+
+pub const SEED_PATTERN: &str = "seed-pattern";
+pub const CMD_SEED_PATTERN: &str = "seed-pattern";
+pub const H_SEED_PATTERN: &str = "Use spaced seeds with this pattern of 0s and 1s, e.g. 1101101101101101. Only bases at 1s must match, so more seeds survive between divergent (< 85% ANI) genomes. k becomes the length of the pattern.";
+
+pub const MASK_LOWERCASE: &str = "mask-lowercase";
+pub const CMD_MASK_LOWERCASE: &str = "mask-lowercase";
+pub const H_MASK_LOWERCASE: &str = "Skip seeds and markers overlapping soft-masked (lowercase) bases, e.g. repeats in soft-masked eukaryotic assemblies. Masked bases are not counted in the aligned fraction.";
+
+pub const DUST: &str = "dust";
+pub const CMD_DUST: &str = "dust";
+pub const H_DUST: &str = "Skip seeds and markers in low-complexity windows (homopolymers, short tandem repeats) found by a DUST score. Useful for small viral and plasmid genomes with --small-genomes, where a few such windows can dominate the seeds.";
+
+pub const DUST_LEVEL: &str = "dust-level";
+pub const CMD_DUST_LEVEL: &str = "dust-level";
+pub const H_DUST_LEVEL: &str = "DUST score above which a 64 bp window is masked by --dust. Homopolymers score ~31, dinucleotide repeats ~15, random sequence < 1.\t[default: 10]";
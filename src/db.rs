@@ -0,0 +1,446 @@
+use crate::error::SkaniError;
+use crate::format;
+use crate::format::FileHeader;
+use crate::params::*;
+use crate::types::*;
+use fxhash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+
+//A sketch database is a folder written by `skani sketch`. It always has a
+//markers.bin file. The full sketches are either stored one per .sketch file
+//(the original layout) or packed into a single data file, sketches.db, with an
+//index sketches.idx mapping each sketch's name to its offset and length in
+//the data file. Every record in sketches.db is encoded exactly like a .sketch file.
+//
+//manifest.tsv maps each input genome file to the name of its sketch, so that
+//genomes with the same base name in different directories do not overwrite each
+//other. Folders written before the manifest existed name every sketch by
+//`sketch_file_name` and are read as if they had the corresponding manifest.
+
+pub const MARKER_FILE: &str = "markers.bin";
+pub const PACKED_DATA_FILE: &str = "sketches.db";
+pub const PACKED_INDEX_FILE: &str = "sketches.idx";
+pub const PACKED_DATA_MAGIC: &[u8; 8] = b"SKANIPKD";
+pub const MANIFEST_FILE: &str = "manifest.tsv";
+const MANIFEST_HEADER: &str = "Input_file\tSketch_file";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub name: String,
+    pub offset: u64,
+    pub length: u64,
+}
+
+///Name of the sketch for `file_name` inside a database, e.g. genome.fa.sketch.
+pub fn sketch_file_name(file_name: &str) -> String {
+    let base = Path::new(file_name)
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_else(|| file_name.to_string());
+    format!("{}.sketch", base)
+}
+
+pub fn is_packed(folder: &Path) -> bool {
+    folder.join(PACKED_INDEX_FILE).exists()
+}
+
+///Input files of a database and the names of their sketches.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Manifest {
+    entries: Vec<(String, String)>,
+    by_file: FxHashMap<String, usize>,
+    names: FxHashSet<String>,
+}
+
+impl Manifest {
+    ///The manifest a folder without manifest.tsv implicitly has.
+    pub fn from_markers(marker_sketches: &[Sketch]) -> Manifest {
+        let mut manifest = Manifest::default();
+        for marker in marker_sketches.iter() {
+            if !manifest.contains(&marker.file_name) {
+                manifest.insert(&marker.file_name, &sketch_file_name(&marker.file_name));
+            }
+        }
+        manifest
+    }
+
+    ///Reads manifest.tsv in `folder`, or derives the manifest from
+    ///`marker_sketches` if the folder has none.
+    pub fn open(folder: &Path, marker_sketches: &[Sketch]) -> Result<Manifest, SkaniError> {
+        let manifest_file = folder.join(MANIFEST_FILE);
+        if !manifest_file.exists() {
+            return Ok(Manifest::from_markers(marker_sketches));
+        }
+        let file_name = manifest_file.to_string_lossy().to_string();
+        let text = std::fs::read_to_string(&manifest_file)
+            .map_err(|e| SkaniError::FileOpen(file_name.clone(), e))?;
+        let mut manifest = Manifest::default();
+        for (i, line) in text.lines().enumerate() {
+            if (i == 0 && line == MANIFEST_HEADER) || line.is_empty() {
+                continue;
+            }
+            match line.rsplit_once('\t') {
+                Some((file, name)) => manifest.insert(file, name),
+                None => {
+                    return Err(SkaniError::CorruptSketch(format!(
+                        "{} (line {} is not <input file> <tab> <sketch name>)",
+                        file_name,
+                        i + 1
+                    )))
+                }
+            }
+        }
+        Ok(manifest)
+    }
+
+    ///Writes manifest.tsv into `folder`, replacing the old one only once it is complete.
+    pub fn write(&self, folder: &Path) -> Result<(), SkaniError> {
+        let manifest_file = folder.join(MANIFEST_FILE);
+        let tmp_file = folder.join(format!("{}.tmp", MANIFEST_FILE));
+        let mut text = format!("{}\n", MANIFEST_HEADER);
+        for (file, name) in self.entries.iter() {
+            text.push_str(&format!("{}\t{}\n", file, name));
+        }
+        std::fs::write(&tmp_file, text)
+            .map_err(|e| SkaniError::FileOpen(tmp_file.to_string_lossy().to_string(), e))?;
+        std::fs::rename(&tmp_file, &manifest_file)?;
+        Ok(())
+    }
+
+    fn insert(&mut self, file: &str, name: &str) {
+        self.by_file.insert(file.to_string(), self.entries.len());
+        self.names.insert(name.to_string());
+        self.entries.push((file.to_string(), name.to_string()));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.entries
+    }
+
+    pub fn contains(&self, file: &str) -> bool {
+        self.by_file.contains_key(file)
+    }
+
+    ///Name of the sketch of `file`. Files not in the manifest get their legacy name.
+    pub fn sketch_name(&self, file: &str) -> String {
+        match self.by_file.get(file) {
+            Some(i) => self.entries[*i].1.clone(),
+            None => sketch_file_name(file),
+        }
+    }
+
+    ///Returns the sketch name of `file`, first giving it a name that no other
+    ///file in the manifest has if it is new. The name is the base name of
+    ///`file` if it is free; otherwise its directories are prepended, joined by
+    ///'_', starting from the innermost one, and a number is added as a last resort.
+    pub fn assign(&mut self, file: &str) -> String {
+        if let Some(i) = self.by_file.get(file) {
+            return self.entries[*i].1.clone();
+        }
+        let components = Path::new(file)
+            .components()
+            .filter_map(|x| match x {
+                Component::Normal(x) => Some(x.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .collect::<Vec<String>>();
+        let mut name = None;
+        for start in (0..components.len()).rev() {
+            let candidate = format!("{}.sketch", components[start..].join("_"));
+            if !self.names.contains(&candidate) {
+                name = Some(candidate);
+                break;
+            }
+        }
+        let name = name.unwrap_or_else(|| {
+            let base = if components.is_empty() {
+                file.to_string()
+            } else {
+                components.join("_")
+            };
+            (2..)
+                .map(|i| format!("{}.{}.sketch", base, i))
+                .find(|x| !self.names.contains(x))
+                .unwrap()
+        });
+        self.insert(file, &name);
+        name
+    }
+
+    ///Keeps the entries whose input file satisfies `keep`.
+    pub fn retain<F: FnMut(&str) -> bool>(&mut self, mut keep: F) {
+        let entries = std::mem::take(&mut self.entries);
+        *self = Manifest::default();
+        for (file, name) in entries.iter() {
+            if keep(file) {
+                self.insert(file, name);
+            }
+        }
+    }
+}
+
+///Appends sketches to a packed database. `finish` must be called to write the index.
+pub struct PackedWriter {
+    folder: PathBuf,
+    sketch_params: SketchParams,
+    writer: BufWriter<File>,
+    offset: u64,
+    entries: Vec<IndexEntry>,
+}
+
+impl PackedWriter {
+    pub fn new(folder: &Path, sketch_params: &SketchParams) -> Result<PackedWriter, SkaniError> {
+        let data_file = folder.join(PACKED_DATA_FILE);
+        let file = File::create(&data_file)
+            .map_err(|e| SkaniError::FileOpen(data_file.to_string_lossy().to_string(), e))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(PACKED_DATA_MAGIC)?;
+        Ok(PackedWriter {
+            folder: folder.to_path_buf(),
+            sketch_params: sketch_params.clone(),
+            writer,
+            offset: PACKED_DATA_MAGIC.len() as u64,
+            entries: vec![],
+        })
+    }
+
+    ///Opens the packed database in `folder` for appending; new records go after the existing ones.
+    pub fn append(folder: &Path, sketch_params: &SketchParams) -> Result<PackedWriter, SkaniError> {
+        let index_file = folder.join(PACKED_INDEX_FILE).to_string_lossy().to_string();
+        let (_header, entries): (_, Vec<IndexEntry>) = format::read_index_file(&index_file)?;
+        let data_file = folder.join(PACKED_DATA_FILE);
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&data_file)
+            .map_err(|e| SkaniError::FileOpen(data_file.to_string_lossy().to_string(), e))?;
+        let offset = file.metadata()?.len();
+        Ok(PackedWriter {
+            folder: folder.to_path_buf(),
+            sketch_params: sketch_params.clone(),
+            writer: BufWriter::new(file),
+            offset,
+            entries,
+        })
+    }
+
+    pub fn add(&mut self, name: &str, sketch: &Sketch) -> Result<(), SkaniError> {
+        let bytes = format::encode_sketch(&self.sketch_params, sketch)?;
+        self.add_bytes(name, &bytes)
+    }
+
+    ///Adds an already encoded sketch, e.g. from `SketchDb::load_bytes`.
+    pub fn add_bytes(&mut self, name: &str, bytes: &[u8]) -> Result<(), SkaniError> {
+        self.writer.write_all(bytes)?;
+        self.entries.push(IndexEntry {
+            name: name.to_string(),
+            offset: self.offset,
+            length: bytes.len() as u64,
+        });
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), SkaniError> {
+        self.writer.flush()?;
+        //Written next to the old index and renamed so that an interrupted
+        //append leaves the old index intact.
+        let index_file = self.folder.join(PACKED_INDEX_FILE);
+        let tmp_file = self.folder.join(format!("{}.tmp", PACKED_INDEX_FILE));
+        format::write_index_file(
+            &tmp_file.to_string_lossy(),
+            &self.sketch_params,
+            self.entries.len(),
+            &self.entries,
+        )?;
+        std::fs::rename(&tmp_file, &index_file)?;
+        Ok(())
+    }
+}
+
+///Read access to the full sketches of a database in either layout.
+pub enum SketchDb {
+    Folder(PathBuf),
+    Packed {
+        data_file: String,
+        data: File,
+        entries: Vec<IndexEntry>,
+        index: FxHashMap<String, usize>,
+    },
+}
+
+impl SketchDb {
+    pub fn open(folder: &Path) -> Result<SketchDb, SkaniError> {
+        if !is_packed(folder) {
+            return Ok(SketchDb::Folder(folder.to_path_buf()));
+        }
+        let index_file = folder.join(PACKED_INDEX_FILE).to_string_lossy().to_string();
+        let (_header, entries): (_, Vec<IndexEntry>) = format::read_index_file(&index_file)?;
+        let data_file = folder.join(PACKED_DATA_FILE).to_string_lossy().to_string();
+        let data = File::open(&data_file).map_err(|e| SkaniError::FileOpen(data_file.clone(), e))?;
+        let index = entries
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (x.name.clone(), i))
+            .collect();
+        Ok(SketchDb::Packed {
+            data_file,
+            data,
+            entries,
+            index,
+        })
+    }
+
+    pub fn is_packed(&self) -> bool {
+        matches!(self, SketchDb::Packed { .. })
+    }
+
+    ///Names of all sketches in the database, as accepted by `load`.
+    pub fn names(&self) -> Result<Vec<String>, SkaniError> {
+        match self {
+            SketchDb::Folder(folder) => {
+                let dir = std::fs::read_dir(folder)
+                    .map_err(|e| SkaniError::FileOpen(folder.to_string_lossy().to_string(), e))?;
+                let mut names = vec![];
+                for entry in dir {
+                    let name = entry?.file_name().to_string_lossy().to_string();
+                    if name.ends_with(".sketch") {
+                        names.push(name);
+                    }
+                }
+                names.sort();
+                Ok(names)
+            }
+            SketchDb::Packed { entries, .. } => Ok(entries.iter().map(|x| x.name.clone()).collect()),
+        }
+    }
+
+    ///Loads the sketch stored under `name`, e.g. `manifest.sketch_name(genome_file)`.
+    pub fn load(&self, name: &str) -> Result<(FileHeader, Sketch), SkaniError> {
+        let bytes = self.load_bytes(name)?;
+        match self {
+            SketchDb::Folder(folder) => {
+                format::decode_sketch(&folder.join(name).to_string_lossy(), &bytes)
+            }
+            SketchDb::Packed { data_file, .. } => {
+                format::decode_sketch(&format!("{}:{}", data_file, name), &bytes)
+            }
+        }
+    }
+
+    ///The encoded sketch stored under `name`, without decoding it.
+    pub fn load_bytes(&self, name: &str) -> Result<Vec<u8>, SkaniError> {
+        match self {
+            SketchDb::Folder(folder) => {
+                let file = folder.join(name).to_string_lossy().to_string();
+                std::fs::read(&file).map_err(|e| SkaniError::FileOpen(file, e))
+            }
+            SketchDb::Packed {
+                data_file,
+                data,
+                entries,
+                index,
+            } => {
+                let entry = match index.get(name) {
+                    Some(i) => &entries[*i],
+                    None => {
+                        return Err(SkaniError::CorruptSketch(format!(
+                            "{} (no sketch named {} in the index)",
+                            data_file, name
+                        )))
+                    }
+                };
+                let mut bytes = vec![0; entry.length as usize];
+                read_exact_at(data, &mut bytes, entry.offset)
+                    .map_err(|e| SkaniError::FileOpen(data_file.clone(), e))?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+///Writes a new database in either layout, including its markers.bin.
+pub struct DbWriter {
+    folder: PathBuf,
+    sketch_params: SketchParams,
+    packed_writer: Option<PackedWriter>,
+    manifest: Manifest,
+}
+
+impl DbWriter {
+    ///Creates `folder`, which must not exist yet.
+    pub fn create(folder: &Path, sketch_params: &SketchParams, packed: bool) -> Result<DbWriter, SkaniError> {
+        let folder_name = folder.to_string_lossy().to_string();
+        if folder.exists() {
+            return Err(SkaniError::InvalidParameter(format!(
+                "output directory {} exists; output directory must not be an existing directory",
+                folder_name
+            )));
+        }
+        std::fs::create_dir_all(folder).map_err(|e| SkaniError::FileOpen(folder_name, e))?;
+        let packed_writer = if packed {
+            Some(PackedWriter::new(folder, sketch_params)?)
+        } else {
+            None
+        };
+        Ok(DbWriter {
+            folder: folder.to_path_buf(),
+            sketch_params: sketch_params.clone(),
+            packed_writer,
+            manifest: Manifest::default(),
+        })
+    }
+
+    ///Adds the encoded sketch of the genome `file`, naming it with `Manifest::assign`.
+    pub fn add_bytes(&mut self, file: &str, bytes: &[u8]) -> Result<(), SkaniError> {
+        let name = self.manifest.assign(file);
+        match &mut self.packed_writer {
+            Some(packed_writer) => packed_writer.add_bytes(&name, bytes),
+            None => {
+                let file = self.folder.join(&name);
+                std::fs::write(&file, bytes)
+                    .map_err(|e| SkaniError::FileOpen(file.to_string_lossy().to_string(), e))
+            }
+        }
+    }
+
+    ///Returns the header of the markers.bin written.
+    pub fn finish(self, marker_sketches: &[Sketch]) -> Result<FileHeader, SkaniError> {
+        if let Some(packed_writer) = self.packed_writer {
+            packed_writer.finish()?;
+        }
+        self.manifest.write(&self.folder)?;
+        let marker_file = self.folder.join(MARKER_FILE);
+        format::write_marker_file(&marker_file.to_string_lossy(), &self.sketch_params, marker_sketches)
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        let n = file.seek_read(buf, offset)?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        buf = &mut buf[n..];
+        offset += n as u64;
+    }
+    Ok(())
+}
@@ -1,65 +1,135 @@
 use crate::chain;
+use crate::error::SkaniError;
 use crate::regression;
 use crate::file_io;
 use crate::params::*;
+use crate::reads;
 use crate::screen;
+use crate::seeding;
+use crate::sink::ResultSink;
 use crate::types::*;
 use log::*;
 use rayon::prelude::*;
+use rayon::ThreadPool;
 use std::sync::Mutex;
 use std::time::Instant;
 
-pub fn dist(command_params: CommandParams, mut sketch_params: SketchParams) {
-    let ref_sketches;
+///Runs `dist_in_pool` in a pool with `command_params.threads` threads.
+pub fn dist<S: ResultSink + Send + ?Sized>(
+    command_params: CommandParams,
+    sketch_params: SketchParams,
+    sink: &mut S,
+) -> Result<(), SkaniError> {
+    let pool = command_params.thread_pool()?;
+    dist_in_pool(&pool, command_params, sketch_params, sink)
+}
+
+pub fn dist_in_pool<S: ResultSink + Send + ?Sized>(
+    pool: &ThreadPool,
+    command_params: CommandParams,
+    sketch_params: SketchParams,
+    sink: &mut S,
+) -> Result<(), SkaniError> {
+    pool.install(|| dist_inner(command_params, sketch_params, sink))
+}
+
+///Sketches the fasta references of `command_params` with `sketch_params`.
+fn sketch_fastx_refs(
+    command_params: &CommandParams,
+    sketch_params: &SketchParams,
+) -> Result<Vec<Sketch>, SkaniError> {
+    if command_params.individual_contig_r {
+        file_io::fastx_to_multiple_sketch_rewrite(&command_params.ref_files, sketch_params, true)
+    } else if let Some(split) = &command_params.genome_split {
+        file_io::fastx_to_split_sketches(&command_params.ref_files, sketch_params, split, true)
+    } else if !command_params.ref_groups.is_empty() {
+        file_io::groups_to_sketches(&command_params.ref_groups, sketch_params, true)
+    } else {
+        file_io::fastx_to_sketches(&command_params.ref_files, sketch_params, true)
+    }
+}
+
+fn dist_inner<S: ResultSink + Send + ?Sized>(
+    command_params: CommandParams,
+    mut sketch_params: SketchParams,
+    sink: &mut S,
+) -> Result<(), SkaniError> {
+    let mut ref_sketches;
     let query_params;
-    let query_sketches;
+    let mut query_sketches;
     let now = Instant::now();
     if command_params.refs_are_sketch {
         let new_sketch_params;
         info!("Sketches detected.");
         (new_sketch_params, ref_sketches) = file_io::sketches_from_sketch(
             &command_params.ref_files,
-        );
+        )?;
         if new_sketch_params != sketch_params {
             warn!("Parameters from .sketch files not equal to the input parameters. Using parameters from .sketch files.")
         }
         sketch_params = new_sketch_params;
-    } else if command_params.individual_contig_r {
-        ref_sketches = file_io::fastx_to_multiple_sketch_rewrite(
-            &command_params.ref_files,
-            &sketch_params,
-            true,
-        );
     } else {
-        ref_sketches =
-            file_io::fastx_to_sketches(&command_params.ref_files, &sketch_params, true);
+        ref_sketches = sketch_fastx_refs(&command_params, &sketch_params)?;
+    }
+    if command_params.reads_q {
+        return dist_reads(&command_params, &sketch_params, &ref_sketches, sink);
     }
     if command_params.queries_are_sketch {
         (query_params, query_sketches) =
-            file_io::sketches_from_sketch(&command_params.query_files);
-        if sketch_params != query_params && command_params.refs_are_sketch {
-            panic!(
-                "Query sketch parameters were not equal to reference sketch parameters. Exiting."
-            );
-        } else if sketch_params != query_params {
-            warn!("Parameters from .sketch files not equal to the input parameters. Using parameters from .sketch files.")
+            file_io::sketches_from_sketch(&command_params.query_files)?;
+        if sketch_params != query_params {
+            if let Some(common_params) = sketch_params.common_downsampled(&query_params) {
+                info!(
+                    "Query and reference sketches differ in c or marker_c; downsampling to c = {}, marker_c = {}.",
+                    common_params.c, common_params.marker_c
+                );
+                if sketch_params != common_params {
+                    ref_sketches
+                        .par_iter_mut()
+                        .for_each(|x| seeding::downsample_sketch(x, &common_params));
+                }
+                if query_params != common_params {
+                    query_sketches
+                        .par_iter_mut()
+                        .for_each(|x| seeding::downsample_sketch(x, &common_params));
+                }
+                sketch_params = common_params;
+            } else if command_params.refs_are_sketch {
+                return Err(SkaniError::SketchParamMismatch(
+                    "query sketch parameters were not equal to reference sketch parameters".to_string(),
+                ));
+            } else {
+                warn!("Parameters from .sketch files not equal to the input parameters. Using parameters from .sketch files.");
+                //Seeds of different k or seeding options can not be compared.
+                ref_sketches = sketch_fastx_refs(&command_params, &query_params)?;
+                sketch_params = query_params;
+            }
         }
     } else if command_params.individual_contig_q {
         query_sketches = file_io::fastx_to_multiple_sketch_rewrite(
             &command_params.query_files,
             &sketch_params,
             true,
-        );
+        )?;
+    } else if let Some(split) = &command_params.genome_split {
+        query_sketches =
+            file_io::fastx_to_split_sketches(&command_params.query_files, &sketch_params, split, true)?;
+    } else if !command_params.query_groups.is_empty() {
+        query_sketches =
+            file_io::groups_to_sketches(&command_params.query_groups, &sketch_params, true)?;
     } else {
         query_sketches =
-            file_io::fastx_to_sketches(&command_params.query_files, &sketch_params, true);
+            file_io::fastx_to_sketches(&command_params.query_files, &sketch_params, true)?;
     }
     if query_sketches.is_empty() || ref_sketches.is_empty() {
-        error!("No reference sketches/genomes or query sketches/genomes found.");
-        std::process::exit(1)
+        return Err(SkaniError::NoInputs(
+            "No reference sketches/genomes or query sketches/genomes found.".to_string(),
+        ));
     }
 
 
+    sink.begin(&sketch_params)?;
+
     let model_opt = regression::get_model(sketch_params.c, command_params.learned_ani);
     if model_opt.is_some() {
         info!("{}", LEARNED_INFO_HELP);
@@ -94,8 +164,8 @@ pub fn dist(command_params: CommandParams, mut sketch_params: SketchParams) {
         .collect::<Vec<usize>>();
     let anis: Mutex<Vec<AniEstResult>> = Mutex::new(vec![]);
     let counter: Mutex<usize> = Mutex::new(0);
-    let first_write: Mutex<bool> = Mutex::new(true);
-    js.into_par_iter().for_each(|j| {
+    let sink = Mutex::new(sink);
+    js.into_par_iter().try_for_each(|j| {
         let query_sketch = &query_sketches[j];
         if !command_params.screen {
             let is = (0..ref_sketches.len()).into_iter().collect::<Vec<usize>>();
@@ -157,32 +227,66 @@ pub fn dist(command_params: CommandParams, mut sketch_params: SketchParams) {
                 let mut locked = anis.lock().unwrap();
                 moved_anis = std::mem::take(&mut locked);
                 }
-                let mut fw = first_write.lock().unwrap();
-                file_io::write_query_ref_list(
-                    &moved_anis,
-                    &command_params.out_file_name,
-                    command_params.max_results,
-                    sketch_params.use_aa,
-                    command_params.est_ci,
-                    command_params.detailed_out,
-                    !*fw
-                );
-                if *fw == true{
-                    *fw = false;
-                }
+                sink.lock().unwrap().push_results(moved_anis)?;
             }
         }
-    });
+        Ok::<(), SkaniError>(())
+    })?;
     let anis = anis.into_inner().unwrap();
     
-    file_io::write_query_ref_list(
-        &anis,
-        &command_params.out_file_name,
-        command_params.max_results,
-        sketch_params.use_aa,
-        command_params.est_ci,
-        command_params.detailed_out,
-        !*first_write.lock().unwrap()
-    );
+    let sink = sink.into_inner().unwrap();
+    sink.push_results(anis)?;
+    sink.finish()?;
     info!("ANI calculation time: {}", now.elapsed().as_secs_f32());
+    Ok(())
+}
+
+///Compares each read set in the queries to `ref_sketches` by containment ANI
+///(see `reads::containment_ani`). Read sets are sketched one at a time.
+fn dist_reads<S: ResultSink + Send + ?Sized>(
+    command_params: &CommandParams,
+    sketch_params: &SketchParams,
+    ref_sketches: &[Sketch],
+    sink: &mut S,
+) -> Result<(), SkaniError> {
+    if ref_sketches.is_empty() {
+        return Err(SkaniError::NoInputs(
+            "No reference sketches/genomes found.".to_string(),
+        ));
+    }
+    let mut samples = command_params.query_groups.clone();
+    if samples.is_empty() {
+        samples = command_params
+            .query_files
+            .iter()
+            .map(|x| GenomeGroup {
+                name: x.clone(),
+                files: vec![x.clone()],
+            })
+            .collect();
+    }
+    let screen_val = if command_params.screen_val == 0. {
+        SEARCH_ANI_CUTOFF_DEFAULT
+    } else {
+        command_params.screen_val
+    };
+    sink.begin(sketch_params)?;
+    for sample in samples.iter() {
+        let now = Instant::now();
+        let read_sketch = reads::reads_to_sketch(sample, sketch_params, command_params.min_kmer_count)?;
+        let anis = ref_sketches
+            .par_iter()
+            .filter_map(|ref_sketch| reads::containment_ani(&read_sketch, ref_sketch))
+            .filter(|x| x.ani as f64 >= screen_val)
+            .collect::<Vec<AniEstResult>>();
+        info!(
+            "{} reads of {} compared in {} seconds.",
+            read_sketch.num_reads,
+            sample.name,
+            now.elapsed().as_secs_f32()
+        );
+        sink.push_results(anis)?;
+    }
+    sink.finish()?;
+    Ok(())
 }
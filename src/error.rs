@@ -0,0 +1,37 @@
+use std::io;
+use thiserror::Error;
+
+///Errors returned by skani's library entry points. The command line
+///binary is the only place where these are turned into exit codes.
+#[derive(Error, Debug)]
+pub enum SkaniError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("could not open {0}: {1}")]
+    FileOpen(String, #[source] io::Error),
+
+    #[error("{0} is not a valid fasta/fastq file")]
+    InvalidFasta(String),
+
+    #[error("{0} is not a valid .sketch file or is corrupted")]
+    CorruptSketch(String),
+
+    #[error("{0} uses sketch format version {1} but this skani only reads up to version {2}; please update skani")]
+    UnsupportedVersion(String, u32, u32),
+
+    #[error("sketch parameters are not compatible: {0}")]
+    SketchParamMismatch(String),
+
+    #[error("invalid parameter: {0}")]
+    InvalidParameter(String),
+
+    #[error("{0}")]
+    NoInputs(String),
+
+    #[error("serialization error: {0}")]
+    Serialization(#[from] bincode::Error),
+
+    #[error("could not build thread pool: {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+}
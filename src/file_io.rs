@@ -1,35 +1,44 @@
+use crate::error::SkaniError;
+use crate::format;
 use crate::params::*;
 use crate::seeding;
 use crate::types::*;
 use fxhash::FxHashMap;
 use log::*;
-use needletail::parse_fastx_file;
+use needletail::errors::ParseError;
+use needletail::{parse_fastx_file, parse_fastx_stdin, FastxReader};
 use rand::rng;
 use rand::seq::SliceRandom;
 use rayon::prelude::*;
+use smallvec::SmallVec;
+use std::collections::HashMap;
 use std::fs::File;
+use std::hash::{BuildHasher, Hash};
 use std::fs::OpenOptions;
-use std::io::{self, BufReader, BufWriter, Write};
+use std::io::{self, BufWriter, Write};
 use std::sync::Mutex;
 
-fn write_header(writer: &mut impl Write, id_str: &str, ci: bool, verbose: bool) {
+fn write_header(writer: &mut impl Write, id_str: &str, ci: bool, verbose: bool, coverage: bool) -> io::Result<()> {
     if !ci && !verbose {
-        writeln!(writer,"Ref_file\tQuery_file\t{}\tAlign_fraction_ref\tAlign_fraction_query\tRef_name\tQuery_name", id_str).unwrap();
+        write!(writer,"Ref_file\tQuery_file\t{}\tAlign_fraction_ref\tAlign_fraction_query\tRef_name\tQuery_name", id_str)?;
     } else if !verbose {
-        writeln!(writer,"Ref_file\tQuery_file\t{}\tAlign_fraction_ref\tAlign_fraction_query\tRef_name\tQuery_name\t{}_5_percentile\t{}_95_percentile", id_str, id_str, id_str).unwrap();
+        write!(writer,"Ref_file\tQuery_file\t{}\tAlign_fraction_ref\tAlign_fraction_query\tRef_name\tQuery_name\t{}_5_percentile\t{}_95_percentile", id_str, id_str, id_str)?;
     } else {
-        writeln!(writer,"Ref_file\tQuery_file\t{}\tAlign_fraction_ref\tAlign_fraction_query\tRef_name\tQuery_name\tNum_ref_contigs\tNum_query_contigs\t{}_5_percentile\t{}_95_percentile\tStandard_deviation\tRef_90_ctg_len\tRef_50_ctg_len\tRef_10_ctg_len\tQuery_90_ctg_len\tQuery_50_ctg_len\tQuery_10_ctg_len\tAvg_chain_len\tTotal_bases_covered", id_str, id_str, id_str).unwrap();
+        write!(writer,"Ref_file\tQuery_file\t{}\tAlign_fraction_ref\tAlign_fraction_query\tRef_name\tQuery_name\tNum_ref_contigs\tNum_query_contigs\t{}_5_percentile\t{}_95_percentile\tStandard_deviation\tRef_90_ctg_len\tRef_50_ctg_len\tRef_10_ctg_len\tQuery_90_ctg_len\tQuery_50_ctg_len\tQuery_10_ctg_len\tAvg_chain_len\tTotal_bases_covered\tRef_ambiguous_bases\tQuery_ambiguous_bases", id_str, id_str, id_str)?;
     }
+    if coverage {
+        write!(writer, "\tEffective_coverage")?;
+    }
+    writeln!(writer)
 }
 
-fn write_ani_res_perfect(writer: &mut impl Write, sketch: &Sketch, ci: bool, verbose: bool) {
+fn write_ani_res_perfect(writer: &mut impl Write, sketch: &Sketch, ci: bool, verbose: bool) -> io::Result<()> {
     if !ci && !verbose {
         writeln!(
             writer,
             "{}\t{}\t{:.2}\t{:.2}\t{:.2}\t{}\t{}",
             sketch.file_name, sketch.file_name, 100, 100, 100, sketch.contigs[0], sketch.contigs[0],
         )
-        .unwrap();
     } else if !verbose {
         writeln!(
             writer,
@@ -44,11 +53,10 @@ fn write_ani_res_perfect(writer: &mut impl Write, sketch: &Sketch, ci: bool, ver
             100,
             100,
         )
-        .unwrap();
     } else {
         writeln!(
             writer,
-            "{}\t{}\t{:.2}\t{:.2}\t{:.2}\t{}\t{}\t{}\t{}\t{:.2}\t{:.2}\t{:.2}\t{:0}\t{:0}\t{:0}\t{:0}\t{:0}\t{:0}\t{:0}\t{:0}",
+            "{}\t{}\t{:.2}\t{:.2}\t{:.2}\t{}\t{}\t{}\t{}\t{:.2}\t{:.2}\t{:.2}\t{:0}\t{:0}\t{:0}\t{:0}\t{:0}\t{:0}\t{:0}\t{:0}\t{}\t{}",
             sketch.file_name,
             sketch.file_name,
             100,
@@ -69,14 +77,15 @@ fn write_ani_res_perfect(writer: &mut impl Write, sketch: &Sketch, ci: bool, ver
             -1,
             0,
             sketch.total_sequence_length,
+            sketch.ambiguous_bases,
+            sketch.ambiguous_bases,
         )
-        .unwrap();
     }
 }
 
-fn write_ani_res(writer: &mut impl Write, ani_res: &AniEstResult, ci: bool, verbose: bool) {
+fn write_ani_res(writer: &mut impl Write, ani_res: &AniEstResult, ci: bool, verbose: bool, coverage: bool) -> io::Result<()> {
     if !ci && !verbose {
-        writeln!(
+        write!(
             writer,
             "{}\t{}\t{:.2}\t{:.2}\t{:.2}\t{}\t{}",
             ani_res.ref_file,
@@ -86,10 +95,9 @@ fn write_ani_res(writer: &mut impl Write, ani_res: &AniEstResult, ci: bool, verb
             ani_res.align_fraction_query * 100.,
             ani_res.ref_contig,
             ani_res.query_contig,
-        )
-        .unwrap();
+        )?;
     } else if !verbose {
-        writeln!(
+        write!(
             writer,
             "{}\t{}\t{:.2}\t{:.2}\t{:.2}\t{}\t{}\t{:.2}\t{:.2}",
             ani_res.ref_file,
@@ -101,12 +109,11 @@ fn write_ani_res(writer: &mut impl Write, ani_res: &AniEstResult, ci: bool, verb
             ani_res.query_contig,
             ani_res.ci_lower * 100.,
             ani_res.ci_upper * 100.,
-        )
-        .unwrap();
+        )?;
     } else {
-        writeln!(
+        write!(
             writer,
-            "{}\t{}\t{:.2}\t{:.2}\t{:.2}\t{}\t{}\t{}\t{}\t{:.2}\t{:.2}\t{:.2}\t{:0}\t{:0}\t{:0}\t{:0}\t{:0}\t{:0}\t{:0}\t{:0}",
+            "{}\t{}\t{:.2}\t{:.2}\t{:.2}\t{}\t{}\t{}\t{}\t{:.2}\t{:.2}\t{:.2}\t{:0}\t{:0}\t{:0}\t{:0}\t{:0}\t{:0}\t{:0}\t{:0}\t{}\t{}",
             ani_res.ref_file,
             ani_res.query_file,
             ani_res.ani * 100.,
@@ -127,29 +134,213 @@ fn write_ani_res(writer: &mut impl Write, ani_res: &AniEstResult, ci: bool, verb
             ani_res.quant_10_contig_len_q,
             ani_res.avg_chain_int_len,
             ani_res.total_bases_covered,
-        )
-        .unwrap();
+            ani_res.ambiguous_bases_r,
+            ani_res.ambiguous_bases_q,
+        )?;
+    }
+    if coverage {
+        write!(writer, "\t{:.2}", ani_res.effective_coverage)?;
     }
+    writeln!(writer)
 }
 
+///Input path that stands for standard input.
+pub const STDIN_PATH: &str = "-";
+
+///Opens a fasta/fastq file, or standard input if `file` is `STDIN_PATH`.
+///Compression is detected from the first bytes in both cases.
+pub fn open_fastx(file: &str) -> Result<Box<dyn FastxReader>, ParseError> {
+    if file == STDIN_PATH {
+        parse_fastx_stdin()
+    } else {
+        parse_fastx_file(file)
+    }
+}
+
+///Seeds one contig into `sketch`, dispatching to the amino acid, AVX2 or
+///scalar seeding routine. Low-complexity windows are masked first if
+///`sketch_params.dust_level` is set.
+pub fn seed_contig(
+    seq: &[u8],
+    sketch_params: &SketchParams,
+    contig_index: ContigIndex,
+    sketch: &mut Sketch,
+    seed: bool,
+) {
+    let seq = seeding::mask_low_complexity(seq, sketch_params);
+    seed_masked_contig(&seq, sketch_params, contig_index, sketch, seed);
+}
+
+fn seed_masked_contig(
+    seq: &[u8],
+    sketch_params: &SketchParams,
+    contig_index: ContigIndex,
+    sketch: &mut Sketch,
+    seed: bool,
+) {
+    if sketch_params.use_aa {
+        let orfs = seeding::get_orfs(seq, sketch_params);
+        seeding::fmh_seeds_aa_with_orf(seq, sketch_params, contig_index, sketch, orfs, seed);
+        return;
+    }
+    #[cfg(any(target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            use crate::avx2_seeding;
+            unsafe {
+                avx2_seeding::avx2_fmh_seeds(seq, sketch_params, contig_index, sketch, seed);
+            }
+            return;
+        }
+    }
+    seeding::fmh_seeds(seq, sketch_params, contig_index, sketch, seed);
+}
+
+///Seeds `contigs` into `sketch` on separate threads. DNA contigs longer than
+///`window_length` are split into windows that start `K_MARKER_DNA - 1` bases
+///early, so each window sees the same k-mers as the whole contig. The windows
+///are merged in order and give the same seed positions as `seed_contig` on
+///each contig.
+pub fn seed_contigs_parallel(
+    contigs: &[(ContigIndex, &[u8])],
+    sketch_params: &SketchParams,
+    sketch: &mut Sketch,
+    seed: bool,
+    window_length: usize,
+) {
+    if seed {
+        sketch.init_seeds(sketch_params);
+    }
+    //Low-complexity masking looks at whole contigs so that windows agree
+    //with `seed_contig`.
+    let masked_contigs = contigs
+        .par_iter()
+        .map(|(_, seq)| seeding::mask_low_complexity(seq, sketch_params))
+        .collect::<Vec<_>>();
+    let mut windows = vec![];
+    for (i, (_, seq)) in contigs.iter().enumerate() {
+        //ORFs can span windows, so amino acid contigs are not split.
+        let num_windows = if sketch_params.use_aa {
+            1
+        } else {
+            usize::max(1, seq.len() / window_length.max(2 * K_MARKER_DNA))
+        };
+        for w in 0..num_windows {
+            windows.push((i, seq.len() * w / num_windows, seq.len() * (w + 1) / num_windows));
+        }
+    }
+    let window_sketches = windows
+        .into_par_iter()
+        .map(|(i, start, end)| {
+            let contig_index = contigs[i].0;
+            let seq = &masked_contigs[i];
+            let offset = start.saturating_sub(K_MARKER_DNA - 1);
+            let mut window_sketch = Sketch::new(
+                sketch_params.marker_c,
+                sketch_params.c,
+                sketch_params.k,
+                String::new(),
+                sketch_params.use_aa,
+            );
+            seed_masked_contig(&seq[offset..end], sketch_params, contig_index, &mut window_sketch, seed);
+            (offset as GnPosition, window_sketch)
+        })
+        .collect::<Vec<(GnPosition, Sketch)>>();
+    for (offset, window_sketch) in window_sketches {
+        sketch.marker_seeds.extend(window_sketch.marker_seeds);
+        if let (Some(kmer_seeds), Some(window_seeds)) =
+            (sketch.kmer_seeds_k.as_mut(), window_sketch.kmer_seeds_k)
+        {
+            merge_seeds(kmer_seeds, window_seeds, offset);
+        }
+        if let (Some(kmer_seeds), Some(window_seeds)) =
+            (sketch.kmer_seeds_k64.as_mut(), window_sketch.kmer_seeds_k64)
+        {
+            merge_seeds(kmer_seeds, window_seeds, offset);
+        }
+    }
+}
+
+fn merge_seeds<K: Eq + Hash, S: BuildHasher>(
+    kmer_seeds: &mut HashMap<K, SmallVec<[SeedPosition; SMALL_VEC_SIZE]>, S>,
+    window_seeds: HashMap<K, SmallVec<[SeedPosition; SMALL_VEC_SIZE]>, S>,
+    offset: GnPosition,
+) {
+    for (kmer, positions) in window_seeds {
+        kmer_seeds.entry(kmer).or_default().extend(positions.into_iter().map(|mut x| {
+            x.pos += offset;
+            x
+        }));
+    }
+}
+
+fn seed_batch(batch: &[(ContigIndex, Vec<u8>)], sketch_params: &SketchParams, sketch: &mut Sketch, seed: bool) {
+    let contigs = batch
+        .iter()
+        .map(|(contig_index, seq)| (*contig_index, seq.as_slice()))
+        .collect::<Vec<(ContigIndex, &[u8])>>();
+    seed_contigs_parallel(&contigs, sketch_params, sketch, seed, SEED_WINDOW_LENGTH);
+}
+
+///Sketches every file as one genome. Files that are not valid fasta/fastq
+///are skipped with a warning, as in `groups_to_sketches`.
 pub fn fastx_to_sketches(
     ref_files: &Vec<String>,
     sketch_params: &SketchParams,
     seed: bool,
-) -> Vec<Sketch> {
+) -> Result<Vec<Sketch>, SkaniError> {
+    let groups = ref_files
+        .iter()
+        .map(|x| GenomeGroup {
+            name: x.clone(),
+            files: vec![x.clone()],
+        })
+        .collect::<Vec<GenomeGroup>>();
+    groups_to_sketches(&groups, sketch_params, seed)
+}
+
+///Sketches every group as one genome named after the group. Contig indices
+///continue across the files of a group. A group with a file that is not valid
+///fasta/fastq is skipped with a warning.
+pub fn groups_to_sketches(
+    groups: &[GenomeGroup],
+    sketch_params: &SketchParams,
+    seed: bool,
+) -> Result<Vec<Sketch>, SkaniError> {
     let ref_sketches: Mutex<Vec<_>> = Mutex::new(vec![]);
-    let mut index_vec = (0..ref_files.len()).collect::<Vec<usize>>();
+    let mut index_vec = (0..groups.len()).collect::<Vec<usize>>();
     index_vec.shuffle(&mut rng());
     index_vec.into_par_iter().for_each(|i| {
-        let ref_file = &ref_files[i];
-        let mut new_sketch = Sketch::new(
-            sketch_params.marker_c,
-            sketch_params.c,
-            sketch_params.k,
-            ref_file.to_string(),
-            sketch_params.use_aa,
-        );
-        let reader = parse_fastx_file(ref_file);
+        if let Some(new_sketch) = sketch_group(&groups[i], sketch_params, seed, i) {
+            let mut locked = ref_sketches.lock().unwrap();
+            locked.push(new_sketch);
+        }
+    });
+    let mut ref_sketches = ref_sketches.into_inner().unwrap();
+    ref_sketches.sort();
+    Ok(ref_sketches)
+}
+
+fn sketch_group(
+    group: &GenomeGroup,
+    sketch_params: &SketchParams,
+    seed: bool,
+    i: usize,
+) -> Option<Sketch> {
+    let mut new_sketch = Sketch::new(
+        sketch_params.marker_c,
+        sketch_params.c,
+        sketch_params.k,
+        group.name.to_string(),
+        sketch_params.use_aa,
+    );
+    trace!("Sketching {} {}", new_sketch.file_name, i);
+    let mut j = 0;
+    //Contigs are seeded in parallel in batches to bound the memory of the copies.
+    let mut batch = vec![];
+    let mut batch_length = 0;
+    for ref_file in group.files.iter() {
+        let reader = open_fastx(ref_file);
         if reader.is_err() {
             if ref_file.contains(".sketch"){
                 warn!("{} is not a valid fasta/fastq file but has the .sketch extension. Not all inputs have .sketch extension, so fasta/fastq is assumed.", ref_file);
@@ -157,105 +348,69 @@ pub fn fastx_to_sketches(
             else{
                 warn!("{} is not a valid fasta/fastq file; skipping.", ref_file);
             }
-        } else {
-            let mut j = 0;
-            let mut is_valid = true;
-            let mut reader = reader.unwrap();
-            trace!("Sketching {} {}", new_sketch.file_name, i);
-            while let Some(record) = reader.next() {
-                if record.is_ok() {
-                    let record = record.unwrap_or_else(|_| panic!("Invalid record for file {}", ref_file));
-                    let contig = record.id();
-                    let seq = record.seq();
-                    if seq.len() >= MIN_LENGTH_CONTIG {
-                        new_sketch
-                            .contigs
-                            .push(String::from_utf8(contig.to_vec()).unwrap());
-                        new_sketch.contig_lengths.push(seq.len() as GnPosition);
+            if group.files.len() > 1 {
+                warn!("Skipping genome {} because {} could not be read.", group.name, ref_file);
+            }
+            return None;
+        }
+        let mut reader = reader.unwrap();
+        while let Some(record) = reader.next() {
+            if let Ok(record) = record {
+                let contig = record.id();
+                let seq = record.seq();
+                if seq.len() >= MIN_LENGTH_CONTIG {
+                    new_sketch
+                        .contigs
+                        .push(String::from_utf8_lossy(contig).to_string());
+                    new_sketch.contig_lengths.push(seq.len() as GnPosition);
 
-                        new_sketch.total_sequence_length += seq.len();
-                        if sketch_params.use_aa {
-                            let orfs = seeding::get_orfs(&seq, sketch_params);
-                            seeding::fmh_seeds_aa_with_orf(
-                                &seq,
-                                sketch_params,
-                                j as u32,
-                                &mut new_sketch,
-                                orfs,
-                                seed,
-                            )
-                        } else {
-                            #[cfg(any(target_arch = "x86_64"))]
-                            {
-                                if is_x86_feature_detected!("avx2"){
-                                    use crate::avx2_seeding;
-                                    unsafe {
-                                        avx2_seeding::avx2_fmh_seeds(
-                                            &seq,
-                                            sketch_params,
-                                            j as u32,
-                                            &mut new_sketch,
-                                            seed,
-                                        );
-                                    }
-                                } else {
-                                    seeding::fmh_seeds(
-                                        &seq,
-                                        sketch_params,
-                                        j as u32,
-                                        &mut new_sketch,
-                                        seed,
-                                    );
-                                }
-                            }
-                            #[cfg(not(target_arch = "x86_64"))]
-                            {
-                            seeding::fmh_seeds(
-                                        &seq,
-                                        sketch_params,
-                                        j as u32,
-                                        &mut new_sketch,
-                                        seed,
-                                    );
-                            }
-
-                        }
-                        //new_sketch.contig_order = 0;
-                        j += 1;
+                    new_sketch.total_sequence_length += seq.len();
+                    new_sketch.masked_length += seeding::masked_bases(&seq, sketch_params);
+                    new_sketch.ambiguous_bases += seeding::ambiguous_bases(&seq);
+                    batch_length += seq.len();
+                    batch.push((j as u32, seq.to_vec()));
+                    if batch_length >= SEED_BATCH_LENGTH {
+                        seed_batch(&batch, sketch_params, &mut new_sketch, seed);
+                        batch.clear();
+                        batch_length = 0;
                     }
-                } else {
-                    warn!("File {} is not a valid fasta/fastq file", ref_file);
-                    is_valid = false;
-                    break;
+                    //new_sketch.contig_order = 0;
+                    j += 1;
                 }
-            }
-            if is_valid && j > 0{
-                {
-                    let mut locked = ref_sketches.lock().unwrap();
-                    locked.push(new_sketch);
+            } else {
+                warn!("File {} is not a valid fasta/fastq file", ref_file);
+                if group.files.len() > 1 {
+                    warn!("Skipping genome {} because {} could not be read.", group.name, ref_file);
                 }
-            }
-            if j == 0 && is_valid{
-                warn!("File {} consists of only contigs < {} bp. Skipping this file.",  ref_file, MIN_LENGTH_CONTIG);
+                return None;
             }
         }
-    });
-    let mut ref_sketches = ref_sketches.into_inner().unwrap();
-    ref_sketches.sort();
-    ref_sketches
+    }
+    seed_batch(&batch, sketch_params, &mut new_sketch, seed);
+    new_sketch.repetitive_kmers = seeding::get_repetitive_kmers(&new_sketch);
+    if j == 0 {
+        if group.files.len() > 1 {
+            warn!("Genome {} consists of only contigs < {} bp. Skipping this genome.", group.name, MIN_LENGTH_CONTIG);
+        } else {
+            warn!("File {} consists of only contigs < {} bp. Skipping this file.", group.name, MIN_LENGTH_CONTIG);
+        }
+        return None;
+    }
+    Some(new_sketch)
 }
+
 pub fn fastx_to_multiple_sketch_rewrite(
     ref_files: &Vec<String>,
     sketch_params: &SketchParams,
     seed: bool,
-) -> Vec<Sketch> {
+) -> Result<Vec<Sketch>, SkaniError> {
     let ref_sketches: Mutex<Vec<_>> = Mutex::new(vec![]);
     let mut index_vec = (0..ref_files.len()).collect::<Vec<usize>>();
     index_vec.shuffle(&mut rng());
     index_vec.into_par_iter().for_each(|i| {
         let mut small_contig_warn = false;
         let ref_file = &ref_files[i];
-        let reader = parse_fastx_file(ref_file);
+        let reader = open_fastx(ref_file);
         if reader.is_err() {
             warn!("{} is not a valid fasta/fastq file; skipping.", ref_file);
         } else {
@@ -263,9 +418,7 @@ pub fn fastx_to_multiple_sketch_rewrite(
             let mut reader = reader.unwrap();
             trace!("Sketching {} {}", ref_file, i);
             while let Some(record) = reader.next() {
-                if record.is_ok() {
-                    let record =
-                        record.unwrap_or_else(|_| panic!("Invalid record for file {}", ref_file));
+                if let Ok(record) = record {
                     let contig = record.id();
                     let seq = record.seq();
                     if seq.len() >= MIN_LENGTH_CONTIG {
@@ -278,61 +431,15 @@ pub fn fastx_to_multiple_sketch_rewrite(
                         );
                         new_sketch
                             .contigs
-                            .push(String::from_utf8(contig.to_vec()).unwrap());
+                            .push(String::from_utf8_lossy(contig).to_string());
                         new_sketch.contig_lengths.push(seq.len() as GnPosition);
 
                         new_sketch.total_sequence_length += seq.len();
-                        if sketch_params.use_aa {
-                            let orfs = seeding::get_orfs(&seq, sketch_params);
-                            seeding::fmh_seeds_aa_with_orf(
-                                &seq,
-                                sketch_params,
-                                0_u32,
-                                &mut new_sketch,
-                                orfs,
-                                seed,
-                            )
-                        } else {
-                            #[cfg(any(target_arch = "x86_64"))]
-                            {
-                                if is_x86_feature_detected!("avx2") {
-                                    use crate::avx2_seeding;
-                                    unsafe {
-                                        avx2_seeding::avx2_fmh_seeds(
-                                            &seq,
-                                            sketch_params,
-                                            0_u32,
-                                            &mut new_sketch,
-                                            seed,
-                                        );
-                                    }
-                                } else {
-                                    seeding::fmh_seeds(
-                                        &seq,
-                                        sketch_params,
-                                        0_u32,
-                                        &mut new_sketch,
-                                        seed,
-                                    );
-                                }
-                            }
-                            #[cfg(not(target_arch = "x86_64"))]
-                            {
-                            seeding::fmh_seeds(
-                                        &seq,
-                                        sketch_params,
-                                        j as u32,
-                                        &mut new_sketch,
-                                        seed,
-                                    );
-                            }
-                        }
+                        new_sketch.masked_length += seeding::masked_bases(&seq, sketch_params);
+                        new_sketch.ambiguous_bases += seeding::ambiguous_bases(&seq);
+                        seed_contig(&seq, sketch_params, 0_u32, &mut new_sketch, seed);
                         new_sketch.contig_order = j;
-
-//                        if new_sketch.total_sequence_length > REPET_KMER_THRESHOLD {
-//                            new_sketch.repetitive_kmers =
-//                                seeding::get_repetitive_kmers(&new_sketch.kmer_seeds_k, new_sketch.c);
-//                        }
+                        new_sketch.repetitive_kmers = seeding::get_repetitive_kmers(&new_sketch);
 
                         let mut locked = ref_sketches.lock().unwrap();
                         locked.push(new_sketch);
@@ -352,19 +459,91 @@ pub fn fastx_to_multiple_sketch_rewrite(
     });
     let mut ref_sketches = ref_sketches.into_inner().unwrap();
     ref_sketches.sort();
-    ref_sketches
+    Ok(ref_sketches)
+}
+
+///Sketches the genomes of multi-genome fastas. The records of each file are
+///grouped into genomes by `split`; each genome is named by its group and its
+///contig indices follow the order of its records in the file. Records whose ID
+///does not match form one genome named after the file, as without splitting.
+pub fn fastx_to_split_sketches(
+    ref_files: &[String],
+    sketch_params: &SketchParams,
+    split: &GenomeSplit,
+    seed: bool,
+) -> Result<Vec<Sketch>, SkaniError> {
+    let ref_sketches: Mutex<Vec<_>> = Mutex::new(vec![]);
+    let mut index_vec = (0..ref_files.len()).collect::<Vec<usize>>();
+    index_vec.shuffle(&mut rng());
+    index_vec.into_par_iter().for_each(|i| {
+        let mut small_contig_warn = false;
+        let ref_file = &ref_files[i];
+        if let Ok(mut reader) = open_fastx(ref_file) {
+            let mut genome_sketches: Vec<Sketch> = vec![];
+            let mut genome_index: FxHashMap<String, usize> = FxHashMap::default();
+            trace!("Sketching {} {}", ref_file, i);
+            while let Some(record) = reader.next() {
+                if let Ok(record) = record {
+                    let contig = String::from_utf8_lossy(record.id()).to_string();
+                    let seq = record.seq();
+                    if seq.len() >= MIN_LENGTH_CONTIG {
+                        let id = contig.split_whitespace().next().unwrap_or("");
+                        let genome = split.genome_name(id).unwrap_or(ref_file);
+                        let j = *genome_index.entry(genome.to_string()).or_insert_with(|| {
+                            genome_sketches.push(Sketch::new(
+                                sketch_params.marker_c,
+                                sketch_params.c,
+                                sketch_params.k,
+                                genome.to_string(),
+                                sketch_params.use_aa,
+                            ));
+                            genome_sketches.len() - 1
+                        });
+                        let new_sketch = &mut genome_sketches[j];
+                        let contig_index = new_sketch.contigs.len() as u32;
+                        new_sketch.contigs.push(contig);
+                        new_sketch.contig_lengths.push(seq.len() as GnPosition);
+
+                        new_sketch.total_sequence_length += seq.len();
+                        new_sketch.masked_length += seeding::masked_bases(&seq, sketch_params);
+                        new_sketch.ambiguous_bases += seeding::ambiguous_bases(&seq);
+                        seed_contig(&seq, sketch_params, contig_index, new_sketch, seed);
+                    }
+                    else if !small_contig_warn
+                    {
+                        small_contig_warn = true;
+                        warn!("At least one sequence in file {} has < {} bp. These sequences will be skipped.", ref_file, MIN_LENGTH_CONTIG);
+                    }
+                } else {
+                    warn!("File {} is not a valid fasta/fastq file", ref_file);
+                    genome_sketches.clear();
+                    break;
+                }
+            }
+            for new_sketch in genome_sketches.iter_mut() {
+                new_sketch.repetitive_kmers = seeding::get_repetitive_kmers(new_sketch);
+            }
+            debug!("{} genomes found in {}", genome_sketches.len(), ref_file);
+            let mut locked = ref_sketches.lock().unwrap();
+            locked.append(&mut genome_sketches);
+        } else {
+            warn!("{} is not a valid fasta/fastq file; skipping.", ref_file);
+        }
+    });
+    let mut ref_sketches = ref_sketches.into_inner().unwrap();
+    ref_sketches.sort();
+    Ok(ref_sketches)
 }
 
 pub fn write_phyllip_matrix(
     anis: &FxHashMap<usize, FxHashMap<usize, AniEstResult>>,
-    sketches: &Vec<Sketch>,
+    names: &[String],
     file_name: &str,
-    use_contig_names: bool,
     full_matrix: bool,
     diag: bool,
     aai: bool,
     distance: bool,
-) {
+) -> Result<(), SkaniError> {
     let perfect = if distance { 0. } else { 100. };
     let none = 100. - perfect;
 
@@ -372,18 +551,13 @@ pub fn write_phyllip_matrix(
     if file_name.is_empty() {
         let stdout = io::stdout();
         let mut handle = stdout.lock();
-        writeln!(&mut handle, "{}", sketches.len()).unwrap();
-        for i in 0..sketches.len() {
-            let name;
-            if use_contig_names {
-                name = &sketches[i].contigs[0];
-            } else {
-                name = &sketches[i].file_name;
-            }
-            write!(&mut handle, "{}", name).unwrap();
+        writeln!(&mut handle, "{}", names.len())?;
+        for i in 0..names.len() {
+            let name = &names[i];
+            write!(&mut handle, "{}", name)?;
             let end;
             if full_matrix {
-                end = sketches.len();
+                end = names.len();
             } else {
                 if diag {
                     end = i + 1;
@@ -393,96 +567,84 @@ pub fn write_phyllip_matrix(
             }
             for j in 0..end {
                 if j == i {
-                    write!(&mut handle, "\t{:.2}", perfect).unwrap();
+                    write!(&mut handle, "\t{:.2}", perfect)?;
                     continue;
                 }
                 let x = usize::min(i, j);
                 let y = usize::max(i, j);
                 if i == j {
-                    write!(&mut handle, "\t{:.2}", perfect).unwrap();
+                    write!(&mut handle, "\t{:.2}", perfect)?;
                 } else if !anis.contains_key(&x) || !anis[&x].contains_key(&y) {
-                    write!(&mut handle, "\t{:.2}", none).unwrap();
+                    write!(&mut handle, "\t{:.2}", none)?;
                 } else if anis[&x][&y].ani == -1. || anis[&x][&y].ani.is_nan() {
-                    write!(&mut handle, "\t{:.2}", none).unwrap();
+                    write!(&mut handle, "\t{:.2}", none)?;
                 } else {
                     let val = anis[&x][&y].ani * 100.;
                     let ani_val = if !distance { val } else { 100. - val };
-                    write!(&mut handle, "\t{:.2}", ani_val).unwrap();
+                    write!(&mut handle, "\t{:.2}", ani_val)?;
                 }
             }
-            writeln!(&mut handle).unwrap();
+            writeln!(&mut handle)?;
         }
 
         let af_mat_file = "skani_matrix.af".to_string();
-        let mut af_file = BufWriter::new(File::create(af_mat_file).unwrap());
-        writeln!(&mut af_file, "{}", sketches.len()).unwrap();
-        for i in 0..sketches.len() {
-            let name;
-            if use_contig_names {
-                name = &sketches[i].contigs[0];
-            } else {
-                name = &sketches[i].file_name;
-            }
-            write!(&mut af_file, "{}", name).unwrap();
+        let mut af_file = BufWriter::new(create_file(&af_mat_file)?);
+        writeln!(&mut af_file, "{}", names.len())?;
+        for i in 0..names.len() {
+            let name = &names[i];
+            write!(&mut af_file, "{}", name)?;
             //We always output full matrix for AF.
-            let end = sketches.len();
+            let end = names.len();
             for j in 0..end {
                 if i == j {
-                    write!(&mut af_file, "\t{:.2}", 100.).unwrap();
+                    write!(&mut af_file, "\t{:.2}", 100.)?;
                     continue;
                 }
                 let x = usize::min(i, j);
                 let y = usize::max(i, j);
                 if !anis.contains_key(&x) || !anis[&x].contains_key(&y) {
-                    write!(&mut af_file, "\t{:.2}", 0.).unwrap();
+                    write!(&mut af_file, "\t{:.2}", 0.)?;
                 } else if anis[&x][&y].ani == -1. || anis[&x][&y].ani.is_nan() {
-                    write!(&mut af_file, "\t{:.2}", 0.).unwrap();
+                    write!(&mut af_file, "\t{:.2}", 0.)?;
                 } else {
                     if j > i {
                         write!(
                             &mut af_file,
                             "\t{:.2}",
                             anis[&x][&y].align_fraction_ref * 100.
-                        )
-                        .unwrap();
+                        )?;
                     } else {
                         write!(
                             &mut af_file,
                             "\t{:.2}",
                             anis[&x][&y].align_fraction_query * 100.
-                        )
-                        .unwrap();
+                        )?;
                     }
                 }
             }
-            writeln!(&mut af_file).unwrap();
+            writeln!(&mut af_file)?;
         }
 
         info!("Aligned fraction matrix written to skani_matrix.af");
     } else {
         let ani_mat_file = file_name.to_string();
         let af_mat_file = format!("{}.af", file_name);
-        let mut ani_file = BufWriter::new(File::create(ani_mat_file).expect(file_name));
-        let mut af_file = BufWriter::new(File::create(af_mat_file).unwrap());
-        writeln!(&mut ani_file, "{}", sketches.len()).unwrap();
-        writeln!(&mut af_file, "{}", sketches.len()).unwrap();
-        for i in 0..sketches.len() {
-            let name;
-            if use_contig_names {
-                name = &sketches[i].contigs[0];
-            } else {
-                name = &sketches[i].file_name;
-            }
-            write!(&mut ani_file, "{}", name).unwrap();
-            write!(&mut af_file, "{}", name).unwrap();
-            let end = sketches.len();
+        let mut ani_file = BufWriter::new(create_file(&ani_mat_file)?);
+        let mut af_file = BufWriter::new(create_file(&af_mat_file)?);
+        writeln!(&mut ani_file, "{}", names.len())?;
+        writeln!(&mut af_file, "{}", names.len())?;
+        for i in 0..names.len() {
+            let name = &names[i];
+            write!(&mut ani_file, "{}", name)?;
+            write!(&mut af_file, "{}", name)?;
+            let end = names.len();
             for j in 0..end {
                 let full_cond = full_matrix || (i > j);
                 if i == j {
                     if full_cond || diag {
-                        write!(&mut ani_file, "\t{:.2}", perfect).unwrap();
+                        write!(&mut ani_file, "\t{:.2}", perfect)?;
                     }
-                    write!(&mut af_file, "\t{:.2}", 100.).unwrap();
+                    write!(&mut af_file, "\t{:.2}", 100.)?;
                     continue;
                 }
                 let x = usize::min(i, j);
@@ -490,39 +652,37 @@ pub fn write_phyllip_matrix(
 
                 if !anis.contains_key(&x) || !anis[&x].contains_key(&y) {
                     if full_cond {
-                        write!(&mut ani_file, "\t{:.2}", none).unwrap();
+                        write!(&mut ani_file, "\t{:.2}", none)?;
                     }
-                    write!(&mut af_file, "\t{:.2}", 0.).unwrap();
+                    write!(&mut af_file, "\t{:.2}", 0.)?;
                 } else if anis[&x][&y].ani == -1. || anis[&x][&y].ani.is_nan() {
                     if full_cond {
-                        write!(&mut ani_file, "\t{:.2}", none).unwrap();
+                        write!(&mut ani_file, "\t{:.2}", none)?;
                     }
-                    write!(&mut af_file, "\t{:.2}", 0.).unwrap();
+                    write!(&mut af_file, "\t{:.2}", 0.)?;
                 } else {
                     if full_cond {
                         let val = anis[&x][&y].ani * 100.;
                         let ani_val = if !distance { val } else { 100. - val };
-                        write!(&mut ani_file, "\t{:.2}", ani_val).unwrap();
+                        write!(&mut ani_file, "\t{:.2}", ani_val)?;
                     }
                     if j > i {
                         write!(
                             &mut af_file,
                             "\t{:.2}",
                             anis[&x][&y].align_fraction_ref * 100.
-                        )
-                        .unwrap();
+                        )?;
                     } else {
                         write!(
                             &mut af_file,
                             "\t{:.2}",
                             anis[&x][&y].align_fraction_query * 100.
-                        )
-                        .unwrap();
+                        )?;
                     }
                 }
             }
-            writeln!(&mut ani_file).unwrap();
-            writeln!(&mut af_file).unwrap();
+            writeln!(&mut ani_file)?;
+            writeln!(&mut af_file)?;
         }
 
         info!(
@@ -530,35 +690,36 @@ pub fn write_phyllip_matrix(
             file_name, file_name
         );
     }
+    Ok(())
 }
 
 pub fn write_sparse_matrix(
     anis: &FxHashMap<usize, FxHashMap<usize, AniEstResult>>,
-    sketches: &Vec<Sketch>,
+    sketches: &[Sketch],
     file_name: &str,
     aai: bool,
     est_ci: bool,
     detailed_out: bool,
     diag: bool,
     append: bool,
-) {
+) -> Result<(), SkaniError> {
     let id_str = if aai { "AAI" } else { "ANI" };
     if file_name.is_empty() {
         let stdout = io::stdout();
         let mut handle = stdout.lock();
         if !append {
-            write_header(&mut handle, id_str, est_ci, detailed_out);
+            write_header(&mut handle, id_str, est_ci, detailed_out, false)?;
         }
-        //        write!(&mut handle,"Ref_file\tQuery_file\t{}\tAlign_fraction_ref\tAlign_fraction_query\t{}_95_percentile\t{}_5_percentile\tRef_name\tQuery_name\n", id_str, id_str, id_str).unwrap();
+        //        write!(&mut handle,"Ref_file\tQuery_file\t{}\tAlign_fraction_ref\tAlign_fraction_query\t{}_95_percentile\t{}_5_percentile\tRef_name\tQuery_name\n", id_str, id_str, id_str)?;
         if diag {
             for sketch in sketches.iter() {
-                write_ani_res_perfect(&mut handle, sketch, est_ci, detailed_out);
+                write_ani_res_perfect(&mut handle, sketch, est_ci, detailed_out)?;
             }
         }
         for i in anis.keys() {
             for (j, ani_res) in anis[i].iter() {
                 if !(anis[i][j].ani == -1. || anis[i][j].ani.is_nan()) {
-                    write_ani_res(&mut handle, ani_res, est_ci, detailed_out);
+                    write_ani_res(&mut handle, ani_res, est_ci, detailed_out, false)?;
                 }
             }
         }
@@ -569,44 +730,46 @@ pub fn write_sparse_matrix(
             let file = OpenOptions::new()
                 .append(true)
                 .create(true)
-                .open(ani_mat_file)
-                .expect(file_name);
+                .open(&ani_mat_file)
+                .map_err(|e| SkaniError::FileOpen(ani_mat_file.clone(), e))?;
             ani_file = BufWriter::new(file);
         } else {
-            ani_file = BufWriter::new(File::create(ani_mat_file).expect(file_name));
+            ani_file = BufWriter::new(create_file(&ani_mat_file)?);
         }
         if !append {
-            write_header(&mut ani_file, id_str, est_ci, detailed_out);
+            write_header(&mut ani_file, id_str, est_ci, detailed_out, false)?;
         }
 
         if diag {
             for sketch in sketches.iter() {
-                write_ani_res_perfect(&mut ani_file, sketch, est_ci, detailed_out);
+                write_ani_res_perfect(&mut ani_file, sketch, est_ci, detailed_out)?;
             }
         }
 
         for i in anis.keys() {
             if diag {
-                write_ani_res_perfect(&mut ani_file, &sketches[*i], est_ci, detailed_out);
+                write_ani_res_perfect(&mut ani_file, &sketches[*i], est_ci, detailed_out)?;
             }
             for (j, ani_res) in anis[i].iter() {
                 if !(anis[i][j].ani == -1. || anis[i][j].ani.is_nan()) {
-                    write_ani_res(&mut ani_file, ani_res, est_ci, detailed_out);
+                    write_ani_res(&mut ani_file, ani_res, est_ci, detailed_out, false)?;
                 }
             }
         }
     }
+    Ok(())
 }
 
 pub fn write_query_ref_list(
-    anis: &Vec<AniEstResult>,
+    anis: &[AniEstResult],
     file_name: &str,
     n: usize,
     aai: bool,
     est_ci: bool,
     detailed_out: bool,
+    coverage: bool,
     append: bool,
-) {
+) -> Result<(), SkaniError> {
     let id_str = if aai { "AAI" } else { "ANI" };
     let mut query_file_result_map = FxHashMap::default();
     let out_file = file_name.to_string();
@@ -632,14 +795,14 @@ pub fn write_query_ref_list(
         let stdout = io::stdout();
         let mut handle = stdout.lock();
         if !append {
-            write_header(&mut handle, id_str, est_ci, detailed_out);
+            write_header(&mut handle, id_str, est_ci, detailed_out, coverage)?;
         }
         for key in sorted_keys {
             let mut anis = query_file_result_map[key].clone();
 
             anis.sort_by(|y, x| x.ani.partial_cmp(&y.ani).unwrap());
             for i in 0..usize::min(n, anis.len()) {
-                write_ani_res(&mut handle, anis[i], est_ci, detailed_out);
+                write_ani_res(&mut handle, anis[i], est_ci, detailed_out, coverage)?;
             }
         }
     } else {
@@ -648,76 +811,79 @@ pub fn write_query_ref_list(
             let file = OpenOptions::new()
                 .append(true)
                 .create(true)
-                .open(out_file)
-                .expect(file_name);
+                .open(&out_file)
+                .map_err(|e| SkaniError::FileOpen(out_file.clone(), e))?;
             handle = BufWriter::new(file);
         } else {
-            handle = BufWriter::new(File::create(out_file).expect(file_name));
+            handle = BufWriter::new(create_file(&out_file)?);
         }
 
         if !append {
-            write_header(&mut handle, id_str, est_ci, detailed_out);
+            write_header(&mut handle, id_str, est_ci, detailed_out, coverage)?;
         }
         for key in sorted_keys {
             let mut anis = query_file_result_map[key].clone();
 
             anis.sort_by(|y, x| x.ani.partial_cmp(&y.ani).unwrap());
             for i in 0..usize::min(n, anis.len()) {
-                write_ani_res(&mut handle, anis[i], est_ci, detailed_out);
+                write_ani_res(&mut handle, anis[i], est_ci, detailed_out, coverage)?;
             }
         }
     }
+    Ok(())
+}
+
+fn create_file(file_name: &str) -> Result<File, SkaniError> {
+    File::create(file_name).map_err(|e| SkaniError::FileOpen(file_name.to_string(), e))
 }
 
-pub fn sketches_from_sketch(ref_files: &Vec<String>) -> (SketchParams, Vec<Sketch>) {
-    let ret_sketch_params: Mutex<SketchParams> = Mutex::new(SketchParams::default());
-    let ret_ref_sketches: Mutex<Vec<Sketch>> = Mutex::new(vec![]);
+pub fn sketches_from_sketch(ref_files: &Vec<String>) -> Result<(SketchParams, Vec<Sketch>), SkaniError> {
+    let ret_ref_sketches: Mutex<Vec<(SketchParams, Sketch)>> = Mutex::new(vec![]);
 
     (0..ref_files.len())
         .collect::<Vec<usize>>()
         .into_par_iter()
-        .for_each(|i| {
+        .try_for_each(|i| {
             let sketch_file = &ref_files[i];
             if !sketch_file.contains("markers.bin") {
-                let f = File::open(sketch_file);
-                if f.is_err() {
-                    error!(
-                        "Problem reading sketch file {}. Perhaps your file path is wrong? Exiting.",
-                        sketch_file
-                    );
-                    std::process::exit(1)
-                }
-                let reader = BufReader::new(f.unwrap());
-                let res: Result<(SketchParams, Sketch), _> = bincode::deserialize_from(reader);
-                if res.is_ok() {
-                    let (temp_sketch_param, temp_ref_sketch) = res.unwrap();
-                    let mut locked = ret_sketch_params.lock().unwrap();
-                    *locked = temp_sketch_param;
-                    let mut locked = ret_ref_sketches.lock().unwrap();
-                    locked.push(temp_ref_sketch);
-                } else if sketch_file != "markers.bin" {
-                    error!(
-                        "{} is not a valid .sketch file or is corrupted.",
-                        sketch_file
-                    );
-                }
+                let (header, temp_ref_sketch) = format::read_sketch_file(sketch_file)?;
+                let mut locked = ret_ref_sketches.lock().unwrap();
+                locked.push((header.sketch_params, temp_ref_sketch));
             }
-        });
+            Ok::<(), SkaniError>(())
+        })?;
 
-    let ret_sketch_params = ret_sketch_params.into_inner().unwrap();
-    let mut ret_ref_sketches = ret_ref_sketches.into_inner().unwrap();
+    let ret_ref_sketches = ret_ref_sketches.into_inner().unwrap();
+    //Sketches made with different c or marker_c are downsampled to the coarsest.
+    let mut ret_sketch_params = match ret_ref_sketches.first() {
+        Some((sketch_params, _)) => sketch_params.clone(),
+        None => SketchParams::default(),
+    };
+    for (sketch_params, sketch) in ret_ref_sketches.iter() {
+        if *sketch_params != ret_sketch_params {
+            ret_sketch_params = ret_sketch_params.common_downsampled(sketch_params).ok_or_else(|| {
+                SkaniError::SketchParamMismatch(format!(
+                    "{} was sketched with different parameters than the other sketches",
+                    sketch.file_name
+                ))
+            })?;
+        }
+    }
+    let mut ret_ref_sketches = ret_ref_sketches
+        .into_par_iter()
+        .map(|(sketch_params, mut sketch)| {
+            if sketch_params != ret_sketch_params {
+                seeding::downsample_sketch(&mut sketch, &ret_sketch_params);
+            }
+            sketch
+        })
+        .collect::<Vec<Sketch>>();
 
     ret_ref_sketches.sort_by(|x, y| x.file_name.cmp(&y.file_name));
-    (ret_sketch_params, ret_ref_sketches)
+    Ok((ret_sketch_params, ret_ref_sketches))
 }
 
-pub fn marker_sketches_from_marker_file(marker_file: &str) -> (SketchParams, Vec<Sketch>) {
-    let reader = BufReader::new(File::open(marker_file).unwrap());
-    let res: Result<(SketchParams, Vec<Sketch>), _> = bincode::deserialize_from(reader);
-    if res.is_ok() {
-        res.unwrap()
-    } else {
-        error!("Problem reading {}. Exiting. ", marker_file);
-        std::process::exit(1)
-    }
+pub fn marker_sketches_from_marker_file(marker_file: &str) -> Result<(SketchParams, Vec<Sketch>), SkaniError> {
+    let (header, marker_sketches) = format::read_marker_file(marker_file)?;
+    Ok((header.sketch_params, marker_sketches))
 }
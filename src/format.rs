@@ -0,0 +1,433 @@
+use crate::error::SkaniError;
+use crate::params::*;
+use crate::types::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+//On-disk layout of .sketch and markers.bin files:
+//
+//  magic (8 bytes) | format version (u32 LE) | header length (u32 LE) | header (JSON) | payload (bincode)
+//
+//The header is JSON so that it stays readable across versions; it holds the
+//sketch parameters and a checksum of the payload. Files written before the
+//container existed are raw bincode `(SketchParams, payload)` and are read as
+//format version 0.
+
+pub const SKETCH_MAGIC: &[u8; 8] = b"SKANISKT";
+pub const MARKER_MAGIC: &[u8; 8] = b"SKANIMRK";
+pub const INDEX_MAGIC: &[u8; 8] = b"SKANIIDX";
+pub const MARKER_INDEX_MAGIC: &[u8; 8] = b"SKANIMIX";
+pub const FORMAT_VERSION: u32 = 1;
+pub const LEGACY_FORMAT_VERSION: u32 = 0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileKind {
+    Sketch,
+    Markers,
+    Index,
+    MarkerIndex,
+}
+
+impl FileKind {
+    fn magic(&self) -> &'static [u8; 8] {
+        match self {
+            FileKind::Sketch => SKETCH_MAGIC,
+            FileKind::Markers => MARKER_MAGIC,
+            FileKind::Index => INDEX_MAGIC,
+            FileKind::MarkerIndex => MARKER_INDEX_MAGIC,
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            FileKind::Sketch => ".sketch",
+            FileKind::Markers => "markers.bin",
+            FileKind::Index => "sketch index",
+            FileKind::MarkerIndex => "marker index",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileHeader {
+    pub format_version: u32,
+    pub skani_version: String,
+    pub sketch_params: SketchParams,
+    pub num_sketches: usize,
+    pub payload_len: u64,
+    pub checksum: u64,
+    ///For files derived from markers.bin, the checksum of the markers.bin payload they were built from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub markers_checksum: Option<u64>,
+}
+
+///64-bit FNV-1a.
+pub fn checksum(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+///Sketch parameters as laid out by skani <= 0.2.2, before the container existed.
+#[derive(Deserialize)]
+struct LegacySketchParams {
+    c: usize,
+    k: usize,
+    marker_c: usize,
+    use_syncs: bool,
+    use_aa: bool,
+    acgt_to_aa_encoding: Vec<MarkerBits>,
+    acgt_to_aa_letters: Vec<u8>,
+    orf_size: usize,
+}
+
+impl From<LegacySketchParams> for SketchParams {
+    fn from(p: LegacySketchParams) -> SketchParams {
+        SketchParams {
+            c: p.c,
+            k: p.k,
+            marker_c: p.marker_c,
+            use_syncs: p.use_syncs,
+            use_aa: p.use_aa,
+            acgt_to_aa_encoding: p.acgt_to_aa_encoding,
+            acgt_to_aa_letters: p.acgt_to_aa_letters,
+            orf_size: p.orf_size,
+            use_seed64: false,
+            sync_s: 0,
+            closed_syncs: false,
+            seed_pattern: String::new(),
+            mask_lowercase: false,
+            dust_level: None,
+        }
+    }
+}
+
+///Sketch as laid out by skani <= 0.2.2, before the container existed.
+#[derive(Deserialize)]
+struct LegacySketch {
+    file_name: String,
+    kmer_seeds_k: Option<KmerSeeds>,
+    contigs: Vec<String>,
+    total_sequence_length: usize,
+    contig_lengths: Vec<GnPosition>,
+    _repetitive_kmers: usize,
+    marker_seeds: MMHashSet<MarkerBits>,
+    marker_c: usize,
+    c: usize,
+    k: usize,
+    contig_order: usize,
+    amino_acid: bool,
+}
+
+impl From<LegacySketch> for Sketch {
+    fn from(s: LegacySketch) -> Sketch {
+        Sketch {
+            file_name: s.file_name,
+            kmer_seeds_k: s.kmer_seeds_k,
+            contigs: s.contigs,
+            total_sequence_length: s.total_sequence_length,
+            contig_lengths: s.contig_lengths,
+            //Older versions did not compute a repetitive seed cutoff
+            //(some stored usize::MAX); 0 leaves it unset.
+            repetitive_kmers: 0,
+            marker_seeds: s.marker_seeds,
+            marker_c: s.marker_c,
+            c: s.c,
+            k: s.k,
+            contig_order: s.contig_order,
+            amino_acid: s.amino_acid,
+            kmer_seeds_k64: None,
+            seed_weight: s.k,
+            ..Default::default()
+        }
+    }
+}
+
+fn decode_legacy_sketch(payload: &[u8]) -> bincode::Result<Sketch> {
+    Ok(bincode::deserialize::<LegacySketch>(payload)?.into())
+}
+
+fn decode_legacy_sketches(payload: &[u8]) -> bincode::Result<Vec<Sketch>> {
+    let sketches: Vec<LegacySketch> = bincode::deserialize(payload)?;
+    Ok(sketches.into_iter().map(LegacySketch::into).collect())
+}
+
+fn encode_container<T: Serialize>(
+    kind: FileKind,
+    sketch_params: &SketchParams,
+    num_sketches: usize,
+    payload: &T,
+) -> Result<(FileHeader, Vec<u8>), SkaniError> {
+    let payload = bincode::serialize(payload)?;
+    let header = FileHeader {
+        format_version: FORMAT_VERSION,
+        skani_version: VERSION.to_string(),
+        sketch_params: sketch_params.clone(),
+        num_sketches,
+        payload_len: payload.len() as u64,
+        checksum: checksum(&payload),
+        markers_checksum: None,
+    };
+    let header_json = serde_json::to_vec(&header)
+        .map_err(|e| SkaniError::InvalidParameter(format!("could not encode header: {}", e)))?;
+
+    let mut bytes = Vec::with_capacity(16 + header_json.len() + payload.len());
+    bytes.extend_from_slice(kind.magic());
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(header_json.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&header_json);
+    bytes.extend_from_slice(&payload);
+    Ok((header, bytes))
+}
+
+fn write_container<T: Serialize>(
+    file_name: &str,
+    kind: FileKind,
+    sketch_params: &SketchParams,
+    num_sketches: usize,
+    payload: &T,
+) -> Result<FileHeader, SkaniError> {
+    let (header, bytes) = encode_container(kind, sketch_params, num_sketches, payload)?;
+    let file = File::create(file_name).map_err(|e| SkaniError::FileOpen(file_name.to_string(), e))?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(&bytes)?;
+    writer.flush()?;
+    Ok(header)
+}
+
+///Splits a container into its header and payload bytes. Returns `None` for the
+///header if the file has no magic bytes, i.e. it is a legacy file.
+fn split_container<'a>(
+    file_name: &str,
+    kind: FileKind,
+    bytes: &'a [u8],
+) -> Result<(Option<FileHeader>, &'a [u8]), SkaniError> {
+    if bytes.len() >= 8 && &bytes[0..8] != kind.magic() && bytes[0..5] == SKETCH_MAGIC[0..5] {
+        return Err(SkaniError::CorruptSketch(format!(
+            "{} ({} file expected)",
+            file_name,
+            kind.description()
+        )));
+    }
+    if bytes.len() < 8 || &bytes[0..8] != kind.magic() {
+        if kind == FileKind::Index || kind == FileKind::MarkerIndex {
+            return Err(SkaniError::CorruptSketch(file_name.to_string()));
+        }
+        return Ok((None, bytes));
+    }
+    let corrupt = || SkaniError::CorruptSketch(file_name.to_string());
+    if bytes.len() < 16 {
+        return Err(corrupt());
+    }
+    let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+    if version > FORMAT_VERSION {
+        return Err(SkaniError::UnsupportedVersion(
+            file_name.to_string(),
+            version,
+            FORMAT_VERSION,
+        ));
+    }
+    let header_len = u32::from_le_bytes(bytes[12..16].try_into().unwrap()) as usize;
+    if bytes.len() < 16 + header_len {
+        return Err(corrupt());
+    }
+    let header: FileHeader =
+        serde_json::from_slice(&bytes[16..16 + header_len]).map_err(|_| corrupt())?;
+    let payload = &bytes[16 + header_len..];
+    if payload.len() as u64 != header.payload_len || checksum(payload) != header.checksum {
+        return Err(corrupt());
+    }
+    Ok((Some(header), payload))
+}
+
+fn read_container<T: DeserializeOwned>(
+    file_name: &str,
+    kind: FileKind,
+) -> Result<(FileHeader, T), SkaniError> {
+    let bytes = std::fs::read(file_name).map_err(|e| SkaniError::FileOpen(file_name.to_string(), e))?;
+    decode_container(file_name, kind, &bytes)
+}
+
+fn decode_container<T: DeserializeOwned>(
+    file_name: &str,
+    kind: FileKind,
+    bytes: &[u8],
+) -> Result<(FileHeader, T), SkaniError> {
+    decode_container_or_legacy(file_name, kind, bytes, |payload| bincode::deserialize(payload))
+}
+
+///Decodes a container, or a legacy file whose contents are decoded by
+///`decode_legacy`.
+fn decode_container_or_legacy<T: DeserializeOwned>(
+    file_name: &str,
+    kind: FileKind,
+    bytes: &[u8],
+    decode_legacy: impl FnOnce(&[u8]) -> bincode::Result<T>,
+) -> Result<(FileHeader, T), SkaniError> {
+    let corrupt = || SkaniError::CorruptSketch(file_name.to_string());
+    let (header, payload) = split_container(file_name, kind, bytes)?;
+    match header {
+        Some(header) => {
+            let contents: T = bincode::deserialize(payload).map_err(|_| corrupt())?;
+            Ok((header, contents))
+        }
+        None => {
+            //Legacy payloads are a (params, contents) tuple, i.e. the two encodings back to back.
+            let mut rest = payload;
+            let params: LegacySketchParams =
+                bincode::deserialize_from(&mut rest).map_err(|_| corrupt())?;
+            let contents = decode_legacy(rest).map_err(|_| corrupt())?;
+            let header = FileHeader {
+                format_version: LEGACY_FORMAT_VERSION,
+                skani_version: "<= 0.2.2".to_string(),
+                sketch_params: params.into(),
+                num_sketches: 0,
+                payload_len: payload.len() as u64,
+                checksum: checksum(payload),
+                markers_checksum: None,
+            };
+            Ok((header, contents))
+        }
+    }
+}
+
+fn read_sketch_container(file_name: &str, bytes: &[u8]) -> Result<(FileHeader, Sketch), SkaniError> {
+    let (header, mut sketch) =
+        decode_container_or_legacy(file_name, FileKind::Sketch, bytes, decode_legacy_sketch)?;
+    set_legacy_marker_c(&header, std::slice::from_mut(&mut sketch));
+    Ok((header, sketch))
+}
+
+///skani <= 0.2.2 stored c as the marker_c of every sketch; the file's
+///parameters have the right value.
+fn set_legacy_marker_c(header: &FileHeader, sketches: &mut [Sketch]) {
+    if header.format_version == LEGACY_FORMAT_VERSION {
+        for sketch in sketches.iter_mut() {
+            sketch.marker_c = header.sketch_params.marker_c;
+        }
+    }
+}
+
+pub fn write_sketch_file(
+    file_name: &str,
+    sketch_params: &SketchParams,
+    sketch: &Sketch,
+) -> Result<(), SkaniError> {
+    write_container(file_name, FileKind::Sketch, sketch_params, 1, sketch)?;
+    Ok(())
+}
+
+///Returns the header written, whose checksum identifies this set of markers.
+pub fn write_marker_file(
+    file_name: &str,
+    sketch_params: &SketchParams,
+    marker_sketches: &[Sketch],
+) -> Result<FileHeader, SkaniError> {
+    write_container(
+        file_name,
+        FileKind::Markers,
+        sketch_params,
+        marker_sketches.len(),
+        &marker_sketches,
+    )
+}
+
+pub fn read_sketch_file(file_name: &str) -> Result<(FileHeader, Sketch), SkaniError> {
+    let bytes = std::fs::read(file_name).map_err(|e| SkaniError::FileOpen(file_name.to_string(), e))?;
+    let (mut header, sketch) = read_sketch_container(file_name, &bytes)?;
+    header.num_sketches = 1;
+    Ok((header, sketch))
+}
+
+pub fn read_marker_file(file_name: &str) -> Result<(FileHeader, Vec<Sketch>), SkaniError> {
+    let bytes = std::fs::read(file_name).map_err(|e| SkaniError::FileOpen(file_name.to_string(), e))?;
+    let (mut header, mut sketches) =
+        decode_container_or_legacy(file_name, FileKind::Markers, &bytes, decode_legacy_sketches)?;
+    set_legacy_marker_c(&header, &mut sketches);
+    header.num_sketches = sketches.len();
+    Ok((header, sketches))
+}
+
+///Encodes a sketch the same way as a .sketch file, for storing in a packed database.
+pub fn encode_sketch(sketch_params: &SketchParams, sketch: &Sketch) -> Result<Vec<u8>, SkaniError> {
+    let (_header, bytes) = encode_container(FileKind::Sketch, sketch_params, 1, sketch)?;
+    Ok(bytes)
+}
+
+///Decodes a sketch record; `name` is only used for error messages.
+pub fn decode_sketch(name: &str, bytes: &[u8]) -> Result<(FileHeader, Sketch), SkaniError> {
+    let (mut header, sketch) = read_sketch_container(name, bytes)?;
+    header.num_sketches = 1;
+    Ok((header, sketch))
+}
+
+pub fn write_index_file<T: Serialize>(
+    file_name: &str,
+    sketch_params: &SketchParams,
+    num_sketches: usize,
+    index: &T,
+) -> Result<(), SkaniError> {
+    write_container(file_name, FileKind::Index, sketch_params, num_sketches, index)?;
+    Ok(())
+}
+
+pub fn read_index_file<T: DeserializeOwned>(file_name: &str) -> Result<(FileHeader, T), SkaniError> {
+    read_container::<T>(file_name, FileKind::Index)
+}
+
+///Writes a container whose payload is raw bytes instead of bincode, for files
+///that are read in place (memory-mapped) instead of deserialized.
+pub fn write_raw_container(
+    file_name: &str,
+    kind: FileKind,
+    mut header: FileHeader,
+    payload: &[u8],
+) -> Result<(), SkaniError> {
+    header.format_version = FORMAT_VERSION;
+    header.payload_len = payload.len() as u64;
+    header.checksum = checksum(payload);
+    let header = serde_json::to_vec(&header)
+        .map_err(|e| SkaniError::InvalidParameter(format!("could not encode header: {}", e)))?;
+    let file = File::create(file_name).map_err(|e| SkaniError::FileOpen(file_name.to_string(), e))?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(kind.magic())?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    writer.write_all(&(header.len() as u32).to_le_bytes())?;
+    writer.write_all(&header)?;
+    writer.write_all(payload)?;
+    writer.flush()?;
+    Ok(())
+}
+
+///Parses the header of a raw container and returns it with the offset of the
+///payload. Only the payload length is checked, not its checksum, so that the
+///payload does not have to be read in full.
+pub fn read_raw_header(file_name: &str, kind: FileKind, bytes: &[u8]) -> Result<(FileHeader, usize), SkaniError> {
+    let corrupt = || SkaniError::CorruptSketch(file_name.to_string());
+    if bytes.len() < 16 || &bytes[0..8] != kind.magic() {
+        return Err(corrupt());
+    }
+    let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+    if version > FORMAT_VERSION {
+        return Err(SkaniError::UnsupportedVersion(
+            file_name.to_string(),
+            version,
+            FORMAT_VERSION,
+        ));
+    }
+    let header_len = u32::from_le_bytes(bytes[12..16].try_into().unwrap()) as usize;
+    if bytes.len() < 16 + header_len {
+        return Err(corrupt());
+    }
+    let header: FileHeader =
+        serde_json::from_slice(&bytes[16..16 + header_len]).map_err(|_| corrupt())?;
+    if (bytes.len() - 16 - header_len) as u64 != header.payload_len {
+        return Err(corrupt());
+    }
+    Ok((header, 16 + header_len))
+}
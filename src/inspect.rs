@@ -0,0 +1,329 @@
+use crate::db;
+use crate::db::SketchDb;
+use crate::error::SkaniError;
+use crate::format;
+use crate::format::FileHeader;
+use crate::params::*;
+use crate::seeding;
+use crate::types::*;
+use log::*;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+//Human readable output only lists this many contig lengths per genome.
+const MAX_PRINTED_CONTIGS: usize = 20;
+
+#[derive(Serialize, Debug)]
+pub struct GenomeSummary {
+    pub name: String,
+    pub num_contigs: usize,
+    pub contig_lengths: Vec<GnPosition>,
+    pub n50: Option<GnPosition>,
+    pub total_sequence_length: usize,
+    pub masked_length: usize,
+    pub ambiguous_bases: usize,
+    pub num_seeds: Option<usize>,
+    ///Seeds occurring more often than this are masked in chaining.
+    pub repetitive_seed_cutoff: Option<usize>,
+    pub masked_repetitive_seeds: Option<usize>,
+    pub num_marker_seeds: usize,
+    pub compression_factor: Option<f64>,
+    pub marker_compression_factor: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FileSummary {
+    pub file: String,
+    pub kind: String,
+    pub format_version: u32,
+    pub skani_version: String,
+    pub c: usize,
+    pub k: usize,
+    pub marker_c: usize,
+    pub amino_acid: bool,
+    ///"open" or "closed" with the s-mer length if seeds are syncmers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub syncmers: Option<String>,
+    ///Spaced seed pattern, if seeds are spaced.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed_pattern: Option<String>,
+    pub mask_lowercase: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dust_level: Option<f64>,
+    pub num_genomes: usize,
+    pub genomes: Vec<GenomeSummary>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InspectReport {
+    pub files: Vec<FileSummary>,
+    pub consistent_params: bool,
+}
+
+pub fn n50(contig_lengths: &[GnPosition]) -> Option<GnPosition> {
+    if contig_lengths.is_empty() {
+        return None;
+    }
+    let mut sorted = contig_lengths.to_vec();
+    sorted.sort_by(|x, y| y.cmp(x));
+    let total: u64 = sorted.iter().map(|x| *x as u64).sum();
+    let mut cumulative = 0;
+    for len in sorted.iter() {
+        cumulative += *len as u64;
+        if cumulative * 2 >= total {
+            return Some(*len);
+        }
+    }
+    None
+}
+
+pub fn summarize_genome(sketch: &Sketch) -> GenomeSummary {
+    let num_seeds = sketch.num_seeds();
+    let repetitive_seed_cutoff = if sketch.repetitive_kmers > 0 { Some(sketch.repetitive_kmers) } else { None };
+    let masked_repetitive_seeds = match (repetitive_seed_cutoff, &sketch.kmer_seeds_k, &sketch.kmer_seeds_k64) {
+        (Some(cutoff), _, Some(kmer_seeds)) => Some(seeding::count_repetitive_seeds(kmer_seeds, cutoff)),
+        (Some(cutoff), Some(kmer_seeds), None) => Some(seeding::count_repetitive_seeds(kmer_seeds, cutoff)),
+        _ => None,
+    };
+    let compression_factor = match num_seeds {
+        Some(n) if n > 0 => Some(sketch.total_sequence_length as f64 / n as f64),
+        _ => None,
+    };
+    let marker_compression_factor = if sketch.marker_seeds.is_empty() {
+        None
+    } else {
+        Some(sketch.total_sequence_length as f64 / sketch.marker_seeds.len() as f64)
+    };
+    GenomeSummary {
+        name: sketch.file_name.clone(),
+        num_contigs: sketch.contigs.len(),
+        contig_lengths: sketch.contig_lengths.clone(),
+        n50: n50(&sketch.contig_lengths),
+        total_sequence_length: sketch.total_sequence_length,
+        masked_length: sketch.masked_length,
+        ambiguous_bases: sketch.ambiguous_bases,
+        num_seeds,
+        repetitive_seed_cutoff,
+        masked_repetitive_seeds,
+        num_marker_seeds: sketch.marker_seeds.len(),
+        compression_factor,
+        marker_compression_factor,
+    }
+}
+
+fn syncmer_description(sketch_params: &SketchParams) -> Option<String> {
+    if !sketch_params.use_syncs {
+        return None;
+    }
+    let kind = if sketch_params.closed_syncs { "closed" } else { "open" };
+    Some(format!("{}, s = {}", kind, sketch_params.sync_s))
+}
+
+fn summarize_file(file: &str, kind: &str, header: &FileHeader, genomes: Vec<GenomeSummary>) -> FileSummary {
+    FileSummary {
+        file: file.to_string(),
+        kind: kind.to_string(),
+        format_version: header.format_version,
+        skani_version: header.skani_version.clone(),
+        c: header.sketch_params.c,
+        k: header.sketch_params.k,
+        marker_c: header.sketch_params.marker_c,
+        amino_acid: header.sketch_params.use_aa,
+        syncmers: syncmer_description(&header.sketch_params),
+        seed_pattern: if header.sketch_params.seed_pattern.is_empty() {
+            None
+        } else {
+            Some(header.sketch_params.seed_pattern.clone())
+        },
+        mask_lowercase: header.sketch_params.mask_lowercase,
+        dust_level: header.sketch_params.dust_level,
+        num_genomes: header.num_sketches,
+        genomes,
+    }
+}
+
+fn is_marker_file(file: &str) -> bool {
+    file.contains("markers.bin")
+}
+
+///Summarizes a .sketch file, a markers.bin file, or every sketch in a folder.
+///For a folder, markers.bin is only summarized by its header; sketches in a
+///packed database are listed as `sketches.db:<name>`.
+pub fn inspect_paths(paths: &[String]) -> Result<InspectReport, SkaniError> {
+    let mut files = vec![];
+    let mut params: Vec<SketchParams> = vec![];
+    for path in paths {
+        if Path::new(path).is_dir() {
+            let dir = std::fs::read_dir(path).map_err(|e| SkaniError::FileOpen(path.to_string(), e))?;
+            let mut entries = vec![];
+            for entry in dir {
+                entries.push(entry?.path().to_string_lossy().to_string());
+            }
+            entries.sort();
+            let mut found = false;
+            if db::is_packed(Path::new(path)) {
+                let sketch_db = SketchDb::open(Path::new(path))?;
+                let data_file = Path::new(path).join(db::PACKED_DATA_FILE);
+                for name in sketch_db.names()? {
+                    let (header, sketch) = sketch_db.load(&name)?;
+                    let file = format!("{}:{}", data_file.to_string_lossy(), name);
+                    files.push(summarize_file(&file, "sketch", &header, vec![summarize_genome(&sketch)]));
+                    params.push(header.sketch_params);
+                    found = true;
+                }
+            }
+            for entry in entries {
+                if is_marker_file(&entry) {
+                    let (header, _) = format::read_marker_file(&entry)?;
+                    files.push(summarize_file(&entry, "markers", &header, vec![]));
+                    params.push(header.sketch_params);
+                    found = true;
+                } else if entry.ends_with(".sketch") {
+                    let (header, sketch) = format::read_sketch_file(&entry)?;
+                    files.push(summarize_file(&entry, "sketch", &header, vec![summarize_genome(&sketch)]));
+                    params.push(header.sketch_params);
+                    found = true;
+                }
+            }
+            if !found {
+                warn!("No .sketch or markers.bin files found in {}", path);
+            }
+        } else if is_marker_file(path) {
+            let (header, sketches) = format::read_marker_file(path)?;
+            let genomes = sketches.iter().map(summarize_genome).collect();
+            files.push(summarize_file(path, "markers", &header, genomes));
+            params.push(header.sketch_params);
+        } else {
+            let (header, sketch) = format::read_sketch_file(path)?;
+            files.push(summarize_file(path, "sketch", &header, vec![summarize_genome(&sketch)]));
+            params.push(header.sketch_params);
+        }
+    }
+    if files.is_empty() {
+        return Err(SkaniError::NoInputs("No sketches found to inspect.".to_string()));
+    }
+    let consistent_params = params.windows(2).all(|x| x[0] == x[1]);
+    Ok(InspectReport { files, consistent_params })
+}
+
+fn opt_to_string<T: std::fmt::Display>(val: &Option<T>) -> String {
+    match val {
+        Some(x) => format!("{}", x),
+        None => "NA".to_string(),
+    }
+}
+
+fn write_genome(writer: &mut impl Write, genome: &GenomeSummary) -> io::Result<()> {
+    writeln!(writer, "  Genome: {}", genome.name)?;
+    writeln!(writer, "    Contigs: {}", genome.num_contigs)?;
+    if !genome.contig_lengths.is_empty() {
+        let mut lengths = genome
+            .contig_lengths
+            .iter()
+            .take(MAX_PRINTED_CONTIGS)
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+        if genome.contig_lengths.len() > MAX_PRINTED_CONTIGS {
+            lengths += &format!(",... ({} more)", genome.contig_lengths.len() - MAX_PRINTED_CONTIGS);
+        }
+        writeln!(writer, "    Contig lengths: {}", lengths)?;
+    }
+    writeln!(writer, "    N50: {}", opt_to_string(&genome.n50))?;
+    writeln!(writer, "    Total sequence length: {}", genome.total_sequence_length)?;
+    if genome.masked_length > 0 {
+        writeln!(writer, "    Masked bases: {}", genome.masked_length)?;
+    }
+    writeln!(writer, "    Ambiguous (non-ACGT) bases: {}", genome.ambiguous_bases)?;
+    writeln!(writer, "    Seeds: {}", opt_to_string(&genome.num_seeds))?;
+    if let Some(cutoff) = genome.repetitive_seed_cutoff {
+        writeln!(
+            writer,
+            "    Repetitive seed cutoff: {} ({} seeds masked)",
+            cutoff,
+            opt_to_string(&genome.masked_repetitive_seeds)
+        )?;
+    }
+    writeln!(writer, "    Marker seeds: {}", genome.num_marker_seeds)?;
+    writeln!(
+        writer,
+        "    Compression factor: {}",
+        opt_to_string(&genome.compression_factor.map(|x| format!("{:.2}", x)))
+    )?;
+    writeln!(
+        writer,
+        "    Marker compression factor: {}",
+        opt_to_string(&genome.marker_compression_factor.map(|x| format!("{:.2}", x)))
+    )?;
+    Ok(())
+}
+
+pub fn write_report(writer: &mut impl Write, report: &InspectReport) -> io::Result<()> {
+    for file in report.files.iter() {
+        writeln!(writer, "File: {}", file.file)?;
+        writeln!(writer, "  Type: {}", file.kind)?;
+        writeln!(
+            writer,
+            "  Format version: {} (skani {})",
+            file.format_version, file.skani_version
+        )?;
+        writeln!(
+            writer,
+            "  Parameters: c = {}, k = {}, marker_c = {}, AAI = {}",
+            file.c, file.k, file.marker_c, file.amino_acid
+        )?;
+        if let Some(syncmers) = &file.syncmers {
+            writeln!(writer, "  Syncmers: {}", syncmers)?;
+        }
+        if let Some(seed_pattern) = &file.seed_pattern {
+            let weight = seed_pattern.chars().filter(|x| *x == '1').count();
+            writeln!(writer, "  Seed pattern: {} (weight {})", seed_pattern, weight)?;
+        }
+        if file.mask_lowercase {
+            writeln!(writer, "  Soft-masked (lowercase) bases are skipped")?;
+        }
+        if let Some(level) = file.dust_level {
+            writeln!(writer, "  Low-complexity masking: DUST level {}", level)?;
+        }
+        if file.kind == "markers" {
+            writeln!(writer, "  Genomes: {}", file.num_genomes)?;
+        }
+        for genome in file.genomes.iter() {
+            write_genome(writer, genome)?;
+        }
+    }
+    if report.files.len() > 1 {
+        if report.consistent_params {
+            writeln!(writer, "All files have the same sketch parameters.")?;
+        } else {
+            writeln!(writer, "WARNING: files have different sketch parameters and can not be compared with each other.")?;
+        }
+    }
+    Ok(())
+}
+
+pub fn inspect(command_params: CommandParams) -> Result<(), SkaniError> {
+    let report = inspect_paths(&command_params.ref_files)?;
+    if !report.consistent_params {
+        warn!("Inspected files have different sketch parameters.");
+    }
+    let mut writer: Box<dyn Write>;
+    if command_params.out_file_name.is_empty() {
+        writer = Box::new(BufWriter::new(io::stdout()));
+    } else {
+        let file = File::create(&command_params.out_file_name)
+            .map_err(|e| SkaniError::FileOpen(command_params.out_file_name.clone(), e))?;
+        writer = Box::new(BufWriter::new(file));
+    }
+    if command_params.json_out {
+        serde_json::to_writer_pretty(&mut writer, &report)
+            .map_err(|e| SkaniError::Io(e.into()))?;
+        writeln!(writer)?;
+    } else {
+        write_report(&mut writer, &report)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
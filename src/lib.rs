@@ -1,16 +1,26 @@
 pub mod types;
+pub mod error;
 pub mod params;
+pub mod cache;
 pub mod chain;
+pub mod db;
 pub mod file_io;
+pub mod format;
+pub mod inspect;
+pub mod manage;
+pub mod marker_index;
+pub mod reads;
 pub mod seeding;
 pub mod screen;
 pub mod search;
+pub mod sink;
 pub mod sketch;
 pub mod dist;
 pub mod triangle;
 pub mod cmd_line;
 pub mod model;
 pub mod regression;
+pub mod api;
 
 #[cfg(target_arch = "x86_64")]
 pub mod avx2_seeding;
@@ -1,10 +1,14 @@
 use clap::{AppSettings, Arg, ArgGroup, Command, SubCommand};
 use std::env;
 use skani::dist;
+use skani::inspect;
+use skani::manage;
 use skani::cmd_line::*;
+use skani::error::SkaniError;
 use skani::params;
 use skani::parse;
 use skani::search;
+use skani::sink;
 use skani::sketch;
 use skani::triangle;
 
@@ -40,7 +44,7 @@ fn main() {
                 .arg(
                     Arg::new("fasta_files")
                         .index(1)
-                        .help("fastas to sketch.")
+                        .help("fastas to sketch. - reads a fasta from stdin.")
                         .takes_value(true)
                         .multiple(true)
                 )
@@ -50,11 +54,45 @@ fn main() {
                         .help("File with each line containing one fasta/sketch file.")
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::new(GROUPS)
+                        .long(CMD_GROUPS)
+                        .help(H_GROUPS)
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::new("individual contig")
                         .short('i')
                         .help("Use individual sequences instead the entire file for multi-fastas. CURRENTLY DOES NOT WORK WITH `skani search`.")
                 )
+                .arg(
+                    Arg::new(SPLIT_REGEX)
+                        .long(CMD_SPLIT_REGEX)
+                        .help(H_SPLIT_REGEX)
+                        .takes_value(true)
+                        .conflicts_with(SPLIT_SEP),
+                )
+                .arg(
+                    Arg::new(SPLIT_SEP)
+                        .long(CMD_SPLIT_SEP)
+                        .help(H_SPLIT_SEP)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new(PACKED)
+                        .long(CMD_PACKED)
+                        .help(H_PACKED)
+                )
+                .arg(
+                    Arg::new(APPEND)
+                        .long(CMD_APPEND)
+                        .help(H_APPEND)
+                )
+                .arg(
+                    Arg::new(MARKER_INDEX)
+                        .long(CMD_MARKER_INDEX)
+                        .help(H_MARKER_INDEX)
+                )
                 .arg(Arg::new("output sketch folder").short('o').help("Output folder where sketch files are placed. Creates a folder if it does not exist, and overwrites the contents in folder if it does.").takes_value(true).required(true).display_order(1))
                 .help_heading("PRESETS")
                 .arg(
@@ -96,6 +134,44 @@ fn main() {
                         .help(H_C_FACTOR)
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::new(SYNCMERS)
+                        .long(CMD_SYNCMERS)
+                        .help(H_SYNCMERS)
+                        .takes_value(true)
+                        .possible_values(["open", "closed"]),
+                )
+                .arg(
+                    Arg::new(SYNC_S)
+                        .long(CMD_SYNC_S)
+                        .help(H_SYNC_S)
+                        .takes_value(true)
+                        .requires(SYNCMERS),
+                )
+                .arg(
+                    Arg::new(SEED_PATTERN)
+                        .long(CMD_SEED_PATTERN)
+                        .help(H_SEED_PATTERN)
+                        .takes_value(true)
+                        .conflicts_with_all(&["k", SYNCMERS]),
+                )
+                .arg(
+                    Arg::new(MASK_LOWERCASE)
+                        .long(CMD_MASK_LOWERCASE)
+                        .help(H_MASK_LOWERCASE),
+                )
+                .arg(
+                    Arg::new(DUST)
+                        .long(CMD_DUST)
+                        .help(H_DUST),
+                )
+                .arg(
+                    Arg::new(DUST_LEVEL)
+                        .long(CMD_DUST_LEVEL)
+                        .help(H_DUST_LEVEL)
+                        .takes_value(true)
+                        .requires(DUST),
+                )
 
                 .arg(
                     Arg::new(MARKER_C)
@@ -107,6 +183,7 @@ fn main() {
                     ArgGroup::new("ref")
                         .arg("fasta_files")
                         .arg("fasta_list")
+                        .arg(GROUPS)
                         .required(true),
                 )
                 
@@ -136,7 +213,7 @@ fn main() {
                 .arg(
                     Arg::new("query")
                         .index(1)
-                        .help("Query fasta or sketch.")
+                        .help("Query fasta or sketch. - reads the fasta from stdin.")
                         .takes_value(true)
                 )
                 .arg(
@@ -149,7 +226,7 @@ fn main() {
                 .arg(
                     Arg::new("queries")
                         .short('q')
-                        .help("Query fasta(s) or sketch(es)")
+                        .help("Query fasta(s) or sketch(es). - reads a fasta from stdin.")
                         .takes_value(true)
                         .multiple(true)
                 )
@@ -172,6 +249,18 @@ fn main() {
                         .help("File with each line containing one fasta/sketch file.")
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::new(REF_GROUPS)
+                        .long(CMD_REF_GROUPS)
+                        .help(H_REF_GROUPS)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new(QUERY_GROUPS)
+                        .long(CMD_QUERY_GROUPS)
+                        .help(H_QUERY_GROUPS)
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::new(IND_CTG_QRY)
                         .long(CMD_IND_CTG_QRY)
@@ -182,6 +271,32 @@ fn main() {
                         .long(CMD_IND_CTG_REF)
                         .help(H_IND_CTG_REF)
                 )
+                .arg(
+                    Arg::new(READS_Q)
+                        .long(CMD_READS_Q)
+                        .help(H_READS_Q)
+                        .conflicts_with_all(&[IND_CTG_QRY, SPLIT_REGEX, SPLIT_SEP, "aai"]),
+                )
+                .arg(
+                    Arg::new(MIN_KMER_COUNT)
+                        .long(CMD_MIN_KMER_COUNT)
+                        .help(H_MIN_KMER_COUNT)
+                        .takes_value(true)
+                        .requires(READS_Q),
+                )
+                .arg(
+                    Arg::new(SPLIT_REGEX)
+                        .long(CMD_SPLIT_REGEX)
+                        .help(H_SPLIT_REGEX)
+                        .takes_value(true)
+                        .conflicts_with(SPLIT_SEP),
+                )
+                .arg(
+                    Arg::new(SPLIT_SEP)
+                        .long(CMD_SPLIT_SEP)
+                        .help(H_SPLIT_SEP)
+                        .takes_value(true),
+                )
                 .help_heading("OUTPUT")
                 .arg(
                     Arg::new("output")
@@ -266,12 +381,51 @@ fn main() {
                         .help(H_C_FACTOR)
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::new(SYNCMERS)
+                        .long(CMD_SYNCMERS)
+                        .help(H_SYNCMERS)
+                        .takes_value(true)
+                        .possible_values(["open", "closed"]),
+                )
+                .arg(
+                    Arg::new(SYNC_S)
+                        .long(CMD_SYNC_S)
+                        .help(H_SYNC_S)
+                        .takes_value(true)
+                        .requires(SYNCMERS),
+                )
+                .arg(
+                    Arg::new(SEED_PATTERN)
+                        .long(CMD_SEED_PATTERN)
+                        .help(H_SEED_PATTERN)
+                        .takes_value(true)
+                        .conflicts_with_all(&["k", SYNCMERS]),
+                )
+                .arg(
+                    Arg::new(MASK_LOWERCASE)
+                        .long(CMD_MASK_LOWERCASE)
+                        .help(H_MASK_LOWERCASE),
+                )
+                .arg(
+                    Arg::new(DUST)
+                        .long(CMD_DUST)
+                        .help(H_DUST),
+                )
+                .arg(
+                    Arg::new(DUST_LEVEL)
+                        .long(CMD_DUST_LEVEL)
+                        .help(H_DUST_LEVEL)
+                        .takes_value(true)
+                        .requires(DUST),
+                )
 
                 .group(
                     ArgGroup::new("ref")
                         .arg("reference")
                         .arg("references")
                         .arg("reference list file")
+                        .arg(REF_GROUPS)
 //                        .required(true)
                 )
                 .group(
@@ -279,6 +433,7 @@ fn main() {
                         .arg("query")
                         .arg("queries")
                         .arg("query list file")
+                        .arg(QUERY_GROUPS)
                         .required(true),
                 )
                 .arg(Arg::new("s").short('s').takes_value(true).help(H_SCREEN))
@@ -323,6 +478,12 @@ fn main() {
                         .help("File with each line containing one fasta/sketch file.")
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::new(GROUPS)
+                        .long(CMD_GROUPS)
+                        .help(H_GROUPS)
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::new("aai")
                         .short('a')
@@ -342,6 +503,19 @@ fn main() {
                         .short('i')
                         .help("Use individual sequences instead the entire file for multi-fastas.")
                 )
+                .arg(
+                    Arg::new(SPLIT_REGEX)
+                        .long(CMD_SPLIT_REGEX)
+                        .help(H_SPLIT_REGEX)
+                        .takes_value(true)
+                        .conflicts_with(SPLIT_SEP),
+                )
+                .arg(
+                    Arg::new(SPLIT_SEP)
+                        .long(CMD_SPLIT_SEP)
+                        .help(H_SPLIT_SEP)
+                        .takes_value(true),
+                )
                 .help_heading("OUTPUT")
                 .arg(
                     Arg::new("output")
@@ -443,10 +617,49 @@ fn main() {
                         .help(H_C_FACTOR)
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::new(SYNCMERS)
+                        .long(CMD_SYNCMERS)
+                        .help(H_SYNCMERS)
+                        .takes_value(true)
+                        .possible_values(["open", "closed"]),
+                )
+                .arg(
+                    Arg::new(SYNC_S)
+                        .long(CMD_SYNC_S)
+                        .help(H_SYNC_S)
+                        .takes_value(true)
+                        .requires(SYNCMERS),
+                )
+                .arg(
+                    Arg::new(SEED_PATTERN)
+                        .long(CMD_SEED_PATTERN)
+                        .help(H_SEED_PATTERN)
+                        .takes_value(true)
+                        .conflicts_with_all(&["k", SYNCMERS]),
+                )
+                .arg(
+                    Arg::new(MASK_LOWERCASE)
+                        .long(CMD_MASK_LOWERCASE)
+                        .help(H_MASK_LOWERCASE),
+                )
+                .arg(
+                    Arg::new(DUST)
+                        .long(CMD_DUST)
+                        .help(H_DUST),
+                )
+                .arg(
+                    Arg::new(DUST_LEVEL)
+                        .long(CMD_DUST_LEVEL)
+                        .help(H_DUST_LEVEL)
+                        .takes_value(true)
+                        .requires(DUST),
+                )
                 .group(
                     ArgGroup::new("ref")
                         .arg("fasta_files")
                         .arg("fasta_list")
+                        .arg(GROUPS)
                         .required(true),
                 )
                 .arg(
@@ -489,14 +702,14 @@ fn main() {
                 .arg(
                     Arg::new("query")
                         .index(1)
-                        .help("Query fasta(s) or sketch(es).")
+                        .help("Query fasta(s) or sketch(es). - reads a fasta from stdin.")
                         .multiple(true)
                         .takes_value(true),
                 )
                 .arg(
                     Arg::new("queries")
                         .short('q')
-                        .help("Query fasta(s) or sketch(es)")
+                        .help("Query fasta(s) or sketch(es). - reads a fasta from stdin.")
                         .takes_value(true)
                         .multiple(true),
                 )
@@ -511,6 +724,19 @@ fn main() {
                         .long(CMD_IND_CTG_QRY)
                         .help(H_IND_CTG_QRY)
                 )
+                .arg(
+                    Arg::new(SPLIT_REGEX)
+                        .long(CMD_SPLIT_REGEX)
+                        .help(H_SPLIT_REGEX)
+                        .takes_value(true)
+                        .conflicts_with(SPLIT_SEP),
+                )
+                .arg(
+                    Arg::new(SPLIT_SEP)
+                        .long(CMD_SPLIT_SEP)
+                        .help(H_SPLIT_SEP)
+                        .takes_value(true),
+                )
                 .help_heading("OUTPUT")
                 .arg(
                     Arg::new("output")
@@ -562,6 +788,12 @@ fn main() {
                         .long(CMD_KEEP_REFS)
                         .help(H_KEEP_REFS),
                 )
+                .arg(
+                    Arg::new(CACHE_MEM)
+                        .long(CMD_CACHE_MEM)
+                        .help(H_CACHE_MEM)
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::new(NO_FULL_INDEX)
                         .long(CMD_NO_FULL_INDEX)
@@ -583,9 +815,180 @@ fn main() {
                 .arg(Arg::new("trace").long("trace").help("Trace level verbosity."))
 
         )
+        .subcommand(
+            SubCommand::with_name(params::INSPECT_STRING)
+            .about("Describe sketch files, markers.bin files or sketch folders.\nUsage: skani inspect sketch_folder genome.fa.sketch ...")
+                .arg(
+                    Arg::new("t")
+                        .short('t')
+                        .default_value("3")
+                        .help("Number of threads.")
+                        .takes_value(true),
+                )
+                .help_heading("INPUT/OUTPUT")
+                .arg(
+                    Arg::new("sketches")
+                        .index(1)
+                        .help(".sketch files, markers.bin files or folders created by `skani sketch`.")
+                        .takes_value(true)
+                        .multiple(true)
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .help("Output file name; rewrites file by default\t[default: output to stdout]")
+                        .takes_value(true)
+                        .display_order(1)
+                )
+                .arg(
+                    Arg::new(JSON_OUT)
+                        .long(CMD_JSON_OUT)
+                        .help(H_JSON_OUT),
+                )
+                .help_heading("MISC")
+                .arg(Arg::new("v").short('v').long("debug").help("Debug level verbosity."))
+                .arg(Arg::new("trace").long("trace").help("Trace level verbosity."))
+        )
+        .subcommand(
+            SubCommand::with_name(params::MERGE_STRING)
+            .about("Merge sketch folders with the same sketch parameters into a new folder.\nUsage: skani merge database1 database2 ... -o merged_database")
+                .help_heading("INPUT/OUTPUT")
+                .arg(
+                    Arg::new("sketch folders")
+                        .index(1)
+                        .help("Folders created by `skani sketch`.")
+                        .takes_value(true)
+                        .multiple(true)
+                        .required(true)
+                )
+                .arg(Arg::new("output sketch folder").short('o').help("Output folder; must not exist.").takes_value(true).required(true).display_order(1))
+                .arg(
+                    Arg::new(PACKED)
+                        .long(CMD_PACKED)
+                        .help(H_PACKED)
+                )
+                .arg(
+                    Arg::new(MARKER_INDEX)
+                        .long(CMD_MARKER_INDEX)
+                        .help(H_MARKER_INDEX)
+                )
+                .help_heading("MISC")
+                .arg(Arg::new("v").short('v').long("debug").help("Debug level verbosity."))
+                .arg(Arg::new("trace").long("trace").help("Trace level verbosity."))
+        )
+        .subcommand(
+            SubCommand::with_name(params::SUBSET_STRING)
+            .about("Copy the listed genomes of a sketch folder into a new folder.\nUsage: skani subset database -l genome_names.txt -o subset_database")
+                .help_heading("INPUT/OUTPUT")
+                .arg(
+                    Arg::new("sketch folders")
+                        .index(1)
+                        .help("Folder created by `skani sketch`.")
+                        .takes_value(true)
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("names")
+                        .index(2)
+                        .help("Genome names: original file names, base names or .sketch names.")
+                        .takes_value(true)
+                        .multiple(true)
+                )
+                .arg(
+                    Arg::new("name list")
+                        .short('l')
+                        .help("File with each line containing one genome name.")
+                        .takes_value(true),
+                )
+                .arg(Arg::new("output sketch folder").short('o').help("Output folder; must not exist.").takes_value(true).required(true).display_order(1))
+                .arg(
+                    Arg::new(PACKED)
+                        .long(CMD_PACKED)
+                        .help(H_PACKED)
+                )
+                .arg(
+                    Arg::new(MARKER_INDEX)
+                        .long(CMD_MARKER_INDEX)
+                        .help(H_MARKER_INDEX)
+                )
+                .help_heading("MISC")
+                .arg(Arg::new("v").short('v').long("debug").help("Debug level verbosity."))
+                .arg(Arg::new("trace").long("trace").help("Trace level verbosity."))
+        )
+        .subcommand(
+            SubCommand::with_name(params::REMOVE_STRING)
+            .about("Remove genomes from a sketch folder in place.\nUsage: skani remove database genome1.fa genome2.fa ...")
+                .help_heading("INPUT")
+                .arg(
+                    Arg::new("sketch folders")
+                        .index(1)
+                        .help("Folder created by `skani sketch`.")
+                        .takes_value(true)
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("names")
+                        .index(2)
+                        .help("Genome names: original file names, base names or .sketch names.")
+                        .takes_value(true)
+                        .multiple(true)
+                )
+                .arg(
+                    Arg::new("name list")
+                        .short('l')
+                        .help("File with each line containing one genome name.")
+                        .takes_value(true),
+                )
+                .help_heading("MISC")
+                .arg(Arg::new("v").short('v').long("debug").help("Debug level verbosity."))
+                .arg(Arg::new("trace").long("trace").help("Trace level verbosity."))
+        )
+        .subcommand(
+            SubCommand::with_name(params::RESKETCH_STRING)
+            .about("Downsample a sketch folder or .sketch file to a larger c and/or marker c without the original fastas.\nUsage: skani resketch database -c 200 -o database_c200")
+                .help_heading("INPUT/OUTPUT")
+                .arg(
+                    Arg::new("sketch folders")
+                        .index(1)
+                        .help("Folder created by `skani sketch`, or a .sketch file.")
+                        .takes_value(true)
+                        .required(true)
+                )
+                .arg(Arg::new("output sketch folder").short('o').help("Output folder, or output file for a .sketch file; must not exist.").takes_value(true).required(true).display_order(1))
+                .arg(
+                    Arg::new(PACKED)
+                        .long(CMD_PACKED)
+                        .help(H_PACKED)
+                )
+                .arg(
+                    Arg::new(MARKER_INDEX)
+                        .long(CMD_MARKER_INDEX)
+                        .help(H_MARKER_INDEX)
+                )
+                .help_heading("SKETCH PARAMETERS")
+                .arg(
+                    Arg::new("c")
+                        .short('c')
+                        .help(H_RESKETCH_C)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new(MARKER_C)
+                        .short(CMD_MARKER_C)
+                        .help(H_RESKETCH_MARKER_C)
+                        .takes_value(true),
+                )
+                .help_heading("MISC")
+                .arg(Arg::new("v").short('v').long("debug").help("Debug level verbosity."))
+                .arg(Arg::new("trace").long("trace").help("Trace level verbosity."))
+        )
         .get_matches();
 
-    let (sketch_params, command_params) = parse::parse_params(&matches);
+    let (sketch_params, command_params) = match parse::parse_params(&matches) {
+        Ok(params) => params,
+        Err(e) => exit_with_error(e),
+    };
 
     let cmd_txt = env::args().into_iter().collect::<Vec<String>>().join(" ");
     let log_str = &cmd_txt[0..usize::min(cmd_txt.len(), 250)];
@@ -596,13 +999,27 @@ fn main() {
         log::info!("{}", log_str);
     }
 
+    let res;
+    let mut result_sink = sink::sink_from_params(&command_params);
     if command_params.mode == params::Mode::Sketch {
-        sketch::sketch(command_params, sketch_params);
+        res = sketch::sketch(command_params, sketch_params);
     } else if command_params.mode == params::Mode::Search {
-        search::search(command_params);
+        res = search::search(command_params, &mut result_sink);
     } else if command_params.mode == params::Mode::Dist {
-        dist::dist(command_params, sketch_params);
+        res = dist::dist(command_params, sketch_params, &mut result_sink);
     } else if command_params.mode == params::Mode::Triangle {
-        triangle::triangle(command_params, sketch_params);
+        res = triangle::triangle(command_params, sketch_params, &mut result_sink);
+    } else if command_params.mode == params::Mode::Inspect {
+        res = inspect::inspect(command_params);
+    } else {
+        res = manage::manage(command_params, sketch_params);
     }
+    if let Err(e) = res {
+        exit_with_error(e);
+    }
+}
+
+fn exit_with_error(e: SkaniError) -> ! {
+    log::error!("{}. Exiting.", e);
+    std::process::exit(1)
 }
@@ -0,0 +1,366 @@
+use crate::db;
+use crate::db::{DbWriter, Manifest, SketchDb};
+use crate::error::SkaniError;
+use crate::file_io;
+use crate::format;
+use crate::marker_index;
+use crate::seeding;
+use crate::params::*;
+use crate::types::*;
+use fxhash::{FxHashMap, FxHashSet};
+use log::*;
+use std::path::Path;
+use std::time::Instant;
+
+//Set operations on sketch databases. Sketches are copied as encoded bytes, so
+//nothing is re-sketched or re-encoded, except by resketch. A genome is the pair of its marker
+//sketch in markers.bin and its full sketch, stored under the name the
+//database's manifest gives `marker.file_name`. Sketches are renamed in the
+//new database if needed so that names stay unique.
+
+struct OpenDb {
+    folder: String,
+    sketch_params: SketchParams,
+    markers: Vec<Sketch>,
+    sketch_db: SketchDb,
+    manifest: Manifest,
+}
+
+fn open_db(folder: &str) -> Result<OpenDb, SkaniError> {
+    let marker_file = Path::new(folder).join(db::MARKER_FILE);
+    if !marker_file.exists() {
+        return Err(SkaniError::NoInputs(format!(
+            "{} not found. Ensure that {} was generated by `skani sketch`",
+            marker_file.to_string_lossy(),
+            folder
+        )));
+    }
+    let (sketch_params, markers) =
+        file_io::marker_sketches_from_marker_file(&marker_file.to_string_lossy())?;
+    let sketch_db = SketchDb::open(Path::new(folder))?;
+    let manifest = Manifest::open(Path::new(folder), &markers)?;
+    let names: FxHashSet<String> = sketch_db.names()?.into_iter().collect();
+    for marker in markers.iter() {
+        if !names.contains(&manifest.sketch_name(&marker.file_name)) {
+            return Err(SkaniError::CorruptSketch(format!(
+                "{} (no sketch for {}; folders sketched with -i are not supported)",
+                folder, marker.file_name
+            )));
+        }
+    }
+    Ok(OpenDb {
+        folder: folder.to_string(),
+        sketch_params,
+        markers,
+        sketch_db,
+        manifest,
+    })
+}
+
+///For each marker sketch, whether it is one of `names`. A name matches a genome
+///by its original file name or its sketch name in the manifest, with or
+///without the .sketch extension (e.g. genome.fa or genome.fa.sketch). Names
+///that match nothing are logged.
+fn select(markers: &[Sketch], manifest: &Manifest, names: &[String]) -> Vec<bool> {
+    let mut keys: FxHashMap<String, usize> = FxHashMap::default();
+    for (i, name) in names.iter().enumerate() {
+        keys.insert(name.clone(), i);
+        keys.insert(format!("{}.sketch", name), i);
+    }
+    let mut found = vec![false; names.len()];
+    let selected = markers
+        .iter()
+        .map(|marker| {
+            let hit = keys
+                .get(&marker.file_name)
+                .or_else(|| keys.get(&manifest.sketch_name(&marker.file_name)));
+            if let Some(i) = hit {
+                found[*i] = true;
+            }
+            hit.is_some()
+        })
+        .collect();
+    for (i, name) in names.iter().enumerate() {
+        if !found[i] {
+            warn!("{} not found in the database", name);
+        }
+    }
+    selected
+}
+
+///Copies the genomes of `source` for which `keep` is true.
+fn copy_genomes(
+    source: &OpenDb,
+    keep: &[bool],
+    writer: &mut DbWriter,
+    markers: &mut Vec<Sketch>,
+) -> Result<(), SkaniError> {
+    for (marker, keep) in source.markers.iter().zip(keep.iter()) {
+        if *keep {
+            let name = source.manifest.sketch_name(&marker.file_name);
+            let bytes = source.sketch_db.load_bytes(&name)?;
+            writer.add_bytes(&marker.file_name, &bytes)?;
+            markers.push(marker.clone());
+        }
+    }
+    Ok(())
+}
+
+///Merges sketch folders into a new folder `out_folder`. All folders must have the same
+///sketch parameters. If a genome is in several folders, the first one is kept.
+///Returns the number of genomes in the merged database.
+pub fn merge(
+    folders: &[String],
+    out_folder: &str,
+    packed: bool,
+    with_marker_index: bool,
+) -> Result<usize, SkaniError> {
+    if folders.is_empty() {
+        return Err(SkaniError::NoInputs("No sketch folders to merge.".to_string()));
+    }
+    let dbs = folders
+        .iter()
+        .map(|x| open_db(x))
+        .collect::<Result<Vec<OpenDb>, SkaniError>>()?;
+    for other in dbs[1..].iter() {
+        if other.sketch_params != dbs[0].sketch_params {
+            return Err(SkaniError::SketchParamMismatch(format!(
+                "{} (c = {}, k = {}, marker_c = {}) and {} (c = {}, k = {}, marker_c = {}) can not be merged",
+                dbs[0].folder,
+                dbs[0].sketch_params.c,
+                dbs[0].sketch_params.k,
+                dbs[0].sketch_params.marker_c,
+                other.folder,
+                other.sketch_params.c,
+                other.sketch_params.k,
+                other.sketch_params.marker_c
+            )));
+        }
+    }
+
+    let mut writer = DbWriter::create(Path::new(out_folder), &dbs[0].sketch_params, packed)?;
+    let mut markers = vec![];
+    let mut seen: FxHashSet<String> = FxHashSet::default();
+    for source in dbs.iter() {
+        let keep = source
+            .markers
+            .iter()
+            .map(|marker| {
+                let new = seen.insert(marker.file_name.clone());
+                if !new {
+                    warn!(
+                        "{} from {} is already in the merged database; skipping",
+                        marker.file_name, source.folder
+                    );
+                }
+                new
+            })
+            .collect::<Vec<bool>>();
+        copy_genomes(source, &keep, &mut writer, &mut markers)?;
+    }
+    let markers_header = writer.finish(&markers)?;
+    if with_marker_index {
+        marker_index::write_for_folder(Path::new(out_folder), &markers_header, &markers)?;
+    }
+    Ok(markers.len())
+}
+
+///Writes the genomes of `folder` listed in `names` to a new folder `out_folder`.
+///Returns the number of genomes in the new database.
+pub fn subset(
+    folder: &str,
+    names: &[String],
+    out_folder: &str,
+    packed: bool,
+    with_marker_index: bool,
+) -> Result<usize, SkaniError> {
+    let source = open_db(folder)?;
+    let keep = select(&source.markers, &source.manifest, names);
+    let mut writer = DbWriter::create(Path::new(out_folder), &source.sketch_params, packed)?;
+    let mut markers = vec![];
+    copy_genomes(&source, &keep, &mut writer, &mut markers)?;
+    let markers_header = writer.finish(&markers)?;
+    if with_marker_index {
+        marker_index::write_for_folder(Path::new(out_folder), &markers_header, &markers)?;
+    }
+    Ok(markers.len())
+}
+
+///Removes the genomes listed in `names` from `folder` in place and rewrites its
+///markers.bin and marker index. A packed database is rewritten without the removed sketches.
+///Returns the number of genomes removed.
+pub fn remove(folder: &str, names: &[String]) -> Result<usize, SkaniError> {
+    let source = open_db(folder)?;
+    let selected = select(&source.markers, &source.manifest, names);
+    let num_removed = selected.iter().filter(|x| **x).count();
+    if num_removed == 0 {
+        return Ok(0);
+    }
+    let keep = selected.iter().map(|x| !x).collect::<Vec<bool>>();
+    let folder_path = Path::new(folder);
+
+    if source.sketch_db.is_packed() {
+        //The new database is built in a temporary folder and moved over the old files.
+        let tmp_folder = folder_path.join("skani_remove.tmp");
+        let mut writer = DbWriter::create(&tmp_folder, &source.sketch_params, true)?;
+        let mut markers = vec![];
+        copy_genomes(&source, &keep, &mut writer, &mut markers)?;
+        let markers_header = writer.finish(&markers)?;
+        drop(source);
+        for file in [
+            db::PACKED_DATA_FILE,
+            db::PACKED_INDEX_FILE,
+            db::MANIFEST_FILE,
+            db::MARKER_FILE,
+        ] {
+            std::fs::rename(tmp_folder.join(file), folder_path.join(file))?;
+        }
+        std::fs::remove_dir(&tmp_folder)?;
+        marker_index::update_for_folder(folder_path, &markers_header, &markers)?;
+    } else {
+        //markers.bin is rewritten first; if interrupted, unused .sketch files are left behind.
+        let markers = source
+            .markers
+            .iter()
+            .zip(keep.iter())
+            .filter(|(_, keep)| **keep)
+            .map(|(marker, _)| marker.clone())
+            .collect::<Vec<Sketch>>();
+        let mut manifest = source.manifest.clone();
+        let kept_files = markers
+            .iter()
+            .map(|x| x.file_name.as_str())
+            .collect::<FxHashSet<&str>>();
+        manifest.retain(|x| kept_files.contains(x));
+        manifest.write(folder_path)?;
+        let marker_file = folder_path.join(db::MARKER_FILE);
+        let tmp_file = folder_path.join(format!("{}.tmp", db::MARKER_FILE));
+        let markers_header =
+            format::write_marker_file(&tmp_file.to_string_lossy(), &source.sketch_params, &markers)?;
+        std::fs::rename(&tmp_file, &marker_file)?;
+        marker_index::update_for_folder(folder_path, &markers_header, &markers)?;
+        for (marker, keep) in source.markers.iter().zip(keep.iter()) {
+            if !*keep {
+                let sketch_file = folder_path.join(source.manifest.sketch_name(&marker.file_name));
+                std::fs::remove_file(&sketch_file)
+                    .map_err(|e| SkaniError::FileOpen(sketch_file.to_string_lossy().to_string(), e))?;
+            }
+        }
+    }
+    Ok(num_removed)
+}
+
+///Writes the sketches of the folder `input` downsampled to `c` and `marker_c`
+///(see `SketchParams::downsampled`) to a new folder `out_folder`. If `input`
+///is a .sketch file, the downsampled sketch is written to the file
+///`out_folder` instead. A `c` or `marker_c` of 0 keeps the current value.
+///Returns the number of genomes written.
+pub fn resketch(
+    input: &str,
+    c: usize,
+    marker_c: usize,
+    out_folder: &str,
+    packed: bool,
+    with_marker_index: bool,
+) -> Result<usize, SkaniError> {
+    let target_params = |sketch_params: &SketchParams| {
+        let c = if c == 0 { sketch_params.c } else { c };
+        let marker_c = if marker_c == 0 { usize::max(sketch_params.marker_c, c) } else { marker_c };
+        sketch_params.downsampled(c, marker_c)
+    };
+    if !Path::new(input).is_dir() {
+        let (header, mut sketch) = format::read_sketch_file(input)?;
+        let sketch_params = target_params(&header.sketch_params)?;
+        if Path::new(out_folder).exists() {
+            return Err(SkaniError::InvalidParameter(format!(
+                "output file {} exists; output must not be an existing file",
+                out_folder
+            )));
+        }
+        seeding::downsample_sketch(&mut sketch, &sketch_params);
+        format::write_sketch_file(out_folder, &sketch_params, &sketch)?;
+        return Ok(1);
+    }
+
+    let source = open_db(input)?;
+    let sketch_params = target_params(&source.sketch_params)?;
+    let mut writer = DbWriter::create(Path::new(out_folder), &sketch_params, packed)?;
+    let mut markers = vec![];
+    for marker in source.markers.iter() {
+        let (_header, mut sketch) = source.sketch_db.load(&source.manifest.sketch_name(&marker.file_name))?;
+        seeding::downsample_sketch(&mut sketch, &sketch_params);
+        writer.add_bytes(&marker.file_name, &format::encode_sketch(&sketch_params, &sketch)?)?;
+        markers.push(Sketch::get_markers_only(&sketch));
+        if markers.len() % 1000 == 0 {
+            info!("{} sketches resketched.", markers.len());
+        }
+    }
+    let markers_header = writer.finish(&markers)?;
+    if with_marker_index {
+        marker_index::write_for_folder(Path::new(out_folder), &markers_header, &markers)?;
+    }
+    Ok(markers.len())
+}
+
+///Runs a database operation. `sketch_params` only gives the new c and marker_c
+///for resketch.
+pub fn manage(command_params: CommandParams, sketch_params: SketchParams) -> Result<(), SkaniError> {
+    let now = Instant::now();
+    match command_params.mode {
+        Mode::Merge => {
+            let num = merge(
+                &command_params.ref_files,
+                &command_params.out_file_name,
+                command_params.packed,
+                command_params.marker_index,
+            )?;
+            info!(
+                "Merged {} folders into {} with {} genomes",
+                command_params.ref_files.len(),
+                command_params.out_file_name,
+                num
+            );
+        }
+        Mode::Subset => {
+            let num = subset(
+                &command_params.ref_files[0],
+                &command_params.query_files,
+                &command_params.out_file_name,
+                command_params.packed,
+                command_params.marker_index,
+            )?;
+            info!(
+                "{} genomes written to {}",
+                num, command_params.out_file_name
+            );
+        }
+        Mode::Remove => {
+            let num = remove(&command_params.ref_files[0], &command_params.query_files)?;
+            info!(
+                "{} genomes removed from {}",
+                num, command_params.ref_files[0]
+            );
+        }
+        Mode::Resketch => {
+            let num = resketch(
+                &command_params.ref_files[0],
+                sketch_params.c,
+                sketch_params.marker_c,
+                &command_params.out_file_name,
+                command_params.packed,
+                command_params.marker_index,
+            )?;
+            info!(
+                "{} sketches written to {}",
+                num, command_params.out_file_name
+            );
+        }
+        _ => {
+            return Err(SkaniError::InvalidParameter(
+                "not a database operation".to_string(),
+            ))
+        }
+    }
+    info!("Time: {}", now.elapsed().as_secs_f32());
+    Ok(())
+}
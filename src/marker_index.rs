@@ -0,0 +1,207 @@
+use crate::error::SkaniError;
+use crate::format;
+use crate::format::{FileHeader, FileKind};
+use crate::params::*;
+use crate::screen::MarkerIndex;
+use crate::types::*;
+use log::*;
+use memmap2::Mmap;
+use rayon::prelude::*;
+use std::fs::File;
+use std::path::Path;
+
+//The marker inverted index of a sketch database, markers.index, holds the same
+//information as `screen::kmer_to_sketch_from_refs` over the markers in
+//markers.bin, but laid out so that it can be memory-mapped and queried without
+//building a hash table. The payload of the container is
+//
+//  number of k-mers n (u64) | number of ids m (u64) | sorted k-mers (n x u64) | offsets (n+1 x u64) | sketch ids (m x u32)
+//
+//all little endian. The sketch ids containing k-mer i are ids[offsets[i]..offsets[i+1]].
+
+pub const MARKER_INDEX_FILE: &str = "markers.index";
+
+enum IndexBytes {
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+impl IndexBytes {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            IndexBytes::Mapped(mmap) => mmap,
+            IndexBytes::Owned(bytes) => bytes,
+        }
+    }
+}
+
+pub struct StoredMarkerIndex {
+    bytes: IndexBytes,
+    num_kmers: usize,
+    keys_start: usize,
+    offsets_start: usize,
+    ids_start: usize,
+}
+
+fn read_u64(bytes: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(bytes[pos..pos + 8].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap())
+}
+
+///Builds the index payload from the marker sketches of a database.
+fn encode_payload(marker_sketches: &[Sketch]) -> Vec<u8> {
+    let mut pairs: Vec<(MarkerBits, u32)> = marker_sketches
+        .iter()
+        .enumerate()
+        .flat_map(|(i, sketch)| sketch.marker_seeds.iter().map(move |kmer| (*kmer, i as u32)))
+        .collect();
+    pairs.par_sort_unstable();
+
+    let mut keys: Vec<MarkerBits> = vec![];
+    let mut offsets: Vec<u64> = vec![];
+    for (j, (kmer, _)) in pairs.iter().enumerate() {
+        if keys.last() != Some(kmer) {
+            keys.push(*kmer);
+            offsets.push(j as u64);
+        }
+    }
+    offsets.push(pairs.len() as u64);
+
+    let mut payload = Vec::with_capacity(16 + keys.len() * 16 + 8 + pairs.len() * 4);
+    payload.extend_from_slice(&(keys.len() as u64).to_le_bytes());
+    payload.extend_from_slice(&(pairs.len() as u64).to_le_bytes());
+    for kmer in keys.iter() {
+        payload.extend_from_slice(&kmer.to_le_bytes());
+    }
+    for offset in offsets.iter() {
+        payload.extend_from_slice(&offset.to_le_bytes());
+    }
+    for (_, id) in pairs.iter() {
+        payload.extend_from_slice(&id.to_le_bytes());
+    }
+    payload
+}
+
+///Writes the marker index for `marker_sketches`, which were just written to a
+///markers.bin file with header `markers_header`.
+pub fn write_marker_index(
+    file_name: &str,
+    markers_header: &FileHeader,
+    marker_sketches: &[Sketch],
+) -> Result<(), SkaniError> {
+    let payload = encode_payload(marker_sketches);
+    let header = FileHeader {
+        format_version: format::FORMAT_VERSION,
+        skani_version: VERSION.to_string(),
+        sketch_params: markers_header.sketch_params.clone(),
+        num_sketches: marker_sketches.len(),
+        payload_len: 0,
+        checksum: 0,
+        markers_checksum: Some(markers_header.checksum),
+    };
+    format::write_raw_container(file_name, FileKind::MarkerIndex, header, &payload)
+}
+
+///Writes markers.index into `folder`.
+pub fn write_for_folder(
+    folder: &Path,
+    markers_header: &FileHeader,
+    marker_sketches: &[Sketch],
+) -> Result<(), SkaniError> {
+    let now = std::time::Instant::now();
+    let file_name = folder.join(MARKER_INDEX_FILE).to_string_lossy().to_string();
+    write_marker_index(&file_name, markers_header, marker_sketches)?;
+    info!("Marker index written to {} in {}s", file_name, now.elapsed().as_secs_f32());
+    Ok(())
+}
+
+///Rebuilds markers.index in `folder` if the folder has one, after its markers.bin was rewritten.
+pub fn update_for_folder(
+    folder: &Path,
+    markers_header: &FileHeader,
+    marker_sketches: &[Sketch],
+) -> Result<(), SkaniError> {
+    if folder.join(MARKER_INDEX_FILE).exists() {
+        write_for_folder(folder, markers_header, marker_sketches)?;
+    }
+    Ok(())
+}
+
+impl StoredMarkerIndex {
+    ///Opens a marker index, memory-mapping it if possible and reading it into memory otherwise.
+    pub fn open(file_name: &str) -> Result<(FileHeader, StoredMarkerIndex), SkaniError> {
+        let file = File::open(file_name).map_err(|e| SkaniError::FileOpen(file_name.to_string(), e))?;
+        //Safety: the index is only read. Modifying it while skani runs is not supported.
+        let bytes = match unsafe { Mmap::map(&file) } {
+            Ok(mmap) => IndexBytes::Mapped(mmap),
+            Err(e) => {
+                debug!("Could not memory-map {}: {}; reading it instead", file_name, e);
+                IndexBytes::Owned(
+                    std::fs::read(file_name).map_err(|e| SkaniError::FileOpen(file_name.to_string(), e))?,
+                )
+            }
+        };
+        let corrupt = || SkaniError::CorruptSketch(file_name.to_string());
+        let (header, start) = format::read_raw_header(file_name, FileKind::MarkerIndex, bytes.as_slice())?;
+        let data = bytes.as_slice();
+        if data.len() < start + 16 {
+            return Err(corrupt());
+        }
+        let num_kmers = read_u64(data, start) as usize;
+        let num_ids = read_u64(data, start + 8) as usize;
+        let keys_start = start + 16;
+        let offsets_start = keys_start + num_kmers * 8;
+        let ids_start = offsets_start + (num_kmers + 1) * 8;
+        if data.len() != ids_start + num_ids * 4 {
+            return Err(corrupt());
+        }
+        Ok((
+            header,
+            StoredMarkerIndex {
+                bytes,
+                num_kmers,
+                keys_start,
+                offsets_start,
+                ids_start,
+            },
+        ))
+    }
+
+    pub fn num_kmers(&self) -> usize {
+        self.num_kmers
+    }
+
+    fn find(&self, kmer: MarkerBits) -> Option<usize> {
+        let data = self.bytes.as_slice();
+        let mut lo = 0;
+        let mut hi = self.num_kmers;
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let key = read_u64(data, self.keys_start + mid * 8);
+            if key < kmer {
+                lo = mid + 1;
+            } else if key > kmer {
+                hi = mid;
+            } else {
+                return Some(mid);
+            }
+        }
+        None
+    }
+}
+
+impl MarkerIndex for StoredMarkerIndex {
+    fn for_each_ref(&self, kmer: &MarkerBits, f: &mut dyn FnMut(u32)) {
+        if let Some(i) = self.find(*kmer) {
+            let data = self.bytes.as_slice();
+            let start = read_u64(data, self.offsets_start + i * 8) as usize;
+            let end = read_u64(data, self.offsets_start + (i + 1) * 8) as usize;
+            for j in start..end {
+                f(read_u32(data, self.ids_start + j * 4));
+            }
+        }
+    }
+}
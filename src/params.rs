@@ -1,5 +1,7 @@
+use crate::error::SkaniError;
 use crate::types::*;
 use gbdt::gradient_boost::GBDT;
+use regex::Regex;
 
 pub const VERSION: &str = "0.2.2";
 
@@ -16,6 +18,19 @@ pub const DEFAULT_C: &str = "125";
 pub const DEFAULT_C_AAI: &str = "15";
 pub const DEFAULT_K: &str = "15";
 pub const DEFAULT_K_AAI: &str = "6";
+//Number of s-mers in a k-mer for the default syncmer s-mer length.
+pub const D_SYNC_WINDOW: usize = 5;
+//Window length and default score level for DUST low-complexity masking.
+//Homopolymers score ~31 and dinucleotide repeats ~15 in a 64 bp window;
+//random sequence scores below 1.
+pub const D_DUST_WINDOW: usize = 64;
+pub const D_DUST_LEVEL: f64 = 10.;
+pub const D_MIN_KMER_COUNT: &str = "2";
+//Reads are seeded in batches of this many reads.
+pub const READ_BATCH_SIZE: usize = 100_000;
+//Containment of a reference in reads is only corrected for coverage if at
+//least this many of its single-copy seeds are shared.
+pub const READS_MIN_COVERAGE_SEEDS: usize = 20;
 pub const D_MAX_GAP_LENGTH: f64 = 300.;
 pub const D_MAX_GAP_LENGTH_AAI: f64 = 50.;
 pub const D_MAX_LIN_LENGTH: f64 = 5000.;
@@ -37,6 +52,11 @@ pub const SEARCH_STRING: &str = "search";
 pub const DIST_STRING: &str = "dist";
 pub const SKETCH_STRING: &str = "sketch";
 pub const TRIANGLE_STRING: &str = "triangle";
+pub const INSPECT_STRING: &str = "inspect";
+pub const MERGE_STRING: &str = "merge";
+pub const SUBSET_STRING: &str = "subset";
+pub const REMOVE_STRING: &str = "remove";
+pub const RESKETCH_STRING: &str = "resketch";
 pub const CHUNK_SIZE_DNA: usize = 20000;
 pub const CHUNK_SIZE_AA: usize = 20000;
 pub const MIN_LENGTH_CONTIG: usize = 500;
@@ -49,6 +69,9 @@ pub const SEARCH_ANI_CUTOFF_DEFAULT: f64 = 0.80;
 pub const SCREEN_MINIMUM_KMERS: usize = 20;
 pub const FULL_INDEX_THRESH: usize = 50;
 pub const REPET_KMER_THRESHOLD: usize = 8_000_000;
+///Contigs longer than this are split into windows seeded on separate threads.
+pub const SEED_WINDOW_LENGTH: usize = 1_000_000;
+pub const SEED_BATCH_LENGTH: usize = 64 * SEED_WINDOW_LENGTH;
 pub const OVERLAP_ORTHOLOGOUS_FRACTION: f32  = 0.50;
 pub const TOTAL_BASES_REGRESS_CUTOFF: usize = 150000;
 pub const LEARNED_INFO_HELP: &str = "Learned ANI mode detected. ANI may be adjusted according to a regression model trained on MAGs.";
@@ -69,6 +92,11 @@ pub enum Mode {
     Dist,
     Triangle,
     Search,
+    Inspect,
+    Merge,
+    Subset,
+    Remove,
+    Resketch,
 }
 
 #[derive(Default)]
@@ -88,7 +116,51 @@ pub struct MapParams<'a> {
     pub median: bool,
     pub bp_chain_band: usize,
     pub min_length_cover: usize,
-    pub model: Option<&'a GBDT>
+    pub model: Option<&'a GBDT>,
+    ///Bases per seed, used instead of k in the ANI formula; smaller than k for spaced seeds.
+    pub seed_weight: usize,
+}
+
+///Fasta files sketched together as one genome, which is reported as `name`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GenomeGroup {
+    pub name: String,
+    pub files: Vec<String>,
+}
+
+///Groups the records of a multi-genome fasta into genomes by their ID, the
+///first word of the header.
+#[derive(Clone, Debug)]
+pub enum GenomeSplit {
+    ///The genome is the first capture group of the regex, or the whole match if it has none.
+    Regex(Regex),
+    ///The genome is the part of the ID before the last occurrence of the separator.
+    Separator(String),
+}
+
+impl GenomeSplit {
+    ///The genome of a record with ID `id`, or None if the ID does not match.
+    pub fn genome_name<'a>(&self, id: &'a str) -> Option<&'a str> {
+        match self {
+            GenomeSplit::Regex(regex) => regex
+                .captures(id)
+                .map(|captures| captures.get(1).or_else(|| captures.get(0)).unwrap().as_str()),
+            GenomeSplit::Separator(separator) => match id.rfind(separator.as_str()) {
+                Some(i) if i > 0 => Some(&id[..i]),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl PartialEq for GenomeSplit {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (GenomeSplit::Regex(x), GenomeSplit::Regex(y)) => x.as_str() == y.as_str(),
+            (GenomeSplit::Separator(x), GenomeSplit::Separator(y)) => x == y,
+            _ => false,
+        }
+    }
 }
 
 #[derive(PartialEq)]
@@ -111,11 +183,36 @@ pub struct CommandParams{
     pub individual_contig_r: bool,
     pub min_aligned_frac: f64,
     pub keep_refs: bool,
+    ///Memory budget in bytes for reference sketches cached by search. 0 disables the cache.
+    pub cache_mem: usize,
     pub est_ci: bool,
     pub learned_ani: bool,
     pub detailed_out: bool,
     pub distance: bool,
     pub rescue_small: bool,
+    pub threads: usize,
+    pub json_out: bool,
+    pub packed: bool,
+    pub append: bool,
+    pub marker_index: bool,
+    ///Genomes from a grouping table. When not empty, used instead of `ref_files`/`query_files`.
+    pub ref_groups: Vec<GenomeGroup>,
+    pub query_groups: Vec<GenomeGroup>,
+    ///Splits multi-genome fastas into genomes (--split-regex, --split-sep).
+    pub genome_split: Option<GenomeSplit>,
+    ///Queries are read sets, compared to references by seed containment (--reads).
+    pub reads_q: bool,
+    ///Seeds seen fewer times than this in a read set are discarded.
+    pub min_kmer_count: u32,
+}
+
+impl CommandParams {
+    ///Builds a thread pool with `threads` threads for a single run. 0 lets rayon decide.
+    pub fn thread_pool(&self) -> Result<rayon::ThreadPool, SkaniError> {
+        Ok(rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()?)
+    }
 }
 
 pub fn fragment_length_formula(_n: usize, aa: bool) -> usize {
@@ -129,7 +226,8 @@ pub fn fragment_length_formula(_n: usize, aa: bool) -> usize {
 //    return (n as f64).sqrt() as usize * 3;
 }
 
-#[derive(Default,  PartialEq, Serialize, Deserialize, Debug)]
+#[derive(Default, Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(default)]
 pub struct SketchParams {
     pub c: usize,
     pub k: usize,
@@ -139,10 +237,22 @@ pub struct SketchParams {
     pub acgt_to_aa_encoding: Vec<MarkerBits>,
     pub acgt_to_aa_letters: Vec<u8>,
     pub orf_size: usize,
+    ///Seeds are stored with 64-bit keys because they do not fit SeedBits (k > 16 for DNA, k > 6 for AAI).
+    pub use_seed64: bool,
+    ///With `use_syncs`, the s-mer length and whether syncmers are closed instead of open.
+    pub sync_s: usize,
+    pub closed_syncs: bool,
+    ///Spaced seed pattern such as "1101101...11"; k is its length and only the
+    ///bases at 1s are part of a seed. Empty for contiguous seeds.
+    pub seed_pattern: String,
+    ///Seeds and markers overlapping soft-masked (lowercase) bases are skipped.
+    pub mask_lowercase: bool,
+    ///Seeds and markers in windows with a DUST score above this level are skipped.
+    pub dust_level: Option<f64>,
 }
 
 impl SketchParams {
-    pub fn new(marker_c: usize, c: usize, k: usize, use_syncs: bool, use_aa: bool) -> SketchParams {
+    pub fn new(marker_c: usize, c: usize, k: usize, use_syncs: bool, use_aa: bool) -> Result<SketchParams, SkaniError> {
         let mut acgt_to_aa_encoding = vec![0;64];
                 let letter_to_int_aa: FxHashMap<u8, MarkerBits> = [
             (b'A', 0),
@@ -177,9 +287,22 @@ impl SketchParams {
         let orf_size = ORF_SIZE;
         let marker_c = marker_c;
         if c > marker_c{
-            panic!("We currently don't allow c ({}) > m ({}). -m should be larger than c.", c,  marker_c);
+            return Err(SkaniError::InvalidParameter(format!("We currently don't allow c ({}) > m ({}). -m should be larger than c.", c,  marker_c)));
+        }
+        if c == 0 || k == 0{
+            return Err(SkaniError::InvalidParameter(format!("c ({}) and k ({}) must be positive.", c, k)));
+        }
+        //Seeds are the last k letters of a marker k-mer.
+        let max_k = if use_aa { K_MARKER_AA } else { K_MARKER_DNA };
+        if k > max_k{
+            return Err(SkaniError::InvalidParameter(format!("k ({}) must be at most {}.", k, max_k)));
+        }
+        if use_syncs && use_aa{
+            return Err(SkaniError::InvalidParameter("Syncmers are not supported for AAI.".to_string()));
         }
-        SketchParams {
+        let bits_per_letter = if use_aa { 5 } else { 2 };
+        let use_seed64 = bits_per_letter * k > 8 * std::mem::size_of::<SeedBits>();
+        Ok(SketchParams {
             c,
             k,
             marker_c,
@@ -188,6 +311,142 @@ impl SketchParams {
             acgt_to_aa_encoding,
             acgt_to_aa_letters: DNA_TO_AA.to_vec(),
             orf_size,
+            use_seed64,
+            sync_s: if use_syncs { k.saturating_sub(D_SYNC_WINDOW - 1).max(1) } else { 0 },
+            closed_syncs: false,
+            seed_pattern: String::new(),
+            mask_lowercase: false,
+            dust_level: None,
+        })
+    }
+
+    ///Skips seeds and markers that overlap soft-masked (lowercase) bases.
+    pub fn set_mask_lowercase(&mut self, mask_lowercase: bool) -> Result<(), SkaniError> {
+        if mask_lowercase && self.use_aa {
+            return Err(SkaniError::InvalidParameter("Soft-masking is not supported for AAI.".to_string()));
+        }
+        self.mask_lowercase = mask_lowercase;
+        Ok(())
+    }
+
+    ///Skips seeds and markers in low-complexity windows whose DUST score is
+    ///above `level`. `None` turns masking off.
+    pub fn set_dust_level(&mut self, level: Option<f64>) -> Result<(), SkaniError> {
+        if let Some(level) = level {
+            if self.use_aa {
+                return Err(SkaniError::InvalidParameter("Low-complexity masking is not supported for AAI.".to_string()));
+            }
+            if !level.is_finite() || level <= 0. {
+                return Err(SkaniError::InvalidParameter(format!("DUST level ({}) must be positive.", level)));
+            }
+        }
+        self.dust_level = level;
+        Ok(())
+    }
+
+    ///Uses spaced seeds with `pattern`, a string of 0s and 1s that starts and
+    ///ends with 1. k becomes the length of the pattern.
+    pub fn set_seed_pattern(&mut self, pattern: &str) -> Result<(), SkaniError> {
+        if self.use_aa || self.use_syncs {
+            return Err(SkaniError::InvalidParameter("Spaced seeds can not be used with AAI or syncmers.".to_string()));
+        }
+        if pattern.len() > K_MARKER_DNA
+            || !pattern.starts_with('1')
+            || !pattern.ends_with('1')
+            || pattern.chars().any(|x| x != '0' && x != '1')
+        {
+            return Err(SkaniError::InvalidParameter(format!(
+                "Spaced seed pattern {} must be at most {} 0s and 1s long and start and end with 1.",
+                pattern, K_MARKER_DNA
+            )));
+        }
+        self.k = pattern.len();
+        self.use_seed64 = 2 * self.k > 8 * std::mem::size_of::<SeedBits>();
+        self.seed_pattern = if pattern.contains('0') { pattern.to_string() } else { String::new() };
+        Ok(())
+    }
+
+    ///Number of bases in a seed: the number of 1s of a spaced seed pattern, otherwise k.
+    pub fn seed_weight(&self) -> usize {
+        if self.seed_pattern.is_empty() {
+            self.k
+        } else {
+            self.seed_pattern.chars().filter(|x| *x == '1').count()
+        }
+    }
+
+    ///Mask of the bits of a 2-bit encoded k-mer that are part of a seed.
+    pub fn seed_mask(&self) -> u64 {
+        if self.seed_pattern.is_empty() {
+            return u64::MAX >> (64 - 2 * self.k);
+        }
+        self.seed_pattern
+            .bytes()
+            .rev()
+            .enumerate()
+            .filter(|(_, x)| *x == b'1')
+            .fold(0, |mask, (i, _)| mask | (3 << (2 * i)))
+    }
+
+    ///Parameters of the same sketches at a coarser `c` and `marker_c`.
+    ///FracMinHash samples are nested, so sketches can be downsampled to them
+    ///with `seeding::downsample_sketch`.
+    pub fn downsampled(&self, c: usize, marker_c: usize) -> Result<SketchParams, SkaniError> {
+        if self.use_aa {
+            return Err(SkaniError::InvalidParameter("Resketching is not supported for AAI.".to_string()));
+        }
+        if c < self.c || marker_c < self.marker_c {
+            return Err(SkaniError::InvalidParameter(format!(
+                "Sketches with c = {} and m = {} can only be resketched to c >= {} and m >= {}, not c = {} and m = {}.",
+                self.c, self.marker_c, self.c, self.marker_c, c, marker_c
+            )));
+        }
+        if c > marker_c {
+            return Err(SkaniError::InvalidParameter(format!("We currently don't allow c ({}) > m ({}). -m should be larger than c.", c, marker_c)));
+        }
+        let mut sketch_params = self.clone();
+        sketch_params.c = c;
+        sketch_params.marker_c = marker_c;
+        Ok(sketch_params)
+    }
+
+    ///The coarsest c and marker_c of `self` and `other`, if sketches made with
+    ///either can be downsampled to them, i.e. they only differ in c and marker_c.
+    pub fn common_downsampled(&self, other: &SketchParams) -> Option<SketchParams> {
+        let c = usize::max(self.c, other.c);
+        let marker_c = usize::max(self.marker_c, other.marker_c);
+        let common_params = self.downsampled(c, marker_c).ok()?;
+        if other.downsampled(c, marker_c).ok()? != common_params {
+            return None;
+        }
+        Some(common_params)
+    }
+
+    ///Uses syncmers with s-mers of length `s`; open syncmers have their
+    ///smallest s-mer in the middle and closed ones at either end.
+    pub fn set_syncmers(&mut self, s: usize, closed: bool) -> Result<(), SkaniError> {
+        if self.use_aa {
+            return Err(SkaniError::InvalidParameter("Syncmers are not supported for AAI.".to_string()));
+        }
+        if s == 0 || s >= self.k {
+            return Err(SkaniError::InvalidParameter(format!("The syncmer s-mer length ({}) must be between 1 and k - 1 ({}).", s, self.k - 1)));
+        }
+        self.use_syncs = true;
+        self.sync_s = s;
+        self.closed_syncs = closed;
+        Ok(())
+    }
+
+    ///Hash threshold for seeds. Syncmers are a 1/w (open) or 2/w (closed)
+    ///fraction of k-mers, w = k - s + 1, so the threshold is raised by the
+    ///inverse of that fraction to keep about 1/c of the k-mers as seeds.
+    pub fn seed_threshold(&self) -> u64 {
+        if !self.use_syncs {
+            return u64::MAX / self.c as u64;
         }
+        let w = (self.k - self.sync_s + 1) as u128;
+        let syncs_per_window = if self.closed_syncs { 2 } else { 1 };
+        let threshold = u64::MAX as u128 * w / (self.c as u128 * syncs_per_window);
+        threshold.min(u64::MAX as u128) as u64
     }
 }
@@ -1,14 +1,97 @@
 use crate::cmd_line::*;
+use crate::error::SkaniError;
+use crate::file_io::STDIN_PATH;
 use crate::params::*;
 use crate::regression;
 use clap::parser::ArgMatches;
+use fxhash::FxHashMap;
+use regex::Regex;
 use log::LevelFilter;
 use log::*;
 use std::fs;
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
+use std::str::FromStr;
 
-pub fn parse_params(matches: &ArgMatches) -> (SketchParams, CommandParams) {
+fn parse_value<T: FromStr>(value: &str, name: &str) -> Result<T, SkaniError> {
+    value.parse::<T>().map_err(|_| {
+        SkaniError::InvalidParameter(format!("could not parse value '{}' for {}", value, name))
+    })
+}
+
+fn read_file_list(list_file: &str) -> Result<Vec<String>, SkaniError> {
+    let file = File::open(list_file).map_err(|e| SkaniError::FileOpen(list_file.to_string(), e))?;
+    let reader = BufReader::new(file);
+    let mut temp_vec = vec![];
+    for line in reader.lines() {
+        temp_vec.push(line?.trim().to_string());
+    }
+    Ok(temp_vec)
+}
+
+///Reads a grouping table with lines genome_name<TAB>fasta. Groups are in the
+///order their names first appear and files in the order they are listed.
+fn read_genome_groups(groups_file: &str) -> Result<Vec<GenomeGroup>, SkaniError> {
+    let file = File::open(groups_file).map_err(|e| SkaniError::FileOpen(groups_file.to_string(), e))?;
+    let reader = BufReader::new(file);
+    let mut groups: Vec<GenomeGroup> = vec![];
+    let mut group_index: FxHashMap<String, usize> = FxHashMap::default();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (name, path) = match line.split_once('\t') {
+            Some((name, path)) if !name.trim().is_empty() && !path.trim().is_empty() => {
+                (name.trim(), path.trim())
+            }
+            _ => {
+                return Err(SkaniError::InvalidParameter(format!(
+                    "line {} of {} is not of the form genome_name<TAB>fasta",
+                    i + 1,
+                    groups_file
+                )))
+            }
+        };
+        let j = *group_index.entry(name.to_string()).or_insert_with(|| {
+            groups.push(GenomeGroup {
+                name: name.to_string(),
+                files: vec![],
+            });
+            groups.len() - 1
+        });
+        groups[j].files.push(path.to_string());
+    }
+    Ok(groups)
+}
+
+///Standard input can only be read once, so `-` may be given at most once.
+fn check_stdin_once<'a>(files: impl Iterator<Item = &'a String>) -> Result<(), SkaniError> {
+    if files.filter(|x| *x == STDIN_PATH).count() > 1 {
+        return Err(SkaniError::InvalidParameter(
+            "standard input (-) can only be given once".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn parse_genome_split(matches_subc: &ArgMatches) -> Result<Option<GenomeSplit>, SkaniError> {
+    if let Some(pattern) = matches_subc.value_of(SPLIT_REGEX) {
+        let regex = Regex::new(pattern).map_err(|e| {
+            SkaniError::InvalidParameter(format!("--{} {} is not a valid regex: {}", CMD_SPLIT_REGEX, pattern, e))
+        })?;
+        Ok(Some(GenomeSplit::Regex(regex)))
+    } else if let Some(separator) = matches_subc.value_of(SPLIT_SEP) {
+        if separator.is_empty() {
+            return Err(SkaniError::InvalidParameter(format!("--{} must not be empty", CMD_SPLIT_SEP)));
+        }
+        Ok(Some(GenomeSplit::Separator(separator.to_string())))
+    } else {
+        Ok(None)
+    }
+}
+
+pub fn parse_params(matches: &ArgMatches) -> Result<(SketchParams, CommandParams), SkaniError> {
     let mode;
     let matches_subc;
     match matches.subcommand_name() {
@@ -28,19 +111,31 @@ pub fn parse_params(matches: &ArgMatches) -> (SketchParams, CommandParams) {
             mode = Mode::Search;
             matches_subc = matches.subcommand_matches(SEARCH_STRING).unwrap();
         }
+        Some(INSPECT_STRING) => {
+            mode = Mode::Inspect;
+            matches_subc = matches.subcommand_matches(INSPECT_STRING).unwrap();
+        }
+        Some(MERGE_STRING) => {
+            mode = Mode::Merge;
+            matches_subc = matches.subcommand_matches(MERGE_STRING).unwrap();
+        }
+        Some(SUBSET_STRING) => {
+            mode = Mode::Subset;
+            matches_subc = matches.subcommand_matches(SUBSET_STRING).unwrap();
+        }
+        Some(REMOVE_STRING) => {
+            mode = Mode::Remove;
+            matches_subc = matches.subcommand_matches(REMOVE_STRING).unwrap();
+        }
+        Some(RESKETCH_STRING) => {
+            mode = Mode::Resketch;
+            matches_subc = matches.subcommand_matches(RESKETCH_STRING).unwrap();
+        }
         _ => {
-            panic!()
+            return Err(SkaniError::InvalidParameter("no subcommand given".to_string()));
         } // Either no subcommand or one not tested for...
     }
 
-    let threads = matches_subc.value_of("t").unwrap();
-    let threads = threads.parse::<usize>().unwrap();
-
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(threads)
-        .build_global()
-        .unwrap();
-
     simple_logging::log_to_stderr(LevelFilter::Info);
     if matches_subc.is_present("v") {
         simple_logging::log_to_stderr(LevelFilter::Debug);
@@ -49,8 +144,18 @@ pub fn parse_params(matches: &ArgMatches) -> (SketchParams, CommandParams) {
         simple_logging::log_to_stderr(LevelFilter::Trace);
     }
 
+    //Database operations copy files and do not take -t.
+    if mode == Mode::Merge || mode == Mode::Subset || mode == Mode::Remove || mode == Mode::Resketch {
+        return parse_params_manage(matches_subc, mode);
+    }
+
+    let threads = parse_value::<usize>(matches_subc.value_of("t").unwrap_or("3"), "t")?;
+
     if mode == Mode::Search {
-        return parse_params_search(matches_subc);
+        return parse_params_search(matches_subc, threads);
+    }
+    if mode == Mode::Inspect {
+        return parse_params_inspect(matches_subc, threads);
     }
 
     let amino_acid;
@@ -76,6 +181,7 @@ pub fn parse_params(matches: &ArgMatches) -> (SketchParams, CommandParams) {
 
     let mut ref_files: Vec<String>;
     let mut ref_file_list = None;
+    let mut ref_groups = vec![];
     let mut sparse = false;
     if mode == Mode::Triangle {
         sparse = matches_subc.is_present("sparse");
@@ -86,9 +192,11 @@ pub fn parse_params(matches: &ArgMatches) -> (SketchParams, CommandParams) {
         } else if let Some(values) = matches_subc.value_of("fasta_list") {
             ref_files = vec![];
             ref_file_list = Some(values);
+        } else if let Some(values) = matches_subc.value_of(GROUPS) {
+            ref_files = vec![];
+            ref_groups = read_genome_groups(values)?;
         } else {
-            error!("No reference inputs found.");
-            std::process::exit(1);
+            return Err(SkaniError::NoInputs("No reference inputs found.".to_string()));
         }
     } else if mode == Mode::Dist {
         if let Some(values) = matches_subc.values_of("reference") {
@@ -98,81 +206,56 @@ pub fn parse_params(matches: &ArgMatches) -> (SketchParams, CommandParams) {
             ref_file_list = Some(values);
         } else if let Some(values) = matches_subc.values_of("references") {
             ref_files = values.map(|x| x.to_string()).collect();
+        } else if let Some(values) = matches_subc.value_of(REF_GROUPS) {
+            ref_files = vec![];
+            ref_groups = read_genome_groups(values)?;
         } else {
-            error!("No reference inputs found.");
-            std::process::exit(1);
+            return Err(SkaniError::NoInputs("No reference inputs found.".to_string()));
         }
     } else {
         panic!("PATH TODO");
     }
 
-    if ref_file_list.is_some() {
-        let ref_file_list = ref_file_list.unwrap();
-        let file = File::open(ref_file_list).expect("-l specified file could not be opened properly. Make sure this file exists. Exiting.");
-        let reader = BufReader::new(file);
-        let mut temp_vec = vec![];
-
-        for line in reader.lines() {
-            temp_vec.push(line.unwrap().trim().to_string());
-        }
-        ref_files = temp_vec;
+    if let Some(ref_file_list) = ref_file_list {
+        ref_files = read_file_list(ref_file_list)?;
     }
 
     let mut query_files = vec![];
     let mut query_file_list = None;
+    let mut query_groups = vec![];
     let mut max_results = usize::MAX;
 
     if mode == Mode::Dist {
-        max_results = matches_subc
-            .value_of("n")
-            .unwrap_or("1000000000000")
-            .parse::<usize>()
-            .unwrap();
+        max_results = parse_value::<usize>(matches_subc.value_of("n").unwrap_or("1000000000000"), "n")?;
         if let Some(values) = matches_subc.values_of("query") {
             query_files = values.map(|x| x.to_string()).collect();
         } else if let Some(values) = matches_subc.values_of("queries") {
             query_files = values.map(|x| x.to_string()).collect();
         } else if let Some(values) = matches_subc.value_of("query list file") {
             query_file_list = Some(values)
+        } else if let Some(values) = matches_subc.value_of(QUERY_GROUPS) {
+            query_groups = read_genome_groups(values)?;
         }
     }
 
-    if query_file_list.is_some() {
-        let query_file_list = query_file_list.unwrap();
-        let file = File::open(query_file_list).unwrap();
-        let reader = BufReader::new(file);
-        let mut temp_vec = vec![];
-
-        for line in reader.lines() {
-            temp_vec.push(line.unwrap().trim().to_string());
-        }
-        query_files = temp_vec;
+    if let Some(query_file_list) = query_file_list {
+        query_files = read_file_list(query_file_list)?;
     }
 
     let def_k = if amino_acid { DEFAULT_K_AAI } else { DEFAULT_K };
     let def_c = if amino_acid { DEFAULT_C_AAI } else { DEFAULT_C };
-    let k = matches_subc
-        .value_of("k")
-        .unwrap_or(def_k)
-        .parse::<usize>()
-        .unwrap();
-
-    let use_syncs = false;
-    let mut c = matches_subc
-        .value_of("c")
-        .unwrap_or(def_c)
-        .parse::<usize>()
-        .unwrap();
-
-    let mut marker_c = matches_subc
-        .value_of("marker_c")
-        .unwrap_or(MARKER_C_DEFAULT)
-        .parse::<usize>()
-        .unwrap();
+    let k = parse_value::<usize>(matches_subc.value_of("k").unwrap_or(def_k), "k")?;
+
+    let use_syncs = matches_subc.is_present(SYNCMERS);
+    let mut c = parse_value::<usize>(matches_subc.value_of("c").unwrap_or(def_c), "c")?;
+
+    let mut marker_c = parse_value::<usize>(matches_subc.value_of("marker_c").unwrap_or(MARKER_C_DEFAULT), "marker_c")?;
 
     if matches_subc.is_present(MODE_FAST) &&
         matches_subc.is_present(MODE_SLOW){
-            panic!("Both --slow and --fast were set. This is not allowed.");
+            return Err(SkaniError::InvalidParameter(
+                "both --slow and --fast were set. This is not allowed.".to_string(),
+            ));
     }
     if matches_subc.is_present(MODE_FAST){
         if matches_subc.is_present("c"){
@@ -211,11 +294,7 @@ pub fn parse_params(matches: &ArgMatches) -> (SketchParams, CommandParams) {
         } else {
             D_FRAC_COVER_CUTOFF
         };
-        min_aligned_frac = matches_subc
-            .value_of(MIN_ALIGN_FRAC)
-            .unwrap_or(def_maf)
-            .parse::<f64>()
-            .unwrap()
+        min_aligned_frac = parse_value::<f64>(matches_subc.value_of(MIN_ALIGN_FRAC).unwrap_or(def_maf), MIN_ALIGN_FRAC)?
             / 100.;
         est_ci = matches_subc.is_present(CONF_INTERVAL);
         detailed_out = matches_subc.is_present(DETAIL_OUT);
@@ -243,11 +322,7 @@ pub fn parse_params(matches: &ArgMatches) -> (SketchParams, CommandParams) {
     let mut robust = false;
     let mut median = false;
     if mode == Mode::Triangle || mode == Mode::Dist {
-        screen_val = matches_subc
-            .value_of("s")
-            .unwrap_or("0.00")
-            .parse::<f64>()
-            .unwrap()
+        screen_val = parse_value::<f64>(matches_subc.value_of("s").unwrap_or("0.00"), "s")?
             / 100.;
     }
     if mode == Mode::Triangle || mode == Mode::Search || mode == Mode::Dist {
@@ -255,7 +330,25 @@ pub fn parse_params(matches: &ArgMatches) -> (SketchParams, CommandParams) {
         median = matches_subc.is_present("median");
     }
 
-    let sketch_params = SketchParams::new(marker_c, c, k, use_syncs, amino_acid);
+    let mut sketch_params = SketchParams::new(marker_c, c, k, use_syncs, amino_acid)?;
+    if use_syncs {
+        let sync_s = match matches_subc.value_of(SYNC_S) {
+            Some(sync_s) => parse_value::<usize>(sync_s, "sync-s")?,
+            None => sketch_params.sync_s,
+        };
+        sketch_params.set_syncmers(sync_s, matches_subc.value_of(SYNCMERS) == Some("closed"))?;
+    }
+    if let Some(pattern) = matches_subc.value_of(SEED_PATTERN) {
+        sketch_params.set_seed_pattern(pattern)?;
+    }
+    sketch_params.set_mask_lowercase(matches_subc.is_present(MASK_LOWERCASE))?;
+    if matches_subc.is_present(DUST) {
+        let dust_level = match matches_subc.value_of(DUST_LEVEL) {
+            Some(level) => parse_value::<f64>(level, "dust-level")?,
+            None => D_DUST_LEVEL,
+        };
+        sketch_params.set_dust_level(Some(dust_level))?;
+    }
 
     let mut refs_are_sketch = !ref_files.is_empty();
     for ref_file in ref_files.iter() {
@@ -300,6 +393,41 @@ pub fn parse_params(matches: &ArgMatches) -> (SketchParams, CommandParams) {
 
     }
 
+    if (individual_contig_r && !ref_groups.is_empty())
+        || (individual_contig_q && !query_groups.is_empty())
+    {
+        return Err(SkaniError::InvalidParameter(
+            "a grouping table can not be used with individual contigs (-i, --ri, --qi)".to_string(),
+        ));
+    }
+    check_stdin_once(
+        ref_files
+            .iter()
+            .chain(query_files.iter())
+            .chain(ref_groups.iter().chain(query_groups.iter()).flat_map(|x| x.files.iter())),
+    )?;
+    let genome_split = parse_genome_split(matches_subc)?;
+    if genome_split.is_some()
+        && (individual_contig_r || individual_contig_q || !ref_groups.is_empty() || !query_groups.is_empty())
+    {
+        return Err(SkaniError::InvalidParameter(format!(
+            "--{}/--{} can not be used with individual contigs or a grouping table",
+            CMD_SPLIT_REGEX, CMD_SPLIT_SEP
+        )));
+    }
+
+    let reads_q = mode == Mode::Dist && matches_subc.is_present(READS_Q);
+    let mut min_kmer_count = 0;
+    if reads_q {
+        if queries_are_sketch {
+            return Err(SkaniError::InvalidParameter(format!(
+                "--{} queries must be fastq/fasta files, not sketches",
+                CMD_READS_Q
+            )));
+        }
+        min_kmer_count = parse_value::<u32>(matches_subc.value_of(MIN_KMER_COUNT).unwrap_or(D_MIN_KMER_COUNT), "min-count")?;
+    }
+
     let full_matrix;
     let diagonal;
     if mode == Mode::Triangle {
@@ -312,7 +440,7 @@ pub fn parse_params(matches: &ArgMatches) -> (SketchParams, CommandParams) {
 
     let screen;
     if mode == Mode::Dist {
-        if (query_files.len() > FULL_INDEX_THRESH || individual_contig_q) && !matches_subc.is_present(NO_FULL_INDEX) {
+        if (query_files.len() + query_groups.len() > FULL_INDEX_THRESH || individual_contig_q) && !matches_subc.is_present(NO_FULL_INDEX) {
             screen = true;
         } else {
             screen = false;
@@ -334,6 +462,10 @@ pub fn parse_params(matches: &ArgMatches) -> (SketchParams, CommandParams) {
         learned_ani = regression::use_learned_ani(c, individual_contig_q, individual_contig_r, median);
     }
 
+    let packed = mode == Mode::Sketch && matches_subc.is_present(PACKED);
+    let append = mode == Mode::Sketch && matches_subc.is_present(APPEND);
+    let marker_index = mode == Mode::Sketch && matches_subc.is_present(MARKER_INDEX);
+
     let mut distance = false;
     if mode == Mode::Triangle{
         if matches_subc.is_present(DISTANCE_OUT){
@@ -360,27 +492,34 @@ pub fn parse_params(matches: &ArgMatches) -> (SketchParams, CommandParams) {
         individual_contig_r,
         min_aligned_frac,
         keep_refs: false,
+        cache_mem: 0,
         est_ci,
         learned_ani,
         detailed_out,
         distance,
         rescue_small,
+        threads,
+        json_out: false,
+        packed,
+        append,
+        marker_index,
+        ref_groups,
+        query_groups,
+        genome_split,
+        reads_q,
+        min_kmer_count,
     };
 
-    (sketch_params, command_params)
+    Ok((sketch_params, command_params))
 }
 
-pub fn parse_params_search(matches_subc: &ArgMatches) -> (SketchParams, CommandParams) {
+pub fn parse_params_search(matches_subc: &ArgMatches, threads: usize) -> Result<(SketchParams, CommandParams), SkaniError> {
     let mode = Mode::Search;
     let out_file_name = matches_subc.value_of("output").unwrap_or("").to_string();
 
     let mut query_files = vec![];
     let mut query_file_list = None;
-    let max_results = matches_subc
-        .value_of("n")
-        .unwrap_or("10000000")
-        .parse::<usize>()
-        .unwrap();
+    let max_results = parse_value::<usize>(matches_subc.value_of("n").unwrap_or("10000000"), "n")?;
     if let Some(values) = matches_subc.values_of("query") {
         query_files = values.map(|x| x.to_string()).collect();
     } else if let Some(values) = matches_subc.values_of("queries") {
@@ -388,24 +527,16 @@ pub fn parse_params_search(matches_subc: &ArgMatches) -> (SketchParams, CommandP
     } else if let Some(values) = matches_subc.value_of("query list file") {
         query_file_list = Some(values);
     }
-    if query_file_list.is_some() {
-        let query_file_list = query_file_list.unwrap();
-        let file = File::open(query_file_list).unwrap();
-        let reader = BufReader::new(file);
-        let mut temp_vec = vec![];
-
-        for line in reader.lines() {
-            temp_vec.push(line.unwrap().trim().to_string());
-        }
-        query_files = temp_vec;
+    if let Some(query_file_list) = query_file_list {
+        query_files = read_file_list(query_file_list)?;
     }
     let ref_folder = matches_subc.value_of("sketched database folder").unwrap();
-    let paths =
-        fs::read_dir(ref_folder).expect("Issue with folder specified by -d option; exiting");
-    let ref_files = paths
-        .into_iter()
-        .map(|x| x.unwrap().path().to_str().unwrap().to_string())
-        .collect();
+    let paths = fs::read_dir(ref_folder)
+        .map_err(|e| SkaniError::FileOpen(ref_folder.to_string(), e))?;
+    let mut ref_files = vec![];
+    for path in paths {
+        ref_files.push(path?.path().to_string_lossy().to_string());
+    }
     let refs_are_sketch = true;
 
     let mut queries_are_sketch = !query_files.is_empty();
@@ -420,27 +551,37 @@ pub fn parse_params_search(matches_subc: &ArgMatches) -> (SketchParams, CommandP
     let median = matches_subc.is_present("median");
     let sparse = false;
 
-    let screen_val = matches_subc
-        .value_of("s")
-        .unwrap_or("0.00")
-        .parse::<f64>()
-        .unwrap()
+    let screen_val = parse_value::<f64>(matches_subc.value_of("s").unwrap_or("0.00"), "s")?
         / 100.;
     let screen;
+    check_stdin_once(query_files.iter())?;
     let individual_contig_q = matches_subc.is_present("individual contig query");
+    let genome_split = parse_genome_split(matches_subc)?;
+    if genome_split.is_some() && individual_contig_q {
+        return Err(SkaniError::InvalidParameter(format!(
+            "--{}/--{} can not be used with --{}",
+            CMD_SPLIT_REGEX, CMD_SPLIT_SEP, CMD_IND_CTG_QRY
+        )));
+    }
     if (query_files.len() > FULL_INDEX_THRESH || individual_contig_q) && !matches_subc.is_present(NO_FULL_INDEX) {
         screen = true;
     } else {
         screen = false;
     }
 
-    let min_aligned_frac = matches_subc
-        .value_of(MIN_ALIGN_FRAC)
-        .unwrap_or("-100.0")
-        .parse::<f64>()
-        .unwrap()
+    let min_aligned_frac = parse_value::<f64>(matches_subc.value_of(MIN_ALIGN_FRAC).unwrap_or("-100.0"), MIN_ALIGN_FRAC)?
         / 100.;
-    let keep_refs = matches_subc.is_present(KEEP_REFS);
+    let keep_refs = matches_subc.is_present(KEEP_REFS) || matches_subc.is_present(CACHE_MEM);
+    let cache_mem;
+    if keep_refs {
+        let cache_gb = parse_value::<f64>(matches_subc.value_of(CACHE_MEM).unwrap_or(DEFAULT_CACHE_MEM), CACHE_MEM)?;
+        if cache_gb < 0. {
+            return Err(SkaniError::InvalidParameter(format!("--{} must be >= 0", CMD_CACHE_MEM)));
+        }
+        cache_mem = (cache_gb * 1e9) as usize;
+    } else {
+        cache_mem = 0;
+    }
     let est_ci = matches_subc.is_present(CONF_INTERVAL);
     let detailed_out = matches_subc.is_present(DETAIL_OUT);
 
@@ -472,19 +613,164 @@ pub fn parse_params_search(matches_subc: &ArgMatches) -> (SketchParams, CommandP
         individual_contig_r: false,
         min_aligned_frac,
         keep_refs,
+        cache_mem,
         est_ci,
         learned_ani,
         detailed_out,
         distance: false,
-        rescue_small: false
+        rescue_small: false,
+        threads,
+        json_out: false,
+        packed: false,
+        append: false,
+        marker_index: false,
+        ref_groups: vec![],
+        query_groups: vec![],
+        genome_split,
+        reads_q: false,
+        min_kmer_count: 0,
     };
 
     if command_params.ref_files.is_empty() {
-        error!("No valid reference fastas or sketches found.");
-        std::process::exit(1)
+        return Err(SkaniError::NoInputs(
+            "No valid reference fastas or sketches found.".to_string(),
+        ));
+    }
+
+    Ok((SketchParams::default(), command_params))
+}
+
+pub fn parse_params_inspect(matches_subc: &ArgMatches, threads: usize) -> Result<(SketchParams, CommandParams), SkaniError> {
+    let ref_files: Vec<String>;
+    if let Some(values) = matches_subc.values_of("sketches") {
+        ref_files = values.map(|x| x.to_string()).collect();
+    } else {
+        return Err(SkaniError::NoInputs("No sketches or sketch folders given.".to_string()));
+    }
+    let out_file_name = matches_subc.value_of("output").unwrap_or("").to_string();
+
+    let command_params = CommandParams {
+        screen: false,
+        screen_val: 0.,
+        mode: Mode::Inspect,
+        out_file_name,
+        ref_files,
+        query_files: vec![],
+        refs_are_sketch: true,
+        queries_are_sketch: false,
+        robust: false,
+        median: false,
+        sparse: false,
+        full_matrix: false,
+        diagonal: false,
+        max_results: 0,
+        individual_contig_q: false,
+        individual_contig_r: false,
+        min_aligned_frac: 0.,
+        keep_refs: false,
+        cache_mem: 0,
+        est_ci: false,
+        learned_ani: false,
+        detailed_out: false,
+        distance: false,
+        rescue_small: false,
+        threads,
+        json_out: matches_subc.is_present(JSON_OUT),
+        packed: false,
+        append: false,
+        marker_index: false,
+        ref_groups: vec![],
+        query_groups: vec![],
+        genome_split: None,
+        reads_q: false,
+        min_kmer_count: 0,
+    };
+
+    Ok((SketchParams::default(), command_params))
+}
+
+pub fn parse_params_manage(matches_subc: &ArgMatches, mode: Mode) -> Result<(SketchParams, CommandParams), SkaniError> {
+    let ref_files: Vec<String>;
+    if let Some(values) = matches_subc.values_of("sketch folders") {
+        ref_files = values.map(|x| x.to_string()).collect();
+    } else {
+        return Err(SkaniError::NoInputs("No sketch folders given.".to_string()));
+    }
+
+    //Genome names for subset and remove.
+    let mut query_files = vec![];
+    if mode == Mode::Subset || mode == Mode::Remove {
+        if let Some(values) = matches_subc.values_of("names") {
+            query_files = values.map(|x| x.to_string()).collect();
+        }
+        if let Some(list_file) = matches_subc.value_of("name list") {
+            query_files.extend(read_file_list(list_file)?);
+        }
+        query_files.retain(|x| !x.is_empty());
+        if query_files.is_empty() {
+            return Err(SkaniError::NoInputs("No genome names given.".to_string()));
+        }
     }
 
+    let out_file_name;
+    let packed;
+    let marker_index;
+    if mode != Mode::Remove {
+        out_file_name = matches_subc.value_of("output sketch folder").unwrap_or("").to_string();
+        packed = matches_subc.is_present(PACKED);
+        marker_index = matches_subc.is_present(MARKER_INDEX);
+    } else {
+        out_file_name = "".to_string();
+        packed = false;
+        marker_index = false;
+    }
+
+    //The new c and marker_c for resketch; 0 keeps the current value.
+    let mut sketch_params = SketchParams::default();
+    if mode == Mode::Resketch {
+        sketch_params.c = parse_value::<usize>(matches_subc.value_of("c").unwrap_or("0"), "c")?;
+        sketch_params.marker_c = parse_value::<usize>(matches_subc.value_of(MARKER_C).unwrap_or("0"), "marker_c")?;
+        if sketch_params.c == 0 && sketch_params.marker_c == 0 {
+            return Err(SkaniError::InvalidParameter("resketch needs a new -c or -m.".to_string()));
+        }
+    }
 
+    let command_params = CommandParams {
+        screen: false,
+        screen_val: 0.,
+        mode,
+        out_file_name,
+        ref_files,
+        query_files,
+        refs_are_sketch: true,
+        queries_are_sketch: false,
+        robust: false,
+        median: false,
+        sparse: false,
+        full_matrix: false,
+        diagonal: false,
+        max_results: 0,
+        individual_contig_q: false,
+        individual_contig_r: false,
+        min_aligned_frac: 0.,
+        keep_refs: false,
+        cache_mem: 0,
+        est_ci: false,
+        learned_ani: false,
+        detailed_out: false,
+        distance: false,
+        rescue_small: false,
+        threads: 1,
+        json_out: false,
+        packed,
+        append: false,
+        marker_index,
+        ref_groups: vec![],
+        query_groups: vec![],
+        genome_split: None,
+        reads_q: false,
+        min_kmer_count: 0,
+    };
 
-    (SketchParams::default(), command_params)
+    Ok((sketch_params, command_params))
 }
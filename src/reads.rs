@@ -0,0 +1,199 @@
+use crate::error::SkaniError;
+use crate::file_io;
+use crate::params::*;
+use crate::types::*;
+use fxhash::FxHashMap;
+use log::*;
+use rayon::prelude::*;
+
+//Read sets as queries. Reads are seeded like contigs, but only the number of
+//times each sampled seed occurs is kept: positions within reads say nothing
+//about positions in the genome. Seeds with sequencing errors are mostly seen
+//once and are removed by a minimum count. References are compared by the
+//fraction of their seeds found in the reads (containment), corrected for the
+//seeds missed because of low coverage.
+
+///Sampled seeds of a read set and the number of times each was seen.
+pub struct ReadSketch {
+    pub file_name: String,
+    pub seed_counts: FxHashMap<u64, u32>,
+    ///Seeds seen fewer times than this were removed.
+    pub min_count: u32,
+    pub num_reads: usize,
+    pub total_sequence_length: usize,
+}
+
+///Seeds of `reads`, each once per occurrence.
+fn count_read_seeds(reads: &[Vec<u8>], sketch_params: &SketchParams) -> Vec<(u64, u32)> {
+    let mut sketch = Sketch::default();
+    for (i, read) in reads.iter().enumerate() {
+        file_io::seed_contig(read, sketch_params, i as ContigIndex, &mut sketch, true);
+    }
+    match (sketch.kmer_seeds_k, sketch.kmer_seeds_k64) {
+        (_, Some(kmer_seeds)) => kmer_seeds.into_iter().map(|(kmer, positions)| (kmer, positions.len() as u32)).collect(),
+        (Some(kmer_seeds), None) => kmer_seeds.into_iter().map(|(kmer, positions)| (kmer as u64, positions.len() as u32)).collect(),
+        (None, None) => vec![],
+    }
+}
+
+fn add_batch(read_sketch: &mut ReadSketch, batch: &[Vec<u8>], sketch_params: &SketchParams) {
+    let batch_counts = batch
+        .par_chunks(1000)
+        .map(|reads| count_read_seeds(reads, sketch_params))
+        .collect::<Vec<Vec<(u64, u32)>>>();
+    for (kmer, count) in batch_counts.into_iter().flatten() {
+        *read_sketch.seed_counts.entry(kmer).or_insert(0) += count;
+    }
+}
+
+///Counts the sampled seeds of the reads in `group.files` and removes those
+///seen fewer than `min_count` times.
+pub fn reads_to_sketch(
+    group: &GenomeGroup,
+    sketch_params: &SketchParams,
+    min_count: u32,
+) -> Result<ReadSketch, SkaniError> {
+    let mut read_sketch = ReadSketch {
+        file_name: group.name.clone(),
+        seed_counts: FxHashMap::default(),
+        min_count,
+        num_reads: 0,
+        total_sequence_length: 0,
+    };
+    let mut batch = vec![];
+    for file in group.files.iter() {
+        let mut reader = file_io::open_fastx(file).map_err(|_| SkaniError::InvalidFasta(file.to_string()))?;
+        while let Some(record) = reader.next() {
+            let record = record.map_err(|_| SkaniError::InvalidFasta(file.to_string()))?;
+            let seq = record.seq();
+            read_sketch.num_reads += 1;
+            read_sketch.total_sequence_length += seq.len();
+            //Reads shorter than a marker k-mer have no seeds.
+            if seq.len() < K_MARKER_DNA {
+                continue;
+            }
+            batch.push(seq.to_vec());
+            if batch.len() >= READ_BATCH_SIZE {
+                add_batch(&mut read_sketch, &batch, sketch_params);
+                batch.clear();
+            }
+        }
+    }
+    add_batch(&mut read_sketch, &batch, sketch_params);
+    let num_seeds = read_sketch.seed_counts.len();
+    read_sketch.seed_counts.retain(|_, count| *count >= min_count);
+    debug!(
+        "{}: {} reads, {} bases, {} of {} sampled seeds seen at least {} times",
+        read_sketch.file_name,
+        read_sketch.num_reads,
+        read_sketch.total_sequence_length,
+        read_sketch.seed_counts.len(),
+        num_seeds,
+        min_count
+    );
+    Ok(read_sketch)
+}
+
+///P(X >= t) for X ~ Poisson(lambda).
+fn poisson_tail(lambda: f64, t: u32) -> f64 {
+    if t == 0 {
+        return 1.;
+    }
+    let mut term = (-lambda).exp();
+    if t as f64 > lambda {
+        //Terms decrease from t on, so the tail is summed directly.
+        for i in 1..=t {
+            term *= lambda / i as f64;
+        }
+        let mut tail = 0.;
+        let mut i = t;
+        while term > tail * 1e-15 {
+            tail += term;
+            i += 1;
+            term *= lambda / i as f64;
+        }
+        tail
+    } else {
+        let mut head = 0.;
+        for i in 0..t {
+            head += term;
+            term *= lambda / (i + 1) as f64;
+        }
+        (1. - head).max(0.)
+    }
+}
+
+///Poisson mean whose distribution, truncated to counts of at least
+///`min_count`, has the mean of `counts`.
+pub fn estimate_coverage(counts: &[u32], min_count: u32) -> f64 {
+    if counts.is_empty() {
+        return 0.;
+    }
+    let t = min_count.max(1);
+    let mean = counts.iter().map(|x| *x as f64).sum::<f64>() / counts.len() as f64;
+    let truncated_mean = |lambda: f64| lambda * poisson_tail(lambda, t - 1) / poisson_tail(lambda, t);
+    //Truncation only raises the mean, so the solution is at most `mean`.
+    let mut low = 0.;
+    let mut high = mean;
+    for _ in 0..100 {
+        let mid = (low + high) / 2.;
+        if truncated_mean(mid) < mean {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (low + high) / 2.
+}
+
+///Containment ANI of `ref_sketch` in `read_sketch`. The fraction of the
+///reference's seeds seen in the reads is divided by the chance that a seed
+///present at the estimated coverage is seen `min_count` times, and ANI is its
+///1/k-th power. Coverage is estimated from the counts of shared seeds that
+///occur once in the reference; with fewer than `READS_MIN_COVERAGE_SEEDS` of
+///them, the containment is not corrected. Returns None if no seeds are shared.
+pub fn containment_ani(read_sketch: &ReadSketch, ref_sketch: &Sketch) -> Option<AniEstResult> {
+    let ref_seeds: Vec<(u64, usize)> = match (&ref_sketch.kmer_seeds_k, &ref_sketch.kmer_seeds_k64) {
+        (_, Some(kmer_seeds)) => kmer_seeds.iter().map(|(kmer, positions)| (*kmer, positions.len())).collect(),
+        (Some(kmer_seeds), None) => kmer_seeds.iter().map(|(kmer, positions)| (*kmer as u64, positions.len())).collect(),
+        (None, None) => vec![],
+    };
+    let mut shared_counts = vec![];
+    let mut single_copy_counts = vec![];
+    for (kmer, copies) in ref_seeds.iter() {
+        if let Some(count) = read_sketch.seed_counts.get(kmer) {
+            shared_counts.push(*count);
+            if *copies == 1 {
+                single_copy_counts.push(*count);
+            }
+        }
+    }
+    if shared_counts.is_empty() {
+        return None;
+    }
+    let coverage_counts = if single_copy_counts.is_empty() { &shared_counts } else { &single_copy_counts };
+    let coverage = estimate_coverage(coverage_counts, read_sketch.min_count);
+    let detection = poisson_tail(coverage, read_sketch.min_count);
+    let containment = shared_counts.len() as f64 / ref_seeds.len() as f64;
+    //A few shared seeds, e.g. from a mobile element, give no usable coverage.
+    let corrected_containment = if single_copy_counts.len() >= READS_MIN_COVERAGE_SEEDS && detection > 0. {
+        f64::min(1., containment / detection)
+    } else {
+        containment
+    };
+    let k = if ref_sketch.seed_weight == 0 { ref_sketch.k } else { ref_sketch.seed_weight };
+    let ani = corrected_containment.powf(1. / k as f64);
+    Some(AniEstResult {
+        ani: ani as f32,
+        align_fraction_ref: corrected_containment as f32,
+        align_fraction_query: shared_counts.len() as f32 / read_sketch.seed_counts.len() as f32,
+        ref_file: ref_sketch.file_name.clone(),
+        query_file: read_sketch.file_name.clone(),
+        query_contig: read_sketch.file_name.clone(),
+        ref_contig: ref_sketch.contigs[0].clone(),
+        num_contigs_r: ref_sketch.contigs.len() as u32,
+        ambiguous_bases_r: ref_sketch.ambiguous_bases as u32,
+        effective_coverage: coverage as f32,
+        ..Default::default()
+    })
+}
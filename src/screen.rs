@@ -7,6 +7,24 @@ use fxhash::FxHashMap;
 use fxhash::FxHashSet;
 use log::*;
 
+///Inverted index from marker k-mers to the indices of the reference sketches
+///containing them. Implemented by the in-memory `KmerToSketch` and by the
+///stored index of a sketch database, `marker_index::StoredMarkerIndex`.
+pub trait MarkerIndex: Sync {
+    ///Calls `f` with the index of every reference sketch that has `kmer` as a marker, in increasing order.
+    fn for_each_ref(&self, kmer: &MarkerBits, f: &mut dyn FnMut(u32));
+}
+
+impl MarkerIndex for KmerToSketch {
+    fn for_each_ref(&self, kmer: &MarkerBits, f: &mut dyn FnMut(u32)) {
+        if let Some(sketch_ids) = self.get(kmer) {
+            for sketch_id in sketch_ids.iter() {
+                f(*sketch_id);
+            }
+        }
+    }
+}
+
 pub fn check_small_contigs(ref_sketches: &Vec<Sketch>, query_sketches: &Vec<Sketch>){
     let mut num_small_sketches = 0;
     let mut num_large_sketches = 0;
@@ -38,19 +56,17 @@ pub fn check_small_contigs(ref_sketches: &Vec<Sketch>, query_sketches: &Vec<Sket
 //Used in search, but not in dist,triangle
 pub fn screen_refs_filenames<'a>(
     identity: f64,
-    kmer_to_sketch: &KmerToSketch,
+    kmer_to_sketch: &dyn MarkerIndex,
     query_sketch: &Sketch,
     sketch_params: &SketchParams,
     ref_sketches: &'a Vec<Sketch>
 ) -> Vec<&'a String>{
     let mut count_hash_map = FxHashMap::default();
     for marker in query_sketch.marker_seeds.iter() {
-        if kmer_to_sketch.contains_key(marker) {
-            for sketch_id in kmer_to_sketch[marker].iter() {
-                let count = count_hash_map.entry(sketch_id).or_insert(0);
-                *count += 1;
-            }
-        }
+        kmer_to_sketch.for_each_ref(marker, &mut |sketch_id| {
+            let count = count_hash_map.entry(sketch_id).or_insert(0);
+            *count += 1;
+        });
     }
     //Use fixed K value for AA markers, but flexible ones for DNA because saturation less of an
     //issue.
@@ -66,11 +82,11 @@ pub fn screen_refs_filenames<'a>(
         .filter(|x| {
             *x.1 > usize::max((cutoff 
                 * usize::min(
-                    ref_sketches[**x.0 as usize].marker_seeds.len(),
+                    ref_sketches[*x.0 as usize].marker_seeds.len(),
                     query_sketch.marker_seeds.len(),
                 ) as f64) as usize,1)
         })
-        .map(|x| &ref_sketches[**x.0 as usize].file_name)
+        .map(|x| &ref_sketches[*x.0 as usize].file_name)
         .collect();
     ret
 
@@ -147,10 +163,10 @@ pub fn check_markers_quickly(
 ///query genomes with < 20 k-mers have every reference genome passing the filter.
 pub fn screen_refs(
     identity: f64,
-    kmer_to_sketch: &KmerToSketch,
+    kmer_to_sketch: &dyn MarkerIndex,
     query_sketch: &Sketch,
     sketch_params: &SketchParams,
-    ref_sketches: &Vec<Sketch>,
+    ref_sketches: &[Sketch],
     rescue_small: bool,
 ) -> FxHashSet<usize> {
     let mut count_hash_map = FxHashMap::default();
@@ -159,12 +175,10 @@ pub fn screen_refs(
         return (0..ref_sketches.len()).collect();
     }
     for marker in query_sketch.marker_seeds.iter() {
-        if kmer_to_sketch.contains_key(marker) {
-            for sketch_id in kmer_to_sketch[marker].iter() {
-                let count = count_hash_map.entry(sketch_id).or_insert(0);
-                *count += 1;
-            }
-        }
+        kmer_to_sketch.for_each_ref(marker, &mut |sketch_id| {
+            let count = count_hash_map.entry(sketch_id).or_insert(0);
+            *count += 1;
+        });
     }
     //Use fixed K value for AA markers, but flexible ones for DNA because saturation less of an
     //issue.
@@ -179,15 +193,15 @@ pub fn screen_refs(
         .filter(|x| {
             *x.1 > usize::max((cutoff 
                 * usize::min(
-                    ref_sketches[**x.0 as usize].marker_seeds.len(),
+                    ref_sketches[*x.0 as usize].marker_seeds.len(),
                     query_sketch.marker_seeds.len(),
                 ) as f64) as usize,1)
         })
-        .map(|x| **x.0 as usize)
+        .map(|x| *x.0 as usize)
         .collect();
     ret
 }
-pub fn kmer_to_sketch_from_refs(ref_sketches: &Vec<Sketch>) -> KmerToSketch {
+pub fn kmer_to_sketch_from_refs(ref_sketches: &[Sketch]) -> KmerToSketch {
 //    let max_size: usize = ref_sketches.iter().map(|x| x.marker_seeds.len()).sum::<usize>();
     let mut ret = KmerToSketch::default();
     //ret.reserve(max_size);
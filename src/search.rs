@@ -1,22 +1,55 @@
+use crate::cache::RefCache;
 use crate::chain;
+use crate::db;
+use crate::db::{Manifest, SketchDb};
+use crate::error::SkaniError;
 use crate::file_io;
+use crate::format;
+use crate::marker_index::{self, StoredMarkerIndex};
 use crate::params::*;
 use crate::regression;
 use crate::screen;
+use crate::screen::MarkerIndex;
+use crate::sink::ResultSink;
 use crate::types::*;
-use fxhash::FxHashMap;
 use log::*;
 use rayon::prelude::*;
+use rayon::ThreadPool;
 use std::path::Path;
 use std::sync::Mutex;
-use std::sync::RwLock;
 use std::time::Instant;
 
-pub fn search(command_params: CommandParams) {
+///Runs `search_in_pool` in a pool with `command_params.threads` threads.
+pub fn search<S: ResultSink + Send + ?Sized>(
+    command_params: CommandParams,
+    sink: &mut S,
+) -> Result<(), SkaniError> {
+    let pool = command_params.thread_pool()?;
+    search_in_pool(&pool, command_params, sink)
+}
+
+pub fn search_in_pool<S: ResultSink + Send + ?Sized>(
+    pool: &ThreadPool,
+    command_params: CommandParams,
+    sink: &mut S,
+) -> Result<(), SkaniError> {
+    pool.install(|| search_inner(command_params, sink))
+}
+
+fn search_inner<S: ResultSink + Send + ?Sized>(
+    command_params: CommandParams,
+    sink: &mut S,
+) -> Result<(), SkaniError> {
     let now = Instant::now();
     info!("Searching...");
     let mut ref_marker_file = "";
     for file in command_params.ref_files.iter() {
+        if file.ends_with(db::PACKED_DATA_FILE)
+            || file.ends_with(db::PACKED_INDEX_FILE)
+            || file.ends_with(db::MANIFEST_FILE)
+        {
+            continue;
+        }
         if !file.contains(".sketch") && !file.contains("marker") {
             warn!(
                 "{} does not have .sketch as an extension; skipping file",
@@ -29,13 +62,13 @@ pub fn search(command_params: CommandParams) {
 
     if ref_marker_file.is_empty() {
         //error!("No sketch files found in the folder. Sketch files must be generated by `skani sketch` and have the .sketch extension.");
-        error!("markers.bin not found in the folder. Ensure that the folder was generated by `skani sketch`.");
-        std::process::exit(1)
+        return Err(SkaniError::NoInputs(
+            "markers.bin not found in the folder. Ensure that the folder was generated by `skani sketch`.".to_string(),
+        ));
     }
 
-    let ref_sketches;
-    let sketch_params;
-    (sketch_params, ref_sketches) = file_io::marker_sketches_from_marker_file(ref_marker_file);
+    let (markers_header, ref_sketches) = format::read_marker_file(ref_marker_file)?;
+    let sketch_params = markers_header.sketch_params.clone();
     let screen_val;
     if command_params.screen_val == 0. {
         if sketch_params.use_aa {
@@ -47,6 +80,8 @@ pub fn search(command_params: CommandParams) {
         screen_val = command_params.screen_val;
     }
 
+    sink.begin(&sketch_params)?;
+
     let learned_ani = regression::use_learned_ani(
         sketch_params.c,
         command_params.individual_contig_q,
@@ -59,29 +94,49 @@ pub fn search(command_params: CommandParams) {
     }
 
     info!("Loading markers time: {}", now.elapsed().as_secs_f32());
-    let kmer_to_sketch;
+    let folder = Path::new(&ref_marker_file).parent().unwrap();
+    let kmer_to_sketch: Box<dyn MarkerIndex>;
     if command_params.screen {
         let now = Instant::now();
-        info!("Full index option detected; generating marker hash table");
-        kmer_to_sketch = screen::kmer_to_sketch_from_refs(&ref_sketches);
+        let stored_index = load_marker_index(folder, &markers_header)?;
+        if let Some(stored_index) = stored_index {
+            info!(
+                "Full index option detected; using stored marker index with {} k-mers",
+                stored_index.num_kmers()
+            );
+            kmer_to_sketch = Box::new(stored_index);
+        } else {
+            info!("Full index option detected; generating marker hash table");
+            kmer_to_sketch = Box::new(screen::kmer_to_sketch_from_refs(&ref_sketches));
+        }
         info!("Full indexing time: {}", now.elapsed().as_secs_f32());
     } else {
-        kmer_to_sketch = KmerToSketch::default();
+        kmer_to_sketch = Box::new(KmerToSketch::default());
+    }
+    let ref_cache = RefCache::new(command_params.cache_mem);
+    if command_params.cache_mem > 0 {
+        info!(
+            "Keeping up to {:.2} GB of reference sketches in memory",
+            command_params.cache_mem as f64 / 1e9
+        );
     }
-    let ref_sketches_used: RwLock<FxHashMap<_, _>> = RwLock::new(FxHashMap::default());
 
     let now = Instant::now();
     //assert!(ref_sketches.len() == ref_marker_files.len());
     let anis: Mutex<Vec<AniEstResult>> = Mutex::new(vec![]);
     let counter: Mutex<usize> = Mutex::new(0);
-    let first_write: Mutex<bool> = Mutex::new(true);
-    let folder = Path::new(&ref_marker_file).parent().unwrap();
+    let sink = Mutex::new(sink);
+    let sketch_db = SketchDb::open(folder)?;
+    let manifest = Manifest::open(folder, &ref_sketches)?;
+    if sketch_db.is_packed() {
+        info!("Reading reference sketches from packed database {}", db::PACKED_DATA_FILE);
+    }
     for query_file in command_params.query_files.iter() {
         let query_params;
         let query_sketches;
         if command_params.queries_are_sketch {
             (query_params, query_sketches) =
-                file_io::sketches_from_sketch(&vec![query_file.clone()]);
+                file_io::sketches_from_sketch(&vec![query_file.clone()])?;
             if query_params != sketch_params && !query_file.contains("markers.bin") {
                 warn!("Query sketch parameters for {} not equal to reference sketch parameters; no ANI calculated", query_file);
             }
@@ -90,15 +145,22 @@ pub fn search(command_params: CommandParams) {
                 &vec![query_file.clone()],
                 &sketch_params,
                 true,
-            );
+            )?;
+        } else if let Some(split) = &command_params.genome_split {
+            query_sketches = file_io::fastx_to_split_sketches(
+                &vec![query_file.clone()],
+                &sketch_params,
+                split,
+                true,
+            )?;
         } else {
             query_sketches =
-                file_io::fastx_to_sketches(&vec![query_file.clone()], &sketch_params, true);
+                file_io::fastx_to_sketches(&vec![query_file.clone()], &sketch_params, true)?;
         }
 
         if !query_sketches.is_empty() {
             let is = 0..query_sketches.len();
-            is.into_par_iter().for_each(|i| {
+            is.into_par_iter().try_for_each(|i| {
                 let query_sketch = &query_sketches[i];
                 let refs_to_try;
                 if !command_params.screen {
@@ -120,7 +182,7 @@ pub fn search(command_params: CommandParams) {
                 } else {
                     refs_to_try = screen::screen_refs_filenames(
                         screen_val,
-                        &kmer_to_sketch,
+                        kmer_to_sketch.as_ref(),
                         query_sketch,
                         &sketch_params,
                         &ref_sketches,
@@ -128,90 +190,25 @@ pub fn search(command_params: CommandParams) {
                 }
                 debug!("Refs to try {}", refs_to_try.len());
                 let js = 0..refs_to_try.len();
-                js.into_par_iter().for_each(|j| {
+                js.into_par_iter().try_for_each(|j| {
                     let original_file = refs_to_try[j];
-                    let ref_sketch;
-                    if !command_params.keep_refs {
-                        let sketch_file = folder.join(
-                            Path::new(&format!("{}.sketch", original_file))
-                                .file_name()
-                                .unwrap(),
-                        );
-                        let (_sketch_params_ref, ref_sketch_new) =
-                            file_io::sketches_from_sketch(&vec![sketch_file
-                                .to_str()
-                                .unwrap()
-                                .to_string()]);
-                        ref_sketch = ref_sketch_new;
-                        let map_params = chain::map_params_from_sketch(
-                            &ref_sketch[0],
-                            sketch_params.use_aa,
-                            &command_params,
-                            &model_opt,
-                        );
-                        let ani_res;
-                        ani_res = chain::chain_seeds(&ref_sketch[0], query_sketch, map_params);
-                        if ani_res.ani > 0.5 {
-                            let mut locked = anis.lock().unwrap();
-                            locked.push(ani_res);
-                        }
-                    } else {
-                        let mut contains = false;
-                        {
-                            let read_table = ref_sketches_used.read().unwrap();
-                            if read_table.contains_key(original_file) {
-                                contains = true;
-                            }
-                        }
-                        if contains {
-                            let read_table = ref_sketches_used.read().unwrap();
-                            let ref_sketch: &Vec<_> = &read_table[original_file];
-                            let map_params = chain::map_params_from_sketch(
-                                &ref_sketch[0],
-                                sketch_params.use_aa,
-                                &command_params,
-                                &model_opt,
-                            );
-                            let ani_res;
-                            ani_res = chain::chain_seeds(&ref_sketch[0], query_sketch, map_params);
-                            if ani_res.ani > 0.5 {
-                                let mut locked = anis.lock().unwrap();
-                                locked.push(ani_res);
-                            }
-                        } else {
-                            let sketch_file = folder.join(
-                                Path::new(&format!("{}.sketch", original_file))
-                                    .file_name()
-                                    .unwrap(),
-                            );
-                            let (_sketch_params_ref, ref_sketch) =
-                                file_io::sketches_from_sketch(&vec![sketch_file
-                                    .to_str()
-                                    .unwrap()
-                                    .to_string()]);
-
-                            let map_params = chain::map_params_from_sketch(
-                                &ref_sketch[0],
-                                sketch_params.use_aa,
-                                &command_params,
-                                &model_opt,
-                            );
-                            let ani_res;
-                            ani_res = chain::chain_seeds(&ref_sketch[0], query_sketch, map_params);
-                            {
-                                let mut write_table = ref_sketches_used.write().unwrap();
-                                write_table.insert(original_file, ref_sketch);
-                            }
-
-                            if ani_res.ani > 0.5 {
-                                {
-                                    let mut locked = anis.lock().unwrap();
-                                    locked.push(ani_res);
-                                }
-                            }
-                        }
+                    let sketch_name = manifest.sketch_name(original_file);
+                    let ref_sketch = ref_cache.get_or_load(&sketch_name, || {
+                        sketch_db.load(&sketch_name).map(|(_header, sketch)| sketch)
+                    })?;
+                    let map_params = chain::map_params_from_sketch(
+                        &ref_sketch,
+                        sketch_params.use_aa,
+                        &command_params,
+                        &model_opt,
+                    );
+                    let ani_res = chain::chain_seeds(&ref_sketch, query_sketch, map_params);
+                    if ani_res.ani > 0.5 {
+                        let mut locked = anis.lock().unwrap();
+                        locked.push(ani_res);
                     }
-                });
+                    Ok::<(), SkaniError>(())
+                })?;
 
                 let c;
                 {
@@ -231,41 +228,53 @@ pub fn search(command_params: CommandParams) {
                             let mut locked = anis.lock().unwrap();
                             moved_anis = std::mem::take(&mut locked);
                         }
-                        let mut fw = first_write.lock().unwrap();
-                        file_io::write_query_ref_list(
-                            &moved_anis,
-                            &command_params.out_file_name,
-                            command_params.max_results,
-                            sketch_params.use_aa,
-                            command_params.est_ci,
-                            command_params.detailed_out,
-                            !*fw,
-                        );
-                        if *fw == true {
-                            *fw = false;
-                        }
+                        sink.lock().unwrap().push_results(moved_anis)?;
                     }
                 }
-            });
+                Ok::<(), SkaniError>(())
+            })?;
         }
     }
-    if command_params.keep_refs {
+    if command_params.cache_mem > 0 {
+        let stats = ref_cache.stats();
         info!(
-            "{} references kept in memory for --keep-refs",
-            ref_sketches_used.read().unwrap().len()
+            "Reference cache: {} hits, {} misses, {} evictions; {} sketches ({:.2} GB) held at the end",
+            stats.hits,
+            stats.misses,
+            stats.evictions,
+            stats.entries,
+            stats.bytes as f64 / 1e9
         );
     }
 
     let anis = anis.into_inner().unwrap();
 
-    file_io::write_query_ref_list(
-        &anis,
-        &command_params.out_file_name,
-        command_params.max_results,
-        sketch_params.use_aa,
-        command_params.est_ci,
-        command_params.detailed_out,
-        !*first_write.lock().unwrap(),
-    );
+    let sink = sink.into_inner().unwrap();
+    sink.push_results(anis)?;
+    sink.finish()?;
     info!("Searching time: {}", now.elapsed().as_secs_f32());
+    Ok(())
+}
+
+///Opens the stored marker index of the database in `folder` if it has one and
+///it was built from the markers in markers.bin.
+fn load_marker_index(
+    folder: &Path,
+    markers_header: &format::FileHeader,
+) -> Result<Option<StoredMarkerIndex>, SkaniError> {
+    let index_file = folder.join(marker_index::MARKER_INDEX_FILE);
+    if !index_file.exists() {
+        return Ok(None);
+    }
+    let (header, stored_index) = StoredMarkerIndex::open(&index_file.to_string_lossy())?;
+    if header.markers_checksum != Some(markers_header.checksum)
+        || header.sketch_params != markers_header.sketch_params
+    {
+        warn!(
+            "{} was not built from the current markers.bin and is ignored",
+            index_file.to_string_lossy()
+        );
+        return Ok(None);
+    }
+    Ok(Some(stored_index))
 }
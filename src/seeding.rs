@@ -2,6 +2,8 @@ use crate::params::*;
 use crate::types::*;
 use rust_lapper::{Interval, Lapper};
 use smallvec::SmallVec;
+use std::borrow::Cow;
+use std::collections::HashMap;
 
 #[inline]
 fn _position_min<T: Ord>(slice: &[T]) -> Option<usize> {
@@ -121,10 +123,11 @@ pub fn fmh_seeds_aa_with_orf(
     seed: bool,
 ) {
     let marker_k = K_MARKER_AA;
-    if seed && new_sketch.kmer_seeds_k.is_none() {
-        new_sketch.kmer_seeds_k = Some(KmerSeeds::default());
+    if seed {
+        new_sketch.init_seeds(sketch_params);
     }
     let kmer_seeds_k = &mut new_sketch.kmer_seeds_k;
+    let kmer_seeds_k64 = &mut new_sketch.kmer_seeds_k64;
     let k = sketch_params.k;
     let c = sketch_params.c;
     let kmer_to_aa_table = &sketch_params.acgt_to_aa_encoding;
@@ -201,18 +204,17 @@ pub fn fmh_seeds_aa_with_orf(
                     if hash < threshold {
                         if seed {
                             //dbg!(rolling_aa_kmer, rolling_aa_kmer as SeedBits);
-                            let kmer_seeds = &mut kmer_seeds_k.as_mut().unwrap();
-                            let kmer_positions = kmer_seeds
-                                .entry(rolling_aa_kmer as SeedBits)
-                                .or_insert(SmallVec::<[SeedPosition; SMALL_VEC_SIZE]>::new());
-                            //                            .or_insert(vec![]);
-
-                            kmer_positions.push(SeedPosition {
-                                pos: i as GnPosition,
-                                canonical: !rc,
-                                contig_index,
-                                phase,
-                            });
+                            push_seed(
+                                kmer_seeds_k,
+                                kmer_seeds_k64,
+                                rolling_aa_kmer,
+                                SeedPosition {
+                                    pos: i as GnPosition,
+                                    canonical: !rc,
+                                    contig_index,
+                                    phase,
+                                },
+                            );
                         }
                         if hash < marker_threshold && j >= marker_k * 3 - 1 {
                             new_sketch.marker_seeds.insert(marker_rolling_aa_kmer);
@@ -227,6 +229,99 @@ pub fn fmh_seeds_aa_with_orf(
     //kmer_seeds_k].shrink_to_fit();
 }
 
+///Number of bases of `string` that are not A, C, G or T.
+pub fn ambiguous_bases(string: &[u8]) -> usize {
+    string.iter().filter(|x| BYTE_IS_AMBIGUOUS[**x as usize]).count()
+}
+
+///Number of bases of `string` that seeding skips because they are masked.
+pub fn masked_bases(string: &[u8], sketch_params: &SketchParams) -> usize {
+    if !sketch_params.mask_lowercase {
+        return 0;
+    }
+    string.iter().filter(|x| x.is_ascii_lowercase()).count()
+}
+
+///Replaces bases in low-complexity windows of `string` with N so that no
+///seed or marker overlaps them. A window of `D_DUST_WINDOW` bases is
+///low-complexity if its DUST score, the sum of c(c - 1)/2 over the counts c
+///of each triplet divided by the number of triplets minus one, is above
+///`sketch_params.dust_level`. Returns `string` unchanged if masking is off
+///or nothing is masked.
+pub fn mask_low_complexity<'a>(string: &'a [u8], sketch_params: &SketchParams) -> Cow<'a, [u8]> {
+    let level = match sketch_params.dust_level {
+        Some(level) => level,
+        None => return Cow::Borrowed(string),
+    };
+    if string.len() < D_DUST_WINDOW {
+        return Cow::Borrowed(string);
+    }
+    //Triplet ending at base i, or None if it contains an ambiguous base.
+    let triplet = |i: usize| -> Option<usize> {
+        let bases = &string[i - 2..=i];
+        if bases.iter().any(|x| BYTE_IS_AMBIGUOUS[*x as usize]) {
+            return None;
+        }
+        Some(bases.iter().fold(0, |acc, x| acc << 2 | BYTE_TO_SEQ[*x as usize] as usize))
+    };
+    let max_score = level * (D_DUST_WINDOW - 3) as f64;
+    let mut counts = [0_u32; 64];
+    let mut score = 0_u64;
+    let mut masked: Option<Vec<u8>> = None;
+    let mut mask_end = 0;
+    for i in 2..string.len() {
+        if let Some(t) = triplet(i) {
+            score += counts[t] as u64;
+            counts[t] += 1;
+        }
+        if i < D_DUST_WINDOW - 1 {
+            continue;
+        }
+        let start = i + 1 - D_DUST_WINDOW;
+        if score as f64 > max_score {
+            let masked = masked.get_or_insert_with(|| string.to_vec());
+            for x in &mut masked[usize::max(start, mask_end)..=i] {
+                *x = b'N';
+            }
+            mask_end = i + 1;
+        }
+        //Drop the first triplet of the window before the next one is added.
+        if let Some(t) = triplet(start + 2) {
+            counts[t] -= 1;
+            score -= counts[t] as u64;
+        }
+    }
+    match masked {
+        Some(masked) => Cow::Owned(masked),
+        None => Cow::Borrowed(string),
+    }
+}
+
+///Whether a k-mer is a syncmer: the smallest of its k - s + 1 s-mers, by
+///hash, is the middle one for open syncmers or the first or last one for
+///closed syncmers.
+#[inline]
+pub fn is_syncmer(kmer: u64, sketch_params: &SketchParams) -> bool {
+    let k = sketch_params.k;
+    let s = sketch_params.sync_s;
+    let w = k - s + 1;
+    let s_mask = u64::MAX >> (64 - 2 * s);
+    let mut min_hash = u64::MAX;
+    let mut min_pos = 0;
+    for j in 0..w {
+        let hash = mm_hash64((kmer >> (2 * (k - s - j))) & s_mask);
+        if j == 0 || hash < min_hash {
+            min_hash = hash;
+            min_pos = j;
+        }
+    }
+    if sketch_params.closed_syncs {
+        min_pos == 0 || min_pos == w - 1
+    } else {
+        min_pos == (w - 1) / 2
+    }
+}
+
 pub fn fmh_seeds(
     string: &[u8],
     sketch_params: &SketchParams,
@@ -234,15 +329,15 @@ pub fn fmh_seeds(
     new_sketch: &mut Sketch,
     seed: bool,
 ) {
-    if seed && new_sketch.kmer_seeds_k.is_none() {
-        new_sketch.kmer_seeds_k = Some(KmerSeeds::default());
+    if seed {
+        new_sketch.init_seeds(sketch_params);
     }
     let marker_k = K_MARKER_DNA;
     let kmer_seeds_k = &mut new_sketch.kmer_seeds_k;
+    let kmer_seeds_k64 = &mut new_sketch.kmer_seeds_k64;
     let marker_seeds = &mut new_sketch.marker_seeds;
     let k = sketch_params.k;
-    let c = sketch_params.c;
-    if k > 16 {
+    if k > marker_k {
         panic!("Value of k > {} for DNA; not allowed.", marker_k);
     }
     if string.len() < 2 * marker_k {
@@ -252,7 +347,7 @@ pub fn fmh_seeds(
     let mut rolling_kmer_f_seed: MarkerBits;
     let mut rolling_kmer_r_marker: MarkerBits = 0;
     let mut rolling_kmer_r_seed: MarkerBits;
-    let seed_mask = MarkerBits::MAX >> (std::mem::size_of::<MarkerBits>() * 8 - 2 * k);
+    let seed_mask: MarkerBits = sketch_params.seed_mask();
 
     let marker_reverse_shift_dist = 2 * (marker_k - 1);
     let marker_mask = MarkerBits::MAX >> (std::mem::size_of::<MarkerBits>() * 8 - 2 * marker_k);
@@ -261,10 +356,22 @@ pub fn fmh_seeds(
     //    let threshold = i64::MIN + (u64::MAX / (c as u64)) as i64;
     //    let threshold_marker = i64::MIN + (u64::MAX / sketch_params.marker_c as u64) as i64;
 
-    let threshold = u64::MAX / (c as u64);
+    let threshold = sketch_params.seed_threshold();
     let threshold_marker = u64::MAX / (sketch_params.marker_c as u64);
+    //Seeds and markers resume once they no longer overlap a non-ACGT (or,
+    //with mask_lowercase, soft-masked) base. Bases before the first seed are
+    //tracked too, so that a window of a contig seeds the same k-mers as the
+    //whole contig.
+    let mut resume_ind = 0;
+    let mut marker_resume_ind = 0;
+    let mask_lowercase = sketch_params.mask_lowercase;
     for i in 0..marker_k - 1 {
-        let nuc_f = BYTE_TO_SEQ[string[i] as usize];
+        let nuc_byte = string[i] as usize;
+        if BYTE_IS_AMBIGUOUS[nuc_byte] || (mask_lowercase && string[i].is_ascii_lowercase()){
+            resume_ind = i + k;
+            marker_resume_ind = i + marker_k;
+        }
+        let nuc_f = BYTE_TO_SEQ[nuc_byte];
         //        let nuc_f = KmerEnc::encode(string[i]
         let nuc_r = 3 - nuc_f;
         rolling_kmer_f_marker <<= 2;
@@ -273,11 +380,11 @@ pub fn fmh_seeds(
         rolling_kmer_r_marker >>= 2;
         rolling_kmer_r_marker |= nuc_r << marker_reverse_shift_dist;
     }
-    let mut resume_ind = 0;
     for i in marker_k-1..len {
         let nuc_byte = string[i] as usize;
-        if nuc_byte == ASCII_N || nuc_byte == ASCII_N_SMALL{
+        if BYTE_IS_AMBIGUOUS[nuc_byte] || (mask_lowercase && string[i].is_ascii_lowercase()){
             resume_ind = i + k;
+            marker_resume_ind = i + marker_k;
         }
         let nuc_f = BYTE_TO_SEQ[nuc_byte];
         let nuc_r = 3 - nuc_f;
@@ -304,19 +411,19 @@ pub fn fmh_seeds(
 //        let hash_seed = mm_hashi64(canonical_kmer_seed as i64);
         let hash_seed = mm_hash64(canonical_kmer_seed);
         if hash_seed < threshold && resume_ind <= i {
-            if seed {
-                let kmer_seeds = &mut kmer_seeds_k.as_mut().unwrap();
-                let kmer_positions = kmer_seeds
-                    //Since we fix k = 15,can represent seeds as 32bits
-                    .entry(canonical_kmer_seed as SeedBits)
-                    .or_insert(SmallVec::<[SeedPosition; SMALL_VEC_SIZE]>::new());
-                //                    .or_insert(vec![]);
-                kmer_positions.push(SeedPosition {
-                    pos: i as GnPosition,
-                    canonical: canonical_seed,
-                    contig_index,
-                    phase: 0,
-                });
+            if seed && (!sketch_params.use_syncs || is_syncmer(canonical_kmer_seed, sketch_params)) {
+                //Seeds with k <= 16 are represented as 32bits
+                push_seed(
+                    kmer_seeds_k,
+                    kmer_seeds_k64,
+                    canonical_kmer_seed,
+                    SeedPosition {
+                        pos: i as GnPosition,
+                        canonical: canonical_seed,
+                        contig_index,
+                        phase: 0,
+                    },
+                );
             }
             let canonical_marker = rolling_kmer_f_marker < rolling_kmer_r_marker;
             let canonical_kmer_marker = if canonical_marker {
@@ -325,34 +432,74 @@ pub fn fmh_seeds(
                 rolling_kmer_r_marker
             };
 
-            if hash_seed < threshold_marker {
+            if hash_seed < threshold_marker && marker_resume_ind <= i {
                 marker_seeds.insert(canonical_kmer_marker);
             }
         }
     }
 }
 
-//This function is unused right now. Originally used for
-//getting a repetitive k-mer masking threshold. We may
-//modify the masking procedure in the future, so leaving for now.
-pub fn get_repetitive_kmers(kmer_seeds: &Option<KmerSeeds>, c: usize) -> usize {
-    if kmer_seeds.is_none() {
-        usize::MAX
-    } else {
-        let kmer_seeds = kmer_seeds.as_ref().unwrap();
-        let mut count_vec = vec![];
-        let kmer_seeds_ref = &kmer_seeds;
-        for ref_pos in kmer_seeds_ref.values() {
-            count_vec.push(ref_pos.len());
-        }
-        count_vec.sort();
-        let mut max_repet_cutoff = count_vec[count_vec.len() - count_vec.len() / 5000 - 1];
-        let default_repet_cutoff = BP_CHAIN_BAND / c;
-        if max_repet_cutoff < 20 || max_repet_cutoff > default_repet_cutoff{
-            max_repet_cutoff = default_repet_cutoff;
-        }
-        max_repet_cutoff
+fn reverse_complement(kmer: MarkerBits, k: usize) -> MarkerBits {
+    (0..k).fold(0, |rc, i| rc << 2 | (3 - (kmer >> (2 * i) & 3)))
+}
+
+///Keeps the seeds and markers of a DNA `sketch` that sketching at the
+///coarser `sketch_params` (see `SketchParams::downsampled`) would keep. Seeds
+///are stored as canonical k-mers and markers as canonical marker k-mers, so
+///their hashes can be recomputed without the sequence.
+pub fn downsample_sketch(sketch: &mut Sketch, sketch_params: &SketchParams) {
+    let threshold = sketch_params.seed_threshold();
+    let threshold_marker = u64::MAX / (sketch_params.marker_c as u64);
+    let seed_mask = sketch_params.seed_mask();
+    if let Some(kmer_seeds) = sketch.kmer_seeds_k.as_mut() {
+        kmer_seeds.retain(|kmer, _| mm_hash64(*kmer as u64) < threshold);
+    }
+    if let Some(kmer_seeds) = sketch.kmer_seeds_k64.as_mut() {
+        kmer_seeds.retain(|kmer, _| mm_hash64(*kmer) < threshold);
+    }
+    //Markers are kept by the hash of their canonical seed, which is in the
+    //last k bases of either strand.
+    sketch.marker_seeds.retain(|marker| {
+        let marker_rc = reverse_complement(*marker, K_MARKER_DNA);
+        let canonical_seed = u64::min(marker & seed_mask, marker_rc & seed_mask);
+        mm_hash64(canonical_seed) < threshold_marker
+    });
+    sketch.c = sketch_params.c;
+    sketch.marker_c = sketch_params.marker_c;
+    if sketch.kmer_seeds_k.is_some() || sketch.kmer_seeds_k64.is_some() {
+        sketch.repetitive_kmers = get_repetitive_kmers(sketch);
+    }
+}
+
+///Number of seeds of `kmer_seeds` that occur more than `cutoff` times.
+pub fn count_repetitive_seeds<K, S>(
+    kmer_seeds: &HashMap<K, SmallVec<[SeedPosition; SMALL_VEC_SIZE]>, S>,
+    cutoff: usize,
+) -> usize {
+    kmer_seeds.values().filter(|x| x.len() > cutoff).count()
+}
+
+///Per-genome repetitive seed cutoff: chaining does not use seeds that occur
+///more often than this. It is the multiplicity of the seed at the top 1/5000
+///of seeds, capped at BP_CHAIN_BAND / c. Returns 0 (unset) for sketches
+///without seeds.
+pub fn get_repetitive_kmers(sketch: &Sketch) -> usize {
+    let mut count_vec: Vec<usize> = match (&sketch.kmer_seeds_k, &sketch.kmer_seeds_k64) {
+        (_, Some(kmer_seeds)) => kmer_seeds.values().map(|x| x.len()).collect(),
+        (Some(kmer_seeds), None) => kmer_seeds.values().map(|x| x.len()).collect(),
+        (None, None) => vec![],
+    };
+    if count_vec.is_empty() || sketch.c == 0 {
+        return 0;
+    }
+    count_vec.sort_unstable();
+    let mut max_repet_cutoff = count_vec[count_vec.len() - count_vec.len() / 5000 - 1];
+    let bp_chain_band = if sketch.amino_acid { BP_CHAIN_BAND_AAI } else { BP_CHAIN_BAND };
+    let default_repet_cutoff = bp_chain_band / sketch.c;
+    if max_repet_cutoff < 20 || max_repet_cutoff > default_repet_cutoff{
+        max_repet_cutoff = default_repet_cutoff;
     }
+    max_repet_cutoff
 }
 
 
@@ -0,0 +1,274 @@
+use crate::error::SkaniError;
+use crate::file_io;
+use crate::params::*;
+use crate::types::*;
+use fxhash::FxHashMap;
+
+pub type PairwiseResults = FxHashMap<usize, FxHashMap<usize, AniEstResult>>;
+
+///Destination for the results of dist, triangle and search. Results may be
+///pushed several times during a run (every INTERMEDIATE_WRITE_COUNT queries)
+///and `finish` is called once at the end.
+///
+///Only `push_results` has to be implemented; by default triangle's pairwise
+///results are flattened into a list.
+pub trait ResultSink {
+    ///Called once the sketch parameters of the run are known, before any results are pushed.
+    fn begin(&mut self, _sketch_params: &SketchParams) -> Result<(), SkaniError> {
+        Ok(())
+    }
+
+    ///Query-reference results from dist and search.
+    fn push_results(&mut self, anis: Vec<AniEstResult>) -> Result<(), SkaniError>;
+
+    ///Pairwise results from triangle, keyed by the indices of the
+    ///two genomes in `sketches` with the first index being the smaller one.
+    fn push_pairwise(
+        &mut self,
+        anis: PairwiseResults,
+        _sketches: &[Sketch],
+    ) -> Result<(), SkaniError> {
+        let mut flat = vec![];
+        for (_, map) in anis.into_iter() {
+            for (_, ani_res) in map.into_iter() {
+                if !(ani_res.ani == -1. || ani_res.ani.is_nan()) {
+                    flat.push(ani_res);
+                }
+            }
+        }
+        self.push_results(flat)
+    }
+
+    fn finish(&mut self) -> Result<(), SkaniError> {
+        Ok(())
+    }
+}
+
+impl<S: ResultSink + ?Sized> ResultSink for Box<S> {
+    fn begin(&mut self, sketch_params: &SketchParams) -> Result<(), SkaniError> {
+        (**self).begin(sketch_params)
+    }
+    fn push_results(&mut self, anis: Vec<AniEstResult>) -> Result<(), SkaniError> {
+        (**self).push_results(anis)
+    }
+    fn push_pairwise(
+        &mut self,
+        anis: PairwiseResults,
+        sketches: &[Sketch],
+    ) -> Result<(), SkaniError> {
+        (**self).push_pairwise(anis, sketches)
+    }
+    fn finish(&mut self) -> Result<(), SkaniError> {
+        (**self).finish()
+    }
+}
+
+///skani's tab-separated output, written to a file or to stdout if the file name is empty.
+pub struct TsvSink {
+    pub out_file_name: String,
+    pub max_results: usize,
+    pub est_ci: bool,
+    pub detailed_out: bool,
+    pub diagonal: bool,
+    ///Adds an Effective_coverage column for read queries.
+    pub coverage: bool,
+    aai: bool,
+    first_write: bool,
+}
+
+impl TsvSink {
+    pub fn new(
+        out_file_name: &str,
+        max_results: usize,
+        est_ci: bool,
+        detailed_out: bool,
+        diagonal: bool,
+    ) -> TsvSink {
+        TsvSink {
+            out_file_name: out_file_name.to_string(),
+            max_results,
+            est_ci,
+            detailed_out,
+            diagonal,
+            coverage: false,
+            aai: false,
+            first_write: true,
+        }
+    }
+
+    pub fn from_params(command_params: &CommandParams) -> TsvSink {
+        let mut sink = TsvSink::new(
+            &command_params.out_file_name,
+            command_params.max_results,
+            command_params.est_ci,
+            command_params.detailed_out,
+            command_params.diagonal,
+        );
+        sink.coverage = command_params.reads_q;
+        sink
+    }
+}
+
+impl ResultSink for TsvSink {
+    fn begin(&mut self, sketch_params: &SketchParams) -> Result<(), SkaniError> {
+        self.aai = sketch_params.use_aa;
+        Ok(())
+    }
+
+    fn push_results(&mut self, anis: Vec<AniEstResult>) -> Result<(), SkaniError> {
+        file_io::write_query_ref_list(
+            &anis,
+            &self.out_file_name,
+            self.max_results,
+            self.aai,
+            self.est_ci,
+            self.detailed_out,
+            self.coverage,
+            !self.first_write,
+        )?;
+        self.first_write = false;
+        Ok(())
+    }
+
+    fn push_pairwise(
+        &mut self,
+        anis: PairwiseResults,
+        sketches: &[Sketch],
+    ) -> Result<(), SkaniError> {
+        //The diagonal only needs to be written with the first batch.
+        file_io::write_sparse_matrix(
+            &anis,
+            sketches,
+            &self.out_file_name,
+            self.aai,
+            self.est_ci,
+            self.detailed_out,
+            self.diagonal && self.first_write,
+            !self.first_write,
+        )?;
+        self.first_write = false;
+        Ok(())
+    }
+}
+
+///Lower-triangular (or full) PHYLIP matrix for triangle. Results are
+///accumulated in memory and written by `finish`.
+pub struct PhylipSink {
+    pub out_file_name: String,
+    pub use_contig_names: bool,
+    pub full_matrix: bool,
+    pub diagonal: bool,
+    pub distance: bool,
+    aai: bool,
+    names: Vec<String>,
+    anis: PairwiseResults,
+}
+
+impl PhylipSink {
+    pub fn new(
+        out_file_name: &str,
+        use_contig_names: bool,
+        full_matrix: bool,
+        diagonal: bool,
+        distance: bool,
+    ) -> PhylipSink {
+        PhylipSink {
+            out_file_name: out_file_name.to_string(),
+            use_contig_names,
+            full_matrix,
+            diagonal,
+            distance,
+            aai: false,
+            names: vec![],
+            anis: PairwiseResults::default(),
+        }
+    }
+
+    pub fn from_params(command_params: &CommandParams) -> PhylipSink {
+        PhylipSink::new(
+            &command_params.out_file_name,
+            command_params.individual_contig_r,
+            command_params.full_matrix,
+            command_params.diagonal,
+            command_params.distance,
+        )
+    }
+}
+
+impl ResultSink for PhylipSink {
+    fn begin(&mut self, sketch_params: &SketchParams) -> Result<(), SkaniError> {
+        self.aai = sketch_params.use_aa;
+        Ok(())
+    }
+
+    fn push_results(&mut self, _anis: Vec<AniEstResult>) -> Result<(), SkaniError> {
+        Err(SkaniError::InvalidParameter(
+            "PHYLIP output is only available for triangle".to_string(),
+        ))
+    }
+
+    fn push_pairwise(
+        &mut self,
+        anis: PairwiseResults,
+        sketches: &[Sketch],
+    ) -> Result<(), SkaniError> {
+        if self.names.len() != sketches.len() {
+            self.names = sketches
+                .iter()
+                .map(|x| {
+                    if self.use_contig_names {
+                        x.contigs[0].clone()
+                    } else {
+                        x.file_name.clone()
+                    }
+                })
+                .collect();
+        }
+        for (i, map) in anis.into_iter() {
+            self.anis.entry(i).or_default().extend(map);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), SkaniError> {
+        let anis = std::mem::take(&mut self.anis);
+        file_io::write_phyllip_matrix(
+            &anis,
+            &self.names,
+            &self.out_file_name,
+            self.full_matrix,
+            self.diagonal,
+            self.aai,
+            self.distance,
+        )
+    }
+}
+
+///Keeps every result in memory.
+#[derive(Default)]
+pub struct MemorySink {
+    pub results: Vec<AniEstResult>,
+}
+
+impl MemorySink {
+    pub fn new() -> MemorySink {
+        MemorySink::default()
+    }
+}
+
+impl ResultSink for MemorySink {
+    fn push_results(&mut self, mut anis: Vec<AniEstResult>) -> Result<(), SkaniError> {
+        self.results.append(&mut anis);
+        Ok(())
+    }
+}
+
+///The sink the command line uses for the given mode: PHYLIP for triangle
+///without --sparse, TSV otherwise.
+pub fn sink_from_params(command_params: &CommandParams) -> Box<dyn ResultSink + Send> {
+    if command_params.mode == Mode::Triangle && !command_params.sparse {
+        Box::new(PhylipSink::from_params(command_params))
+    } else {
+        Box::new(TsvSink::from_params(command_params))
+    }
+}
@@ -1,74 +1,159 @@
+use crate::db;
+use crate::db::{PackedWriter, SketchDb};
+use crate::error::SkaniError;
 use crate::file_io;
+use crate::format;
+use crate::marker_index;
 use crate::params::*;
 use crate::types::*;
+use fxhash::FxHashSet;
 use log::*;
 use rayon::prelude::*;
-use std::fs::File;
-use std::io::BufWriter;
+use rayon::ThreadPool;
 
 use std::path::Path;
 use std::sync::Mutex;
 use std::time::Instant;
 
-pub fn sketch(command_params: CommandParams, sketch_params: SketchParams) {
+///Runs `sketch_in_pool` in a pool with `command_params.threads` threads.
+pub fn sketch(command_params: CommandParams, sketch_params: SketchParams) -> Result<(), SkaniError> {
+    let pool = command_params.thread_pool()?;
+    sketch_in_pool(&pool, command_params, sketch_params)
+}
+
+pub fn sketch_in_pool(
+    pool: &ThreadPool,
+    command_params: CommandParams,
+    sketch_params: SketchParams,
+) -> Result<(), SkaniError> {
+    pool.install(|| sketch_inner(command_params, sketch_params))
+}
+
+fn sketch_inner(command_params: CommandParams, sketch_params: SketchParams) -> Result<(), SkaniError> {
     let now = Instant::now();
     info!("Sketching files...");
     let p = command_params.out_file_name.to_string();
-    if Path::new(&p).exists() {
-        error!("Output directory exists; output directory must not be an existing directory. Exiting.");
-        std::process::exit(1);
+    let marker_file = format!("{}/{}", &p, db::MARKER_FILE);
+    let mut old_markers = vec![];
+    let mut packed = command_params.packed;
+    //Files without a grouping table are genomes of their own.
+    let mut genomes = command_params.ref_groups.clone();
+    if genomes.is_empty() {
+        genomes = command_params
+            .ref_files
+            .iter()
+            .map(|x| GenomeGroup {
+                name: x.clone(),
+                files: vec![x.clone()],
+            })
+            .collect();
+    }
+    let mut manifest;
+    if command_params.append {
+        (old_markers, packed, manifest) = open_for_append(&p, &marker_file, &sketch_params, command_params.packed)?;
+        genomes.retain(|x| {
+            let exists = manifest.contains(&x.name);
+            if exists {
+                warn!("{} is already in {}; skipping", x.name, p);
+            }
+            !exists
+        });
+    } else {
+        if Path::new(&p).exists() {
+            return Err(SkaniError::InvalidParameter(format!(
+                "output directory {} exists; output directory must not be an existing directory. Use --append to add genomes to an existing database",
+                p
+            )));
+        }
+        std::fs::create_dir_all(&p).map_err(|e| SkaniError::FileOpen(p.clone(), e))?;
+        manifest = db::Manifest::default();
     }
-    std::fs::create_dir_all(p).unwrap();
+    //Names are given before sketching so that they do not depend on the order
+    //the threads finish in. Genomes split from multi-genome fastas are only
+    //known once their file is read and are named as they are sketched.
+    let sketch_names = if command_params.individual_contig_r {
+        //The contigs of a file are written as {contig index}_{name}. The manifest
+        //maps a file to one sketch, so these files are not listed in it.
+        let mut contig_names = manifest.clone();
+        genomes
+            .iter()
+            .map(|x| contig_names.assign(&x.name))
+            .collect::<Vec<String>>()
+    } else if command_params.genome_split.is_none() {
+        genomes
+            .iter()
+            .map(|x| manifest.assign(&x.name))
+            .collect::<Vec<String>>()
+    } else {
+        vec![]
+    };
+    let manifest = Mutex::new(manifest);
 
-    let num_iters = command_params.ref_files.len();
+    let num_iters = genomes.len();
     let counter: Mutex<usize> = Mutex::new(0);
     let marker_sketches: Mutex<Vec<Sketch>> = Mutex::new(vec![]);
-    (0..num_iters).into_par_iter().for_each(|i| {
+    let packed_writer = if !packed {
+        None
+    } else if command_params.append {
+        Some(Mutex::new(PackedWriter::append(Path::new(&p), &sketch_params)?))
+    } else {
+        Some(Mutex::new(PackedWriter::new(Path::new(&p), &sketch_params)?))
+    };
+    (0..num_iters).into_par_iter().try_for_each(|i| {
         let ref_sketches;
         if command_params.individual_contig_r {
             ref_sketches = file_io::fastx_to_multiple_sketch_rewrite(
-                &vec![command_params.ref_files[i].clone()],
+                &genomes[i].files,
                 &sketch_params,
                 true,
-        );
+        )?;
+        }
+        else if let Some(split) = &command_params.genome_split {
+            ref_sketches = file_io::fastx_to_split_sketches(
+                &genomes[i].files,
+                &sketch_params,
+                split,
+                true,
+            )?;
         }
         else{
-            ref_sketches = file_io::fastx_to_sketches(
-                &vec![command_params.ref_files[i].clone()],
+            ref_sketches = file_io::groups_to_sketches(
+                &genomes[i..i + 1],
                 &sketch_params,
-                true,);
+                true,)?;
         }
         let marker_ref_sketches = ref_sketches
             .iter()
             .map(Sketch::get_markers_only)
             .collect::<Vec<Sketch>>();
-        (0..ref_sketches.len()).into_par_iter().for_each(|j|{
+        (0..ref_sketches.len()).into_par_iter().try_for_each(|j|{
             let sketch = &ref_sketches[j];
             let marker_sketch = &marker_ref_sketches[j];
-            let path = Path::new(&sketch.file_name);
-            let filename = path.file_name().unwrap().to_str().unwrap();
             let sketch_name;
             if command_params.individual_contig_r{
-                sketch_name = format!(
-                    "{}/{}_{}.sketch",
-                    &command_params.out_file_name, j, filename
-                );
+                sketch_name = format!("{}_{}", j, sketch_names[i]);
+            }
+            else if command_params.genome_split.is_some(){
+                let mut manifest = manifest.lock().unwrap();
+                if manifest.contains(&sketch.file_name) {
+                    warn!("Genome {} is already in {}; skipping the one in {}", sketch.file_name, p, genomes[i].name);
+                    return Ok(());
+                }
+                sketch_name = manifest.assign(&sketch.file_name);
             }
             else{
-                sketch_name = format!(
-                    "{}/{}.sketch",
-                    &command_params.out_file_name, filename
-                );
+                sketch_name = sketch_names[i].clone();
             }
-            let mut file_bin = BufWriter::new(
-                File::create(sketch_name)
-                .unwrap(),
-            );
 
-            trace!("{} compress factor", sketch.total_sequence_length / sketch.kmer_seeds_k.as_ref().unwrap().len());
+            trace!("{} compress factor", sketch.total_sequence_length / sketch.num_seeds().unwrap());
             trace!("{} marker compress factor", sketch.total_sequence_length / sketch.marker_seeds.len());
 
-            bincode::serialize_into(&mut file_bin, &(&sketch_params, sketch)).unwrap();
+            if let Some(packed_writer) = &packed_writer {
+                packed_writer.lock().unwrap().add(&sketch_name, sketch)?;
+            } else {
+                let sketch_file = format!("{}/{}", &command_params.out_file_name, sketch_name);
+                format::write_sketch_file(&sketch_file, &sketch_params, sketch)?;
+            }
 
             let mut locked = marker_sketches.lock().unwrap();
             locked.push(marker_sketch.clone());
@@ -77,17 +162,89 @@ pub fn sketch(command_params: CommandParams, sketch_params: SketchParams) {
             if *locked % 100 == 0 && *locked != 0 {
                 info!("{} sequences sketched.", locked);
             }
-        });
-    });
-    let mut file_bin_marker = BufWriter::new(
-        File::create(format!(
-            "{}/markers.bin",
-            &command_params.out_file_name
-        ))
-        .unwrap(),
-    );
-    let markers = marker_sketches.into_inner().unwrap();
-    bincode::serialize_into(&mut file_bin_marker, &(&sketch_params, markers)).unwrap();
+            Ok::<(), SkaniError>(())
+        })
+    })?;
+    if let Some(packed_writer) = packed_writer {
+        packed_writer.into_inner().unwrap().finish()?;
+    }
+    let mut markers = marker_sketches.into_inner().unwrap();
+    if command_params.append {
+        info!("{} new sketches added to {}", markers.len(), p);
+        old_markers.append(&mut markers);
+        markers = old_markers;
+    }
+    //Skipped files (not valid fasta/fastq or only short contigs) have no sketch and
+    //are left out. The manifest is written before markers.bin so that every genome
+    //in markers.bin has an entry.
+    let sketched_files = markers
+        .iter()
+        .map(|x| x.file_name.as_str())
+        .collect::<FxHashSet<&str>>();
+    let mut manifest = manifest.into_inner().unwrap();
+    manifest.retain(|x| sketched_files.contains(x));
+    manifest.write(Path::new(&p))?;
+    if command_params.append {
+        //Written to a temporary file first so an interrupted append keeps the old markers.
+        let tmp_file = format!("{}.tmp", marker_file);
+        let markers_header = format::write_marker_file(&tmp_file, &sketch_params, &markers)?;
+        std::fs::rename(&tmp_file, &marker_file)?;
+        if command_params.marker_index {
+            marker_index::write_for_folder(Path::new(&p), &markers_header, &markers)?;
+        } else {
+            marker_index::update_for_folder(Path::new(&p), &markers_header, &markers)?;
+        }
+    } else {
+        let markers_header = format::write_marker_file(&marker_file, &sketch_params, &markers)?;
+        if command_params.marker_index {
+            marker_index::write_for_folder(Path::new(&p), &markers_header, &markers)?;
+        }
+    }
     info!("Sketching time: {}", now.elapsed().as_secs_f32());
-    
+    Ok(())
+}
+
+///Checks that `folder` is a sketch database with the same parameters as
+///`sketch_params`. Returns its marker sketches, whether it is packed, and its manifest.
+fn open_for_append(
+    folder: &str,
+    marker_file: &str,
+    sketch_params: &SketchParams,
+    packed: bool,
+) -> Result<(Vec<Sketch>, bool, db::Manifest), SkaniError> {
+    if !Path::new(marker_file).exists() {
+        return Err(SkaniError::NoInputs(format!(
+            "{} not found; --append needs a folder generated by `skani sketch`",
+            marker_file
+        )));
+    }
+    let (stored_params, old_markers) = file_io::marker_sketches_from_marker_file(marker_file)?;
+    if stored_params != *sketch_params {
+        return Err(SkaniError::SketchParamMismatch(format!(
+            "{} was sketched with c = {}, k = {}, marker_c = {}, AAI = {} but the new genomes use c = {}, k = {}, marker_c = {}, AAI = {}",
+            folder,
+            stored_params.c,
+            stored_params.k,
+            stored_params.marker_c,
+            stored_params.use_aa,
+            sketch_params.c,
+            sketch_params.k,
+            sketch_params.marker_c,
+            sketch_params.use_aa
+        )));
+    }
+    let sketch_db = SketchDb::open(Path::new(folder))?;
+    if packed && !sketch_db.is_packed() {
+        warn!(
+            "{} is not a packed database; --packed is ignored when appending",
+            folder
+        );
+    }
+    let manifest = db::Manifest::open(Path::new(folder), &old_markers)?;
+    info!(
+        "Appending to {} with {} sketches",
+        folder,
+        old_markers.len()
+    );
+    Ok((old_markers, sketch_db.is_packed(), manifest))
 }
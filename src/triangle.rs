@@ -1,21 +1,47 @@
 use crate::chain;
+use crate::error::SkaniError;
 use crate::file_io;
 use crate::params::*;
 use crate::regression;
 use crate::screen;
+use crate::sink::ResultSink;
 use crate::types::*;
 use fxhash::FxHashMap;
 use log::*;
 use rayon::prelude::*;
+use rayon::ThreadPool;
 use std::sync::Mutex;
 use std::time::Instant;
 
-pub fn triangle(command_params: CommandParams, mut sketch_params: SketchParams) {
+///Runs `triangle_in_pool` in a pool with `command_params.threads` threads.
+pub fn triangle<S: ResultSink + Send + ?Sized>(
+    command_params: CommandParams,
+    sketch_params: SketchParams,
+    sink: &mut S,
+) -> Result<(), SkaniError> {
+    let pool = command_params.thread_pool()?;
+    triangle_in_pool(&pool, command_params, sketch_params, sink)
+}
+
+pub fn triangle_in_pool<S: ResultSink + Send + ?Sized>(
+    pool: &ThreadPool,
+    command_params: CommandParams,
+    sketch_params: SketchParams,
+    sink: &mut S,
+) -> Result<(), SkaniError> {
+    pool.install(|| triangle_inner(command_params, sketch_params, sink))
+}
+
+fn triangle_inner<S: ResultSink + Send + ?Sized>(
+    command_params: CommandParams,
+    mut sketch_params: SketchParams,
+    sink: &mut S,
+) -> Result<(), SkaniError> {
     let ref_sketches;
     let now = Instant::now();
     if command_params.refs_are_sketch {
         info!("Sketches detected.");
-        let param_and_sketches = file_io::sketches_from_sketch(&command_params.ref_files);
+        let param_and_sketches = file_io::sketches_from_sketch(&command_params.ref_files)?;
         if param_and_sketches.0.c != sketch_params.c || param_and_sketches.0.marker_c != sketch_params.marker_c {
             warn!("Input parameter c = {}, m = {} is not equal to the sketch parameter c = {},m = {}. Using sketch parameters.", sketch_params.c, sketch_params.marker_c, param_and_sketches.0.c, param_and_sketches.0.marker_c);
         }
@@ -26,9 +52,13 @@ pub fn triangle(command_params: CommandParams, mut sketch_params: SketchParams)
             &command_params.ref_files,
             &sketch_params,
             true,
-        );
+        )?;
+    } else if let Some(split) = &command_params.genome_split {
+        ref_sketches = file_io::fastx_to_split_sketches(&command_params.ref_files, &sketch_params, split, true)?;
+    } else if !command_params.ref_groups.is_empty() {
+        ref_sketches = file_io::groups_to_sketches(&command_params.ref_groups, &sketch_params, true)?;
     } else {
-        ref_sketches = file_io::fastx_to_sketches(&command_params.ref_files, &sketch_params, true);
+        ref_sketches = file_io::fastx_to_sketches(&command_params.ref_files, &sketch_params, true)?;
     }
     let screen_val;
     if command_params.screen_val == 0. {
@@ -44,12 +74,12 @@ pub fn triangle(command_params: CommandParams, mut sketch_params: SketchParams)
         Mutex::new(FxHashMap::default());
 
     if ref_sketches.is_empty() {
-        error!("No genomes/sketches found.");
-        std::process::exit(1)
+        return Err(SkaniError::NoInputs("No genomes/sketches found.".to_string()));
     }
     let kmer_to_sketch = screen::kmer_to_sketch_from_refs(&ref_sketches);
     let counter: Mutex<usize> = Mutex::new(0);
-    let first: Mutex<bool> = Mutex::new(true);
+    sink.begin(&sketch_params)?;
+    let sink = Mutex::new(sink);
 
     let model_opt = regression::get_model(sketch_params.c, command_params.learned_ani);
     if model_opt.is_some() {
@@ -66,7 +96,7 @@ pub fn triangle(command_params: CommandParams, mut sketch_params: SketchParams)
     (0..ref_sketches.len() - 1)
         .collect::<Vec<usize>>()
         .into_par_iter()
-        .for_each(|i| {
+        .try_for_each(|i| {
             let ref_sketch_i = &ref_sketches[i];
             let screened_refs = screen::screen_refs(
                 screen_val,
@@ -107,56 +137,25 @@ pub fn triangle(command_params: CommandParams, mut sketch_params: SketchParams)
             }
             if c % 100 == 0 && c != 0 {
                 info!("{} query sequences processed.", c);
-                if c % INTERMEDIATE_WRITE_COUNT == 0 && c != 0 && command_params.sparse{
+                if c % INTERMEDIATE_WRITE_COUNT == 0 && c != 0 {
                     let moved_anis: FxHashMap<_,_>;
                     {
                         let mut locked = anis.lock().unwrap();
                         moved_anis = std::mem::take(&mut locked);
-                        let mut locked = first.lock().unwrap();
+                        let mut locked = sink.lock().unwrap();
                         info!("Writing results for {} query sequences.", INTERMEDIATE_WRITE_COUNT);
-                        file_io::write_sparse_matrix(
-                            &moved_anis,
-                            &ref_sketches,
-                            &command_params.out_file_name,
-                            sketch_params.use_aa,
-                            command_params.est_ci,
-                            command_params.detailed_out,
-                            command_params.diagonal,
-                            !*locked,
-                        );
-                        if *locked == true {
-                            *locked = false;
-                        }
+                        locked.push_pairwise(moved_anis, &ref_sketches)?;
                     }
                 }
             }
 
-            //
-        });
+            Ok::<(), SkaniError>(())
+        })?;
     let anis = anis.into_inner().unwrap();
 
-    if command_params.sparse {
-        file_io::write_sparse_matrix(
-            &anis,
-            &ref_sketches,
-            &command_params.out_file_name,
-            sketch_params.use_aa,
-            command_params.est_ci,
-            command_params.detailed_out,
-            command_params.diagonal,
-            !*first.lock().unwrap(),
-        );
-    } else {
-        file_io::write_phyllip_matrix(
-            &anis,
-            &ref_sketches,
-            &command_params.out_file_name,
-            command_params.individual_contig_r,
-            command_params.full_matrix,
-            command_params.diagonal,
-            sketch_params.use_aa,
-            command_params.distance,
-        );
-    }
+    let sink = sink.into_inner().unwrap();
+    sink.push_pairwise(anis, &ref_sketches)?;
+    sink.finish()?;
     info!("ANI triangle time: {}", now.elapsed().as_secs_f32());
+    Ok(())
 }
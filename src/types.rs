@@ -47,6 +47,19 @@ pub const BYTE_TO_SEQ: [MarkerBits; 256] = [
     0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
 ];
 
+//Bytes other than A, C, G and T in either case, e.g. N and the other
+//IUPAC ambiguity codes. Seeds and markers never span them.
+pub const BYTE_IS_AMBIGUOUS: [bool; 256] = {
+    let mut table = [true; 256];
+    let acgt = b"ACGTacgt";
+    let mut i = 0;
+    while i < acgt.len() {
+        table[acgt[i] as usize] = false;
+        i += 1;
+    }
+    table
+};
+
 
 pub type GnPosition = u32;
 pub type ContigIndex = u32;
@@ -56,9 +69,33 @@ pub type SeedBits = u32;
 pub type KmerToSketch = MMHashMap<MarkerBits, SmallVec<[u32; KMER_SK_SMALL_VEC_SIZE]>>;
 //pub type KmerToSketch = MMHashMap<MarkerBits, Vec<usize>>;
 pub type KmerSeeds = MMHashMap32<SeedBits, SmallVec<[SeedPosition;SMALL_VEC_SIZE]>>;
+//Seeds that do not fit SeedBits, i.e. k > 16 for DNA.
+pub type SeedBits64 = u64;
+pub type KmerSeeds64 = MMHashMap<SeedBits64, SmallVec<[SeedPosition;SMALL_VEC_SIZE]>>;
 //pub type KmerSeeds = MMHashMap<SeedBits, SmallVec<[SeedPosition;SMALL_VEC_SIZE]>>;
 
 
+///Adds a seed position to whichever seed table exists. The 32-bit table
+///keeps the lower bits of `kmer`, which hold the whole k-mer when it fits.
+#[inline]
+pub fn push_seed(
+    kmer_seeds: &mut Option<KmerSeeds>,
+    kmer_seeds64: &mut Option<KmerSeeds64>,
+    kmer: SeedBits64,
+    position: SeedPosition,
+) {
+    if let Some(kmer_seeds64) = kmer_seeds64 {
+        kmer_seeds64.entry(kmer).or_default().push(position);
+    } else {
+        kmer_seeds
+            .as_mut()
+            .unwrap()
+            .entry(kmer as SeedBits)
+            .or_default()
+            .push(position);
+    }
+}
+
 //Implement minimap2 hashing, will test later.
 pub type MMBuildHasher = BuildHasherDefault<MMHasher>;
 pub type MMBuildHasher32 = BuildHasherDefault<MMHasher32>;
@@ -133,7 +170,7 @@ pub struct SeedPosition{
 //    }
 //}
 
-#[derive(Eq, PartialEq, Serialize, Deserialize, Clone)]
+#[derive(PartialEq, Serialize, Deserialize, Clone)]
 pub struct Sketch {
     pub file_name: String,
     pub kmer_seeds_k: Option<KmerSeeds>,
@@ -141,23 +178,39 @@ pub struct Sketch {
     pub total_sequence_length: usize,
     pub contig_lengths: Vec<GnPosition>,
 
-    //repetitive_kmers isn't being used right now, but we may
-    //make the repeat masking procedure smarter in the future. 
-    //A repetitive k-mer threshold is set in chaining as
-    //2500/c. 
-    //
-    //Also leaving this here so that we users can have 
-    //valid .sketch files across versions, although we may
-    //break that in the future. 
+    //Seeds occurring more often than this are not used as anchors;
+    //see seeding::get_repetitive_kmers. 0 for sketches from before it
+    //was computed, for which chaining uses BP_CHAIN_BAND/c.
     pub repetitive_kmers: usize,
     pub marker_seeds: MMHashSet<MarkerBits>,
     pub marker_c: usize,
     pub c: usize,
     pub k: usize,
     pub contig_order: usize,
-    pub amino_acid: bool
+    pub amino_acid: bool,
+    //Used instead of kmer_seeds_k when the sketch parameters
+    //have use_seed64 set.
+    pub kmer_seeds_k64: Option<KmerSeeds64>,
+    //Number of bases in a seed; less than k for spaced seeds.
+    //0 is read as k.
+    pub seed_weight: usize,
+    //Bases excluded from seeding by masking; not counted
+    //in aligned fraction denominators.
+    pub masked_length: usize,
+    //Bases that are not A, C, G or T (N and other IUPAC codes).
+    pub ambiguous_bases: usize,
+    //How seeds were sampled, as in the sketch parameters. Sketches
+    //can only be compared if these agree.
+    pub seed_pattern: String,
+    pub sync_s: usize,
+    pub closed_syncs: bool,
+    pub mask_lowercase: bool,
+    pub dust_level: Option<f64>,
 }
 
+//dust_level is never NaN; see SketchParams::set_dust_level.
+impl Eq for Sketch {}
+
 impl Sketch{
     pub fn get_markers_only(sketch: &Sketch) -> Sketch{
         
@@ -173,7 +226,49 @@ impl Sketch{
             c : sketch.c,
             k : sketch.k,
             contig_order: sketch.contig_order,
-            amino_acid: sketch.amino_acid
+            amino_acid: sketch.amino_acid,
+            kmer_seeds_k64: None,
+            seed_weight: sketch.seed_weight,
+            masked_length: sketch.masked_length,
+            ambiguous_bases: sketch.ambiguous_bases,
+            seed_pattern: sketch.seed_pattern.clone(),
+            sync_s: sketch.sync_s,
+            closed_syncs: sketch.closed_syncs,
+            mask_lowercase: sketch.mask_lowercase,
+            dust_level: sketch.dust_level,
+        }
+    }
+
+    ///Number of distinct seeds, or None for marker-only sketches.
+    pub fn num_seeds(&self) -> Option<usize> {
+        match (&self.kmer_seeds_k, &self.kmer_seeds_k64) {
+            (_, Some(kmer_seeds)) => Some(kmer_seeds.len()),
+            (Some(kmer_seeds), None) => Some(kmer_seeds.len()),
+            (None, None) => None,
+        }
+    }
+
+    ///Sequence length that was not masked, used as the aligned fraction denominator.
+    pub fn unmasked_length(&self) -> usize {
+        self.total_sequence_length.saturating_sub(self.masked_length)
+    }
+
+    ///Creates the seed table for `sketch_params` if the sketch has none and
+    ///records how seeds are sampled.
+    pub fn init_seeds(&mut self, sketch_params: &SketchParams) {
+        self.seed_weight = sketch_params.seed_weight();
+        self.seed_pattern = sketch_params.seed_pattern.clone();
+        self.sync_s = if sketch_params.use_syncs { sketch_params.sync_s } else { 0 };
+        self.closed_syncs = sketch_params.use_syncs && sketch_params.closed_syncs;
+        self.mask_lowercase = sketch_params.mask_lowercase;
+        self.dust_level = sketch_params.dust_level;
+        if self.kmer_seeds_k.is_some() || self.kmer_seeds_k64.is_some() {
+            return;
+        }
+        if sketch_params.use_seed64 {
+            self.kmer_seeds_k64 = Some(KmerSeeds64::default());
+        } else {
+            self.kmer_seeds_k = Some(KmerSeeds::default());
         }
     }
     
@@ -182,7 +277,8 @@ impl Sketch{
         let mut new_sketch = Sketch::default();
         new_sketch.c = c;
         new_sketch.k = k;
-        new_sketch.marker_c = c;
+        new_sketch.seed_weight = k;
+        new_sketch.marker_c = marker_c;
         new_sketch.file_name = file_name;
         new_sketch.amino_acid = amino_acid;
         new_sketch
@@ -222,6 +318,15 @@ impl Default for Sketch {
             k: 0,
             contig_order:0,
             amino_acid: false,
+            kmer_seeds_k64: None,
+            seed_weight: 0,
+            masked_length: 0,
+            ambiguous_bases: 0,
+            seed_pattern: String::new(),
+            sync_s: 0,
+            closed_syncs: false,
+            mask_lowercase: false,
+            dust_level: None,
         }
     }
 }
@@ -421,4 +526,8 @@ pub struct AniEstResult{
     pub num_contigs_r: u32,
     pub avg_chain_int_len: u32,
     pub total_bases_covered: u32, 
+    pub ambiguous_bases_q: u32,
+    pub ambiguous_bases_r: u32,
+    //Estimated k-mer coverage of the reference by a read query; 0 otherwise.
+    pub effective_coverage: f32,
 }
@@ -5,6 +5,7 @@ use tsv::*;
  // Used for writing assertions
 use serial_test::serial;
 use std::process::Command; // Run programs
+use std::path::Path;
                            //
 #[derive(Default, Clone, Debug, PartialEq)]
 pub struct AniResult{
@@ -161,3 +162,587 @@ fn fast_show_degenerate_inputs(){
     assert!(results.len() == 0);
 
 }
+
+#[test]
+#[serial]
+fn fast_test_inspect(){
+    let _ = std::fs::remove_dir_all("./tests/results/test_inspect_dir");
+    run_skani(&["sketch", "./test_files/o157_plasmid.fasta", "./test_files/e.coli-W.fasta.gz", "-o", "./tests/results/test_inspect_dir"], true);
+
+    let out_line = run_skani(&["inspect", "./tests/results/test_inspect_dir"], false);
+    println!("{}", out_line);
+    assert!(out_line.contains("c = 125, k = 15, marker_c = 1000"));
+    assert!(out_line.contains("N50: 92596"));
+    assert!(out_line.contains("Repetitive seed cutoff: 20 ("));
+    assert!(out_line.contains("All files have the same sketch parameters."));
+
+    let out_line = run_skani(&["inspect", "--json", "./tests/results/test_inspect_dir/markers.bin"], false);
+    let json: serde_json::Value = serde_json::from_str(&out_line).unwrap();
+    assert!(json["files"][0]["num_genomes"] == 2);
+    assert!(json["files"][0]["c"] == 125);
+
+    let out_line = run_skani(&["inspect", "./test_files/e.coli-o157.fasta.sketch"], false);
+    assert!(out_line.contains("Format version: 0"));
+    assert!(!out_line.contains("Repetitive seed cutoff"));
+}
+
+#[test]
+#[serial]
+fn fast_test_packed_db(){
+    let _ = std::fs::remove_dir_all("./tests/results/test_packed_dir");
+    let _ = std::fs::remove_dir_all("./tests/results/test_unpacked_dir");
+    run_skani(&["sketch", "./test_files/o157_plasmid.fasta", "./test_files/e.coli-W.fasta.gz", "-o", "./tests/results/test_packed_dir", "--packed"], true);
+    run_skani(&["sketch", "./test_files/o157_plasmid.fasta", "./test_files/e.coli-W.fasta.gz", "-o", "./tests/results/test_unpacked_dir"], true);
+    assert!(Path::new("./tests/results/test_packed_dir/sketches.db").exists());
+    assert!(Path::new("./tests/results/test_packed_dir/sketches.idx").exists());
+    assert!(!Path::new("./tests/results/test_packed_dir/e.coli-W.fasta.gz.sketch").exists());
+
+    let packed = run_skani(&["search", "-d", "./tests/results/test_packed_dir", "./test_files/e.coli-W.fasta.gz"], false);
+    let unpacked = run_skani(&["search", "-d", "./tests/results/test_unpacked_dir", "./test_files/e.coli-W.fasta.gz"], false);
+    assert!(packed.contains("e.coli-W.fasta.gz"));
+    let mut packed_lines: Vec<&str> = packed.lines().collect();
+    let mut unpacked_lines: Vec<&str> = unpacked.lines().collect();
+    packed_lines.sort();
+    unpacked_lines.sort();
+    assert!(packed_lines == unpacked_lines);
+
+    let packed = run_skani(&["search", "-d", "./tests/results/test_packed_dir", "./test_files/e.coli-W.fasta.gz", "--keep-refs"], false);
+    let mut packed_lines: Vec<&str> = packed.lines().collect();
+    packed_lines.sort();
+    assert!(packed_lines == unpacked_lines);
+
+    let out_line = run_skani(&["inspect", "./tests/results/test_packed_dir"], false);
+    assert!(out_line.contains("sketches.db:e.coli-W.fasta.gz.sketch"));
+    assert!(out_line.contains("N50: 92596"));
+}
+
+#[test]
+#[serial]
+fn fast_test_sketch_append(){
+    for (dir, packed) in [("./tests/results/test_append_dir", false), ("./tests/results/test_append_packed_dir", true)]{
+        let _ = std::fs::remove_dir_all(dir);
+        let mut args = vec!["sketch", "./test_files/o157_plasmid.fasta", "-o", dir];
+        if packed{
+            args.push("--packed");
+        }
+        run_skani(&args, true);
+        let err = run_skani(&["sketch", "./test_files/e.coli-W.fasta.gz", "-o", dir, "--append"], true);
+        assert!(err.contains("1 new sketches added"));
+
+        let out_line = run_skani(&["inspect", "--json", &format!("{}/markers.bin", dir)], false);
+        let json: serde_json::Value = serde_json::from_str(&out_line).unwrap();
+        assert!(json["files"][0]["num_genomes"] == 2);
+
+        let err = run_skani(&["sketch", "./test_files/e.coli-W.fasta.gz", "-o", dir, "--append"], true);
+        assert!(err.contains("already in"));
+        let out_line = run_skani(&["inspect", "--json", &format!("{}/markers.bin", dir)], false);
+        let json: serde_json::Value = serde_json::from_str(&out_line).unwrap();
+        assert!(json["files"][0]["num_genomes"] == 2);
+
+        let mut cmd = Command::cargo_bin("skani").unwrap();
+        cmd.args(["sketch", "./test_files/viruses.fna", "-o", dir, "--append", "-c", "200"]).assert().failure();
+
+        let out_line = run_skani(&["search", "-d", dir, "./test_files/e.coli-W.fasta.gz"], false);
+        assert!(out_line.contains("e.coli-W.fasta.gz"));
+    }
+
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["sketch", "./test_files/o157_plasmid.fasta", "-o", "./tests/results/test_append_dir"]).assert().failure();
+}
+
+#[test]
+#[serial]
+fn fast_test_merge_subset_remove(){
+    for dir in ["test_db_a", "test_db_b", "test_db_c200", "test_db_merged", "test_db_merged_packed", "test_db_subset"]{
+        let _ = std::fs::remove_dir_all(format!("./tests/results/{}", dir));
+    }
+    run_skani(&["sketch", "./test_files/o157_plasmid.fasta", "./test_files/e.coli-W.fasta.gz", "-o", "./tests/results/test_db_a"], true);
+    run_skani(&["sketch", "./test_files/viruses.fna", "./test_files/e.coli-W.fasta.gz", "-o", "./tests/results/test_db_b", "--packed"], true);
+    run_skani(&["sketch", "./test_files/viruses.fna", "-o", "./tests/results/test_db_c200", "-c", "200"], true);
+
+    let num_genomes = |dir: &str| -> u64 {
+        let out_line = run_skani(&["inspect", "--json", &format!("./tests/results/{}/markers.bin", dir)], false);
+        let json: serde_json::Value = serde_json::from_str(&out_line).unwrap();
+        json["files"][0]["num_genomes"].as_u64().unwrap()
+    };
+
+    let err = run_skani(&["merge", "./tests/results/test_db_a", "./tests/results/test_db_b", "-o", "./tests/results/test_db_merged"], true);
+    assert!(err.contains("already in the merged database"));
+    assert!(num_genomes("test_db_merged") == 3);
+    run_skani(&["merge", "./tests/results/test_db_a", "./tests/results/test_db_b", "-o", "./tests/results/test_db_merged_packed", "--packed"], true);
+    assert!(num_genomes("test_db_merged_packed") == 3);
+    assert!(Path::new("./tests/results/test_db_merged_packed/sketches.db").exists());
+
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["merge", "./tests/results/test_db_a", "./tests/results/test_db_c200", "-o", "./tests/results/test_db_bad"]).assert().failure();
+    assert!(!Path::new("./tests/results/test_db_bad").exists());
+
+    run_skani(&["subset", "./tests/results/test_db_merged_packed", "o157_plasmid.fasta", "viruses.fna.sketch", "-o", "./tests/results/test_db_subset"], true);
+    assert!(num_genomes("test_db_subset") == 2);
+    let out_line = run_skani(&["search", "-d", "./tests/results/test_db_subset", "./test_files/o157_plasmid.fasta"], false);
+    assert!(out_line.contains("o157_plasmid.fasta"));
+
+    for dir in ["test_db_merged", "test_db_merged_packed"]{
+        let dir = format!("./tests/results/{}", dir);
+        let err = run_skani(&["remove", &dir, "./test_files/e.coli-W.fasta.gz", "not_a_genome.fa"], true);
+        assert!(err.contains("not_a_genome.fa not found"));
+        let out_line = run_skani(&["search", "-d", &dir, "./test_files/e.coli-W.fasta.gz"], false);
+        assert!(!out_line.lines().any(|x| x.starts_with("./test_files/e.coli-W.fasta.gz")));
+        let out_line = run_skani(&["search", "-d", &dir, "./test_files/o157_plasmid.fasta"], false);
+        assert!(out_line.contains("o157_plasmid.fasta"));
+    }
+    assert!(num_genomes("test_db_merged") == 2);
+    assert!(num_genomes("test_db_merged_packed") == 2);
+    assert!(!Path::new("./tests/results/test_db_merged/e.coli-W.fasta.gz.sketch").exists());
+}
+
+#[test]
+#[serial]
+fn fast_test_marker_index(){
+    let _ = std::fs::remove_dir_all("./tests/results/test_marker_index_dir");
+    run_skani(&["sketch", "./test_files/o157_plasmid.fasta", "./test_files/e.coli-W.fasta.gz", "./test_files/viruses.fna", "-o", "./tests/results/test_marker_index_dir", "--marker-index"], true);
+    assert!(Path::new("./tests/results/test_marker_index_dir/markers.index").exists());
+
+    let err = run_skani(&["search", "-d", "./tests/results/test_marker_index_dir", "./test_files/e.coli-W.fasta.gz", "--qi"], true);
+    assert!(err.contains("using stored marker index"));
+    let stored = run_skani(&["search", "-d", "./tests/results/test_marker_index_dir", "./test_files/e.coli-W.fasta.gz", "--qi"], false);
+    std::fs::remove_file("./tests/results/test_marker_index_dir/markers.index").unwrap();
+    let built = run_skani(&["search", "-d", "./tests/results/test_marker_index_dir", "./test_files/e.coli-W.fasta.gz", "--qi"], false);
+    let mut stored_lines: Vec<&str> = stored.lines().collect();
+    let mut built_lines: Vec<&str> = built.lines().collect();
+    stored_lines.sort();
+    built_lines.sort();
+    assert!(stored_lines.len() > 1);
+    assert!(stored_lines == built_lines);
+
+    let _ = std::fs::remove_dir_all("./tests/results/test_marker_index_dir");
+    run_skani(&["sketch", "./test_files/o157_plasmid.fasta", "-o", "./tests/results/test_marker_index_dir", "--marker-index"], true);
+    run_skani(&["sketch", "./test_files/e.coli-W.fasta.gz", "-o", "./tests/results/test_marker_index_dir", "--append"], true);
+    let err = run_skani(&["search", "-d", "./tests/results/test_marker_index_dir", "./test_files/e.coli-W.fasta.gz", "--qi"], true);
+    assert!(err.contains("using stored marker index"));
+    let out_line = run_skani(&["search", "-d", "./tests/results/test_marker_index_dir", "./test_files/e.coli-W.fasta.gz", "--qi"], false);
+    assert!(out_line.lines().any(|x| x.starts_with("./test_files/e.coli-W.fasta.gz")));
+}
+
+#[test]
+#[serial]
+fn fast_test_search_cache(){
+    let _ = std::fs::remove_dir_all("./tests/results/test_cache_dir");
+    run_skani(&["sketch", "./test_files/o157_plasmid.fasta", "./test_files/e.coli-W.fasta.gz", "-o", "./tests/results/test_cache_dir"], true);
+    let queries = ["./test_files/e.coli-W.fasta.gz", "./test_files/e.coli-W.fasta.gz", "./test_files/o157_plasmid.fasta"];
+
+    let mut args = vec!["search", "-d", "./tests/results/test_cache_dir"];
+    args.extend(queries.iter());
+    let uncached = run_skani(&args, false);
+    args.push("--keep-refs");
+    let err = run_skani(&args, true);
+    assert!(err.contains("Reference cache:"));
+    assert!(err.contains("0 evictions"));
+    let cached = run_skani(&args, false);
+    args.pop();
+    args.extend(["--cache-mem", "0.000001"].iter());
+    let small_cache = run_skani(&args, false);
+
+    let sorted = |x: &str| { let mut lines: Vec<String> = x.lines().map(|x| x.to_string()).collect(); lines.sort(); lines };
+    assert!(sorted(&uncached).len() > 2);
+    assert!(sorted(&uncached) == sorted(&cached));
+    assert!(sorted(&uncached) == sorted(&small_cache));
+}
+
+#[test]
+#[serial]
+fn fast_test_same_basename(){
+    let input_dir = "./tests/results/test_same_basename_inputs";
+    let _ = std::fs::remove_dir_all(input_dir);
+    std::fs::create_dir_all(format!("{}/sampleA", input_dir)).unwrap();
+    std::fs::create_dir_all(format!("{}/sampleB", input_dir)).unwrap();
+    std::fs::create_dir_all(format!("{}/sampleC", input_dir)).unwrap();
+    std::fs::copy("./test_files/o157_plasmid.fasta", format!("{}/sampleA/bin.1.fa", input_dir)).unwrap();
+    std::fs::copy("./test_files/e.coli-W.fasta.gz", format!("{}/sampleB/bin.1.fa", input_dir)).unwrap();
+    std::fs::copy("./test_files/viruses.fna", format!("{}/sampleC/bin.1.fa", input_dir)).unwrap();
+    let file_a = format!("{}/sampleA/bin.1.fa", input_dir);
+    let file_b = format!("{}/sampleB/bin.1.fa", input_dir);
+    let file_c = format!("{}/sampleC/bin.1.fa", input_dir);
+
+    for (dir, packed) in [("./tests/results/test_same_basename_dir", false), ("./tests/results/test_same_basename_packed_dir", true)]{
+        let _ = std::fs::remove_dir_all(dir);
+        let mut args = vec!["sketch", &file_a, &file_b, "-o", dir];
+        if packed{
+            args.push("--packed");
+        }
+        run_skani(&args, true);
+        let err = run_skani(&["sketch", &file_c, "-o", dir, "--append"], true);
+        assert!(err.contains("1 new sketches added"));
+
+        let manifest = std::fs::read_to_string(format!("{}/manifest.tsv", dir)).unwrap();
+        assert!(manifest.lines().count() == 4);
+        assert!(manifest.contains(&format!("{}\tbin.1.fa.sketch", file_a)));
+        assert!(manifest.contains(&format!("{}\tsampleB_bin.1.fa.sketch", file_b)));
+        assert!(manifest.contains(&format!("{}\tsampleC_bin.1.fa.sketch", file_c)));
+        if !packed{
+            assert!(Path::new(&format!("{}/sampleB_bin.1.fa.sketch", dir)).exists());
+        }
+
+        let out_line = run_skani(&["search", "-d", dir, "./test_files/e.coli-W.fasta.gz", "./test_files/o157_plasmid.fasta"], false);
+        assert!(out_line.lines().any(|x| x.starts_with(&format!("{}\t./test_files/e.coli-W.fasta.gz", file_b))));
+        assert!(out_line.lines().any(|x| x.starts_with(&format!("{}\t./test_files/o157_plasmid.fasta", file_a))));
+        assert!(!out_line.lines().any(|x| x.starts_with(&format!("{}\t./test_files/o157_plasmid.fasta", file_b))));
+
+        run_skani(&["remove", dir, &file_b], true);
+        let manifest = std::fs::read_to_string(format!("{}/manifest.tsv", dir)).unwrap();
+        assert!(manifest.lines().count() == 3);
+        let out_line = run_skani(&["search", "-d", dir, "./test_files/e.coli-W.fasta.gz"], false);
+        assert!(!out_line.contains(&file_b));
+    }
+}
+
+#[test]
+#[serial]
+fn fast_test_individual_contig_sketch(){
+    let dir = "./tests/results/test_individual_contig_dir";
+    let _ = std::fs::remove_dir_all(dir);
+    run_skani(&["sketch", "-i", "./test_files/o157_plasmid.fasta", "-o", dir], true);
+    assert!(Path::new(&format!("{}/0_o157_plasmid.fasta.sketch", dir)).exists());
+    //Sketches of single contigs are not listed in the manifest.
+    let manifest = std::fs::read_to_string(format!("{}/manifest.tsv", dir)).unwrap();
+    assert!(manifest.lines().count() == 1);
+}
+
+#[test]
+#[serial]
+fn fast_test_genome_groups(){
+    let groups_file = "./tests/results/test_groups.tsv";
+    std::fs::create_dir_all("./tests/results").unwrap();
+    std::fs::write(groups_file, "W_with_plasmid\t./test_files/e.coli-W.fasta.gz\nplasmid\t./test_files/o157_plasmid.fasta\nW_with_plasmid\t./test_files/o157_plasmid.fasta\n").unwrap();
+
+    let out_line = run_skani(&["dist", "--qg", groups_file, "-r", "./test_files/e.coli-W.fasta.gz"], false);
+    assert!(out_line.lines().any(|x| x.starts_with("./test_files/e.coli-W.fasta.gz\tW_with_plasmid\t")));
+    assert!(!out_line.contains("./test_files/o157_plasmid.fasta"));
+
+    let out_line = run_skani(&["triangle", "--groups", groups_file, "-E"], false);
+    assert!(out_line.lines().any(|x| x.starts_with("plasmid\tW_with_plasmid\t") || x.starts_with("W_with_plasmid\tplasmid\t")));
+
+    let dir = "./tests/results/test_groups_dir";
+    let _ = std::fs::remove_dir_all(dir);
+    run_skani(&["sketch", "--groups", groups_file, "-o", dir], true);
+    let manifest = std::fs::read_to_string(format!("{}/manifest.tsv", dir)).unwrap();
+    assert!(manifest.contains("W_with_plasmid\tW_with_plasmid.sketch"));
+    let out_line = run_skani(&["search", "-d", dir, "./test_files/o157_plasmid.fasta"], false);
+    assert!(out_line.lines().any(|x| x.starts_with("W_with_plasmid\t")));
+
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["dist", "--qg", groups_file, "--qi", "-r", "./test_files/e.coli-W.fasta.gz"]).assert().failure();
+}
+
+fn write_catalog(file: &str){
+    //Two viruses and the o157 plasmid in two records, as one multi-genome fasta.
+    let viruses = std::fs::read_to_string("./test_files/viruses.fna").unwrap();
+    let plasmid = std::fs::read_to_string("./test_files/o157_plasmid.fasta").unwrap();
+    let plasmid_seq = plasmid.lines().skip(1).collect::<String>();
+    let half = plasmid_seq.len() / 2;
+    let mut catalog = String::new();
+    for (i, record) in viruses.split('>').skip(1).take(2).enumerate(){
+        let (_, seq) = record.split_once('\n').unwrap();
+        catalog.push_str(&format!(">virus{}|contig_1 some description\n{}", i, seq));
+    }
+    catalog.push_str(&format!(">plasmid|contig_1\n{}\n>plasmid|contig_2\n{}\n", &plasmid_seq[..half], &plasmid_seq[half..]));
+    std::fs::write(file, catalog).unwrap();
+}
+
+#[test]
+#[serial]
+fn fast_test_genome_split(){
+    std::fs::create_dir_all("./tests/results").unwrap();
+    let catalog = "./tests/results/test_catalog.fa";
+    write_catalog(catalog);
+
+    let out_line = run_skani(&["dist", "-q", catalog, "-r", "./test_files/o157_plasmid.fasta", "--split-sep", "|"], false);
+    let results = get_result_from_out(&out_line);
+    assert!(out_line.lines().any(|x| x.starts_with("./test_files/o157_plasmid.fasta\tplasmid\t")));
+    assert!(results.iter().all(|x| x.ani > 99.9));
+    let regex_out = run_skani(&["dist", "-q", catalog, "-r", "./test_files/o157_plasmid.fasta", "--split-regex", "^([^|]+)\\|"], false);
+    assert!(regex_out == out_line);
+
+    let out_line = run_skani(&["triangle", catalog, "--split-sep", "|", "-E"], false);
+    assert!(out_line.contains("virus0") && out_line.contains("virus1"));
+    assert!(!out_line.contains("plasmid"));
+
+    let dir = "./tests/results/test_split_dir";
+    let _ = std::fs::remove_dir_all(dir);
+    run_skani(&["sketch", catalog, "-o", dir, "--split-sep", "|"], true);
+    let manifest = std::fs::read_to_string(format!("{}/manifest.tsv", dir)).unwrap();
+    assert!(manifest.lines().count() == 4);
+    assert!(manifest.contains("plasmid\tplasmid.sketch"));
+    let out_line = run_skani(&["search", "-d", dir, "./test_files/o157_plasmid.fasta"], false);
+    assert!(out_line.lines().any(|x| x.starts_with("plasmid\t./test_files/o157_plasmid.fasta")));
+    let out_line = run_skani(&["search", "-d", dir, catalog, "--split-sep", "|"], false);
+    assert!(out_line.lines().any(|x| x.starts_with("virus0\tvirus0\t")));
+
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["dist", "-q", catalog, "-r", catalog, "--split-sep", "|", "--qi"]).assert().failure();
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["dist", "-q", catalog, "-r", catalog, "--split-regex", "(", ]).assert().failure();
+}
+
+fn run_skani_stdin(args: &[&str], stdin: Vec<u8>) -> String{
+    let out = assert_cmd::Command::cargo_bin("skani").unwrap().args(args).write_stdin(stdin).output().unwrap();
+    std::str::from_utf8(&out.stdout).unwrap().to_string()
+}
+
+#[test]
+#[serial]
+fn fast_test_stdin(){
+    let gzipped = std::fs::read("./test_files/e.coli-W.fasta.gz").unwrap();
+    let plasmid = std::fs::read("./test_files/o157_plasmid.fasta").unwrap();
+
+    let from_file = get_result_from_out(&run_skani(&["dist", "./test_files/e.coli-W.fasta.gz", "./test_files/e.coli-W.fasta.gz"], false));
+    let out_line = run_skani_stdin(&["dist", "-", "./test_files/e.coli-W.fasta.gz"], gzipped.clone());
+    assert!(out_line.lines().nth(1).unwrap().starts_with("./test_files/e.coli-W.fasta.gz\t-\t"));
+    let from_stdin = get_result_from_out(&out_line);
+    assert!(from_stdin.len() == 1);
+    assert!(from_stdin[0].ani == from_file[0].ani);
+
+    let dir = "./tests/results/test_stdin_dir";
+    let _ = std::fs::remove_dir_all(dir);
+    run_skani(&["sketch", "./test_files/o157_plasmid.fasta", "./test_files/e.coli-W.fasta.gz", "-o", dir], true);
+    let out_line = run_skani_stdin(&["search", "-d", dir, "-"], plasmid.clone());
+    assert!(out_line.lines().any(|x| x.starts_with("./test_files/o157_plasmid.fasta\t-\t")));
+
+    let dir = "./tests/results/test_stdin_sketch_dir";
+    let _ = std::fs::remove_dir_all(dir);
+    assert_cmd::Command::cargo_bin("skani").unwrap().args(["sketch", "-", "-o", dir]).write_stdin(plasmid).assert().success();
+    let out_line = run_skani(&["search", "-d", dir, "./test_files/o157_plasmid.fasta"], false);
+    assert!(out_line.lines().any(|x| x.starts_with("-\t./test_files/o157_plasmid.fasta\t")));
+
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["dist", "-q", "-", "-r", "-"]).assert().failure();
+}
+
+#[test]
+#[serial]
+fn fast_test_large_k(){
+    let dir = "./tests/results/test_large_k_dir";
+    let _ = std::fs::remove_dir_all(dir);
+    run_skani(&["sketch", "-k", "19", "./test_files/o157_plasmid.fasta", "./test_files/e.coli-W.fasta.gz", "-o", dir], true);
+    let out_line = run_skani(&["inspect", dir], false);
+    assert!(out_line.contains("k = 19"));
+    let results = get_result_from_out(&run_skani(&["search", "-d", dir, "./test_files/o157_plasmid.fasta"], false));
+    assert!(results.len() == 1);
+    assert!(results[0].ani == 100.0);
+
+    let results = get_result_from_out(&run_skani(&["dist", "-k", "19", "./test_files/e.coli-W.fasta.gz", "./test_files/o157_plasmid.fasta"], false));
+    assert!(results.len() <= 1);
+
+    //Default k fasta references are sketched again with the query sketch's k.
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    let out = cmd.args(["dist", "-r", "./test_files/e.coli-W.fasta.gz", "-q", &format!("{}/e.coli-W.fasta.gz.sketch", dir)]).assert().success();
+    let results = get_result_from_out(std::str::from_utf8(&out.get_output().stdout).unwrap());
+    assert!(results.len() == 1);
+    assert!(results[0].ani == 100.0);
+
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["dist", "-k", "22", "./test_files/o157_plasmid.fasta", "./test_files/o157_plasmid.fasta"]).assert().failure();
+}
+
+#[test]
+#[serial]
+fn fast_test_syncmers(){
+    let dir = "./tests/results/test_syncmers_dir";
+    let _ = std::fs::remove_dir_all(dir);
+    run_skani(&["sketch", "--syncmers", "closed", "--sync-s", "10", "./test_files/o157_plasmid.fasta", "./test_files/e.coli-W.fasta.gz", "-o", dir], true);
+    let out_line = run_skani(&["inspect", dir], false);
+    assert!(out_line.contains("Syncmers: closed, s = 10"));
+    let results = get_result_from_out(&run_skani(&["search", "-d", dir, "./test_files/e.coli-W.fasta.gz"], false));
+    assert!(results.len() == 1);
+    assert!(results[0].ani == 100.0);
+
+    let results = get_result_from_out(&run_skani(&["dist", "--syncmers", "open", "./test_files/e.coli-W.fasta.gz", "./test_files/e.coli-W.fasta.gz"], false));
+    assert!(results.len() == 1);
+
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["dist", "--sync-s", "10", "./test_files/o157_plasmid.fasta", "./test_files/o157_plasmid.fasta"]).assert().failure();
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["dist", "--syncmers", "open", "--sync-s", "15", "./test_files/o157_plasmid.fasta", "./test_files/o157_plasmid.fasta"]).assert().failure();
+}
+
+#[test]
+#[serial]
+fn fast_test_seed_pattern(){
+    let dir = "./tests/results/test_seed_pattern_dir";
+    let _ = std::fs::remove_dir_all(dir);
+    run_skani(&["sketch", "--seed-pattern", "1101101101101111", "./test_files/o157_plasmid.fasta", "./test_files/e.coli-W.fasta.gz", "-o", dir], true);
+    let out_line = run_skani(&["inspect", dir], false);
+    assert!(out_line.contains("k = 16"));
+    assert!(out_line.contains("Seed pattern: 1101101101101111 (weight 12)"));
+    let results = get_result_from_out(&run_skani(&["search", "-d", dir, "./test_files/e.coli-W.fasta.gz"], false));
+    assert!(results.len() == 1);
+    assert!(results[0].ani == 100.0);
+
+    let results = get_result_from_out(&run_skani(&["dist", "--seed-pattern", "11011011011011011011", "./test_files/e.coli-W.fasta.gz", "./test_files/e.coli-W.fasta.gz"], false));
+    assert!(results.len() == 1);
+    assert!(results[0].ani == 100.0);
+
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["dist", "--seed-pattern", "0110111", "./test_files/o157_plasmid.fasta", "./test_files/o157_plasmid.fasta"]).assert().failure();
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["dist", "--seed-pattern", "1101111", "--syncmers", "open", "./test_files/o157_plasmid.fasta", "./test_files/o157_plasmid.fasta"]).assert().failure();
+}
+
+#[test]
+#[serial]
+fn fast_test_mask_lowercase(){
+    std::fs::create_dir_all("./tests/results").unwrap();
+    let masked_file = "./tests/results/o157_plasmid_soft_masked.fasta";
+    let fasta = std::fs::read_to_string("./test_files/o157_plasmid.fasta").unwrap();
+    let mut masked = String::new();
+    for (i, line) in fasta.lines().enumerate(){
+        if i > 0 && i <= 300{
+            masked += &line.to_lowercase();
+        }
+        else{
+            masked += line;
+        }
+        masked += "\n";
+    }
+    std::fs::write(masked_file, masked).unwrap();
+
+    let dir = "./tests/results/test_mask_lowercase_dir";
+    let _ = std::fs::remove_dir_all(dir);
+    run_skani(&["sketch", "--mask-lowercase", masked_file, "-o", dir], true);
+    let out_line = run_skani(&["inspect", dir], false);
+    assert!(out_line.contains("Soft-masked (lowercase) bases are skipped"));
+    assert!(out_line.contains("Masked bases: 24000"));
+
+    let results = get_result_from_out(&run_skani(&["dist", "--mask-lowercase", masked_file, masked_file], false));
+    assert!(results.len() == 1);
+    assert!(results[0].ani == 100.0);
+    assert!(results[0].align_fraction_query > 95.0);
+
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["dist", "-a", "--mask-lowercase", masked_file, masked_file]).assert().failure();
+}
+
+#[test]
+#[serial]
+fn fast_test_dust(){
+    std::fs::create_dir_all("./tests/results").unwrap();
+    let dir = "./tests/results/test_dust_dir";
+    let _ = std::fs::remove_dir_all(dir);
+    run_skani(&["sketch", "--dust", "--dust-level", "12", "./test_files/o157_plasmid.fasta", "-o", dir], true);
+    let out_line = run_skani(&["inspect", dir], false);
+    assert!(out_line.contains("Low-complexity masking: DUST level 12"));
+
+    let results = get_result_from_out(&run_skani(&["dist", "--small-genomes", "--dust", "./test_files/o157_plasmid.fasta", "./test_files/o157_plasmid.fasta"], false));
+    assert!(results.len() == 1);
+    assert!(results[0].ani == 100.0);
+
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["dist", "--dust-level", "12", "./test_files/o157_plasmid.fasta", "./test_files/o157_plasmid.fasta"]).assert().failure();
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["dist", "-a", "--dust", "./test_files/o157_plasmid.fasta", "./test_files/o157_plasmid.fasta"]).assert().failure();
+}
+
+#[test]
+#[serial]
+fn fast_test_resketch(){
+    std::fs::create_dir_all("./tests/results").unwrap();
+    for dir in ["test_resketch_fine", "test_resketch_coarse", "test_resketch_direct", "test_resketch_packed"]{
+        let _ = std::fs::remove_dir_all(format!("./tests/results/{}", dir));
+    }
+    let _ = std::fs::remove_file("./tests/results/test_resketch_single.sketch");
+    run_skani(&["sketch", "-c", "30", "-m", "200", "./test_files/e.coli-W.fasta.gz", "./test_files/o157_plasmid.fasta", "-o", "./tests/results/test_resketch_fine"], true);
+    run_skani(&["sketch", "./test_files/e.coli-W.fasta.gz", "./test_files/o157_plasmid.fasta", "-o", "./tests/results/test_resketch_direct"], true);
+    run_skani(&["resketch", "./tests/results/test_resketch_fine", "-c", "125", "-m", "1000", "-o", "./tests/results/test_resketch_coarse"], true);
+    run_skani(&["resketch", "./tests/results/test_resketch_fine", "-c", "125", "-o", "./tests/results/test_resketch_packed", "--packed"], true);
+    run_skani(&["resketch", "./tests/results/test_resketch_fine/o157_plasmid.fasta.sketch", "-c", "125", "-o", "./tests/results/test_resketch_single.sketch"], true);
+
+    //Resketched and directly sketched databases have the same seeds and markers.
+    let inspect = |file: &str| -> serde_json::Value {
+        let out_line = run_skani(&["inspect", "--json", file], false);
+        let json: serde_json::Value = serde_json::from_str(&out_line).unwrap();
+        json["files"][0].clone()
+    };
+    for genome in ["e.coli-W.fasta.gz", "o157_plasmid.fasta"]{
+        let coarse = inspect(&format!("./tests/results/test_resketch_coarse/{}.sketch", genome));
+        let direct = inspect(&format!("./tests/results/test_resketch_direct/{}.sketch", genome));
+        assert!(coarse["c"] == 125);
+        assert!(coarse["genomes"] == direct["genomes"]);
+    }
+    let single = inspect("./tests/results/test_resketch_single.sketch");
+    assert!(single["c"] == 125);
+    assert!(single["marker_c"] == 200);
+    let out_line = run_skani(&["search", "-d", "./tests/results/test_resketch_packed", "./test_files/o157_plasmid.fasta"], false);
+    assert!(out_line.contains("o157_plasmid.fasta"));
+
+    //dist downsamples sketches with different c instead of failing.
+    let results = get_result_from_out(&run_skani(&["dist", "-q", "./tests/results/test_resketch_fine/e.coli-W.fasta.gz.sketch", "-r", "./tests/results/test_resketch_direct/e.coli-W.fasta.gz.sketch"], false));
+    let direct_results = get_result_from_out(&run_skani(&["dist", "-q", "./tests/results/test_resketch_direct/e.coli-W.fasta.gz.sketch", "-r", "./tests/results/test_resketch_direct/e.coli-W.fasta.gz.sketch"], false));
+    assert!(results.len() == 1);
+    assert!(results[0].ani == direct_results[0].ani);
+    assert!(results[0].align_fraction_query == direct_results[0].align_fraction_query);
+
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["resketch", "./tests/results/test_resketch_direct", "-c", "30", "-o", "./tests/results/test_resketch_bad"]).assert().failure();
+    assert!(!Path::new("./tests/results/test_resketch_bad").exists());
+}
+
+#[test]
+#[serial]
+fn fast_test_reads(){
+    std::fs::create_dir_all("./tests/results").unwrap();
+    let reads_file = "./tests/results/o157_plasmid_reads.fq";
+    let fasta = std::fs::read_to_string("./test_files/o157_plasmid.fasta").unwrap();
+    let genome = fasta.lines().skip(1).collect::<String>().into_bytes();
+    //20x error-free 150 bp reads.
+    let mut fastq = String::new();
+    let mut state: u64 = 29;
+    for i in 0..20 * genome.len() / 150{
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let start = (state >> 33) as usize % (genome.len() - 150);
+        fastq += &format!("@read{}\n{}\n+\n{}\n", i, std::str::from_utf8(&genome[start..start + 150]).unwrap(), "I".repeat(150));
+    }
+    //A trimmed read shorter than k.
+    fastq += &format!("@short\n{}\n+\n{}\n", std::str::from_utf8(&genome[..10]).unwrap(), "I".repeat(10));
+    std::fs::write(reads_file, fastq).unwrap();
+
+    let out_line = run_skani(&["dist", "--reads", "-q", reads_file, "-r", "./test_files/o157_plasmid.fasta", "./test_files/e.coli-W.fasta.gz"], false);
+    assert!(out_line.lines().next().unwrap().ends_with("\tEffective_coverage"));
+    let results = get_result_from_out(&out_line);
+    assert!(results.len() == 1);
+    assert!(results[0].ref_file == "./test_files/o157_plasmid.fasta");
+    assert!(results[0].ani > 99.9);
+    let coverage = out_line.lines().nth(1).unwrap().split('\t').last().unwrap().parse::<f32>().unwrap();
+    assert!(coverage > 10. && coverage < 20.);
+
+    let out_line = run_skani(&["dist", "--reads", "--min-count", "3", "-q", reads_file, "-r", "./test_files/o157_plasmid.fasta"], false);
+    assert!(get_result_from_out(&out_line)[0].ani > 99.9);
+
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["dist", "--min-count", "3", "-q", reads_file, "-r", "./test_files/o157_plasmid.fasta"]).assert().failure();
+    let mut cmd = Command::cargo_bin("skani").unwrap();
+    cmd.args(["dist", "--reads", "-q", "./test_files/e.coli-o157.fasta.sketch", "-r", "./test_files/o157_plasmid.fasta"]).assert().failure();
+}
+
+#[test]
+#[serial]
+fn fast_test_ambiguous_bases(){
+    std::fs::create_dir_all("./tests/results").unwrap();
+    let ambiguous_file = "./tests/results/o157_plasmid_ambiguous.fasta";
+    let mut fasta = std::fs::read("./test_files/o157_plasmid.fasta").unwrap();
+    let start = fasta.iter().position(|x| *x == b'\n').unwrap() + 1;
+    for (i, code) in b"RYN".iter().enumerate(){
+        fasta[start + 10_000 * (i + 1)] = *code;
+    }
+    std::fs::write(ambiguous_file, fasta).unwrap();
+
+    let dir = "./tests/results/test_ambiguous_bases_dir";
+    let _ = std::fs::remove_dir_all(dir);
+    run_skani(&["sketch", ambiguous_file, "-o", dir], true);
+    let out_line = run_skani(&["inspect", dir], false);
+    assert!(out_line.contains("Ambiguous (non-ACGT) bases: 3"));
+
+    let out_line = run_skani(&["dist", "--detailed", ambiguous_file, "./test_files/o157_plasmid.fasta"], false);
+    let lines = out_line.lines().collect::<Vec<&str>>();
+    assert!(lines[0].ends_with("Ref_ambiguous_bases\tQuery_ambiguous_bases"));
+    assert!(lines[1].ends_with("\t0\t3"));
+}
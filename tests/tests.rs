@@ -5,6 +5,32 @@ use skani::regression::*;
 use skani::file_io::*;
 use skani::params::*;
 use skani::types::*;
+
+///Next state of a 64-bit linear congruential generator.
+fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+    *state
+}
+
+///Random ACGT sequence of `len` bases; the same `seed` gives the same sequence.
+fn random_seq(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed;
+    (0..len).map(|_| b"ACGT"[(next_random(&mut state) >> 62) as usize]).collect()
+}
+
+///Path of `name` in the temp directory, unique to this test process so
+///that concurrent test runs do not overwrite each other's files.
+fn temp_path(name: &str) -> String {
+    std::env::temp_dir().join(format!("skani_{}_{}", std::process::id(), name)).to_str().unwrap().to_string()
+}
+
+///Writes `seq` as a one-record fasta to `temp_path(name)` and returns the path.
+fn write_temp_fasta(name: &str, seq: &[u8]) -> String {
+    let file = temp_path(name);
+    std::fs::write(&file, [b">", name.as_bytes(), b"\n", seq, b"\n"].concat()).unwrap();
+    file
+}
+
 fn default_params(mode: Mode) -> (CommandParams, SketchParams) {
     let cmd_params = CommandParams {
         screen: false,
@@ -25,14 +51,25 @@ fn default_params(mode: Mode) -> (CommandParams, SketchParams) {
         individual_contig_r: false,
         min_aligned_frac: 0.15,
         keep_refs: false,
+        cache_mem: 0,
         est_ci: false,
         learned_ani: true,
         detailed_out: false,
         distance: false,
-        rescue_small: true
+        rescue_small: true,
+        threads: 3,
+        json_out: false,
+        packed: false,
+        append: false,
+        marker_index: false,
+        ref_groups: vec![],
+        query_groups: vec![],
+        genome_split: None,
+        reads_q: false,
+        min_kmer_count: 0,
     };
 
-    let sketch_params = SketchParams::new(1000, 125, 15, false, false);
+    let sketch_params = SketchParams::new(1000, 125, 15, false, false).unwrap();
     return (cmd_params, sketch_params);
 }
 
@@ -46,9 +83,9 @@ fn fast_ecoli_test_simple() {
         .query_files
         .push("./test_files/e.coli-W.fasta.gz".to_string());
 
-    let ref_sketch = fastx_to_sketches(&command_params.ref_files, &sketch_params, true)[0].clone();
+    let ref_sketch = fastx_to_sketches(&command_params.ref_files, &sketch_params, true).unwrap()[0].clone();
     let query_sketch =
-        fastx_to_sketches(&command_params.query_files, &sketch_params, true)[0].clone();
+        fastx_to_sketches(&command_params.query_files, &sketch_params, true).unwrap()[0].clone();
     let map_params = map_params_from_sketch(&ref_sketch, sketch_params.use_aa, &command_params, &None);
     let ani_res = chain_seeds(&ref_sketch, &query_sketch, map_params);
     assert!(ani_res.ani >= 1.0);
@@ -66,9 +103,9 @@ fn fast_ecoli_plasmid_test() {
         .query_files
         .push("./test_files/o157_plasmid.fasta".to_string());
 
-    let ref_sketch = sketches_from_sketch(&command_params.ref_files).1[0].clone();
+    let ref_sketch = sketches_from_sketch(&command_params.ref_files).unwrap().1[0].clone();
     let query_sketch =
-        fastx_to_sketches(&command_params.query_files, &sketch_params, true)[0].clone();
+        fastx_to_sketches(&command_params.query_files, &sketch_params, true).unwrap()[0].clone();
     let map_params = map_params_from_sketch(&ref_sketch, sketch_params.use_aa, &command_params, &None);
     let ani_res = chain_seeds(&ref_sketch, &query_sketch, map_params);
     assert!(ani_res.ani >= 1.0);
@@ -100,9 +137,9 @@ fn fast_eukaryote_test() {
         .query_files
         .push("./test_files/TOPAZ_RSS1_E007.fna.gz".to_string());
 
-    let ref_sketch = fastx_to_sketches(&command_params.ref_files, &sketch_params, true)[0].clone();
+    let ref_sketch = fastx_to_sketches(&command_params.ref_files, &sketch_params, true).unwrap()[0].clone();
     let query_sketch =
-        fastx_to_sketches(&command_params.query_files, &sketch_params, true)[0].clone();
+        fastx_to_sketches(&command_params.query_files, &sketch_params, true).unwrap()[0].clone();
     let map_params = map_params_from_sketch(&ref_sketch, sketch_params.use_aa, &command_params, &None);
     let ani_res = chain_seeds(&ref_sketch, &query_sketch, map_params);
 
@@ -152,3 +189,720 @@ fn fast_NNN_test_code(){
     fmh_seeds(str1, &sketch_params, 0, &mut new_sketch1, true);
     assert!(new_sketch1.kmer_seeds_k.unwrap().len() == 0);
 }
+
+fn sorted_seeds(sketch: &Sketch) -> Vec<(SeedBits, Vec<SeedPosition>)>{
+    let mut seeds = sketch.kmer_seeds_k.as_ref().unwrap().iter()
+        .map(|(kmer, positions)| {
+            let mut positions = positions.to_vec();
+            positions.sort();
+            (*kmer, positions)
+        })
+        .collect::<Vec<(SeedBits, Vec<SeedPosition>)>>();
+    seeds.sort();
+    seeds
+}
+
+#[test]
+fn fast_parallel_seeding_test(){
+    let (_command_params, mut sketch_params) = default_params(Mode::Dist);
+    sketch_params.c = 10;
+    let mut contigs = vec![];
+    for (contig_index, length) in [(0, 100_003), (1, 5_001), (2, 250_102)]{
+        let mut seq = random_seq(contig_index as u64 + 1, length);
+        for i in (0..length).filter(|i| i % 9_973 < 3){
+            seq[i] = b'N';
+        }
+        contigs.push((contig_index, seq));
+    }
+
+    let mut sequential = Sketch::default();
+    for (contig_index, seq) in contigs.iter(){
+        seed_contig(seq, &sketch_params, *contig_index, &mut sequential, true);
+    }
+    let contigs = contigs.iter().map(|(contig_index, seq)| (*contig_index, seq.as_slice())).collect::<Vec<(u32, &[u8])>>();
+    for window_length in [1_000, 4_999, SEED_WINDOW_LENGTH]{
+        let mut parallel = Sketch::default();
+        seed_contigs_parallel(&contigs, &sketch_params, &mut parallel, true, window_length);
+        assert!(parallel.marker_seeds == sequential.marker_seeds);
+        assert!(sorted_seeds(&parallel) == sorted_seeds(&sequential));
+    }
+}
+
+#[test]
+fn fast_in_memory_api_test(){
+    use skani::api::*;
+    use skani::error::SkaniError;
+    let file = "./test_files/e.coli-W.fasta.gz";
+    let mut records = vec![];
+    let mut reader = needletail::parse_fastx_file(file).unwrap();
+    while let Some(record) = reader.next() {
+        let record = record.unwrap();
+        records.push((String::from_utf8(record.id().to_vec()).unwrap(), record.seq().to_vec()));
+    }
+
+    let calculator = AniCalculator::builder().preset(Preset::Default).build().unwrap();
+    let sketch = calculator
+        .sketch("ecoli", records.iter().map(|x| (x.0.as_str(), x.1.as_slice())))
+        .unwrap();
+    let file_sketch = fastx_to_sketches(&vec![file.to_string()], calculator.sketch_params(), true).unwrap()[0].clone();
+    assert!(sketch.kmer_seeds_k == file_sketch.kmer_seeds_k);
+    assert!(sketch.marker_seeds == file_sketch.marker_seeds);
+    assert!(sketch.contigs == file_sketch.contigs);
+
+    let ani_res = calculator.ani(&sketch, &file_sketch).unwrap().unwrap();
+    assert!(ani_res.ani >= 0.999);
+    assert!(ani_res.align_fraction_query >= 0.99);
+
+    let anis = calculator.ani_many(&vec![sketch.clone()], &vec![file_sketch.clone(), sketch.clone()]).unwrap();
+    assert!(anis.len() == 2);
+
+    assert!(AniCalculator::builder().c(2000).marker_c(1000).build().is_err());
+    let fast = AniCalculator::builder().preset(Preset::Fast).build().unwrap();
+    assert!(fast.ani(&sketch, &file_sketch).is_err());
+    assert!(calculator.sketch("short", vec![("a", &b"ACGT"[..])]).is_err());
+
+    //Same c and k as the calculator, but with the 64-bit seed table.
+    let mut seed64_sketch = file_sketch.clone();
+    seed64_sketch.kmer_seeds_k64 = Some(seed64_sketch.kmer_seeds_k.take().unwrap().into_iter().map(|(kmer, positions)| (kmer as u64, positions)).collect());
+    assert!(matches!(calculator.ani(&sketch, &seed64_sketch), Err(SkaniError::SketchParamMismatch(_))));
+    assert!(calculator.ani_many(&vec![sketch.clone()], &vec![file_sketch.clone(), seed64_sketch]).is_err());
+    let large_k_params = SketchParams::new(calculator.sketch_params().marker_c, calculator.sketch_params().c, 19, false, false).unwrap();
+    let large_k_sketch = fastx_to_sketches(&vec![file.to_string()], &large_k_params, true).unwrap()[0].clone();
+    assert!(matches!(calculator.ani(&large_k_sketch, &sketch), Err(SkaniError::SketchParamMismatch(_))));
+    let mut masked_params = calculator.sketch_params().clone();
+    masked_params.set_mask_lowercase(true).unwrap();
+    let masked_sketch = fastx_to_sketches(&vec![file.to_string()], &masked_params, true).unwrap()[0].clone();
+    assert!(matches!(calculator.ani(&masked_sketch, &sketch), Err(SkaniError::SketchParamMismatch(_))));
+    let mut marker_c_params = calculator.sketch_params().clone();
+    marker_c_params.marker_c *= 2;
+    let marker_c_sketch = fastx_to_sketches(&vec![file.to_string()], &marker_c_params, true).unwrap()[0].clone();
+    assert!(matches!(calculator.ani(&marker_c_sketch, &sketch), Err(SkaniError::SketchParamMismatch(_))));
+}
+
+#[test]
+fn fast_memory_sink_test(){
+    use skani::sink::*;
+    let (mut command_params, sketch_params) = default_params(Mode::Dist);
+    command_params.ref_files.push("./test_files/e.coli-W.fasta.gz".to_string());
+    command_params.ref_files.push("./test_files/o157_plasmid.fasta".to_string());
+    command_params.query_files.push("./test_files/e.coli-W.fasta.gz".to_string());
+    let mut sink = MemorySink::new();
+    skani::dist::dist(command_params, sketch_params, &mut sink).unwrap();
+    assert!(sink.results.len() == 1);
+    assert!(sink.results[0].ani >= 0.999);
+
+    let (mut command_params, sketch_params) = default_params(Mode::Triangle);
+    command_params.ref_files.push("./test_files/e.coli-W.fasta.gz".to_string());
+    command_params.ref_files.push("./test_files/e.coli-W.fasta.gz".to_string());
+    command_params.screen = true;
+    let mut sink = MemorySink::new();
+    skani::triangle::triangle(command_params, sketch_params, &mut sink).unwrap();
+    assert!(sink.results.len() == 1);
+}
+
+#[test]
+fn fast_thread_pool_test(){
+    use skani::sink::*;
+    //The host application may already own the global pool.
+    let _ = rayon::ThreadPoolBuilder::new().num_threads(1).build_global();
+    let files = vec!["./test_files/e.coli-W.fasta.gz".to_string(), "./test_files/o157_plasmid.fasta".to_string()];
+    let mut results = vec![];
+    for threads in [1, 4] {
+        let (mut command_params, sketch_params) = default_params(Mode::Dist);
+        command_params.ref_files = files.clone();
+        command_params.query_files = files.clone();
+        command_params.threads = threads;
+        let mut sink = MemorySink::new();
+        skani::dist::dist(command_params, sketch_params, &mut sink).unwrap();
+        results.push(sink.results.len());
+    }
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+    let (mut command_params, sketch_params) = default_params(Mode::Dist);
+    command_params.ref_files = files.clone();
+    command_params.query_files = files.clone();
+    let mut sink = MemorySink::new();
+    skani::dist::dist_in_pool(&pool, command_params, sketch_params, &mut sink).unwrap();
+    results.push(sink.results.len());
+    assert!(results == vec![2, 2, 2]);
+}
+
+#[test]
+fn fast_sketch_format_test(){
+    use skani::format::*;
+    let (_, sketch_params) = default_params(Mode::Sketch);
+    let sketch = fastx_to_sketches(&vec!["./test_files/o157_plasmid.fasta".to_string()], &sketch_params, true).unwrap()[0].clone();
+    let sketch_file = temp_path("format_test.sketch");
+    let marker_file = temp_path("format_test_markers.bin");
+
+    write_sketch_file(&sketch_file, &sketch_params, &sketch).unwrap();
+    let (header, read_sketch) = read_sketch_file(&sketch_file).unwrap();
+    assert!(header.format_version == FORMAT_VERSION);
+    assert!(header.skani_version == VERSION);
+    assert!(header.sketch_params == sketch_params);
+    assert!(read_sketch == sketch);
+
+    write_marker_file(&marker_file, &sketch_params, &vec![Sketch::get_markers_only(&sketch)]).unwrap();
+    let (header, markers) = read_marker_file(&marker_file).unwrap();
+    assert!(header.num_sketches == 1);
+    assert!(markers[0].marker_seeds == sketch.marker_seeds);
+    assert!(read_sketch_file(&marker_file).is_err());
+
+    let mut bytes = std::fs::read(&sketch_file).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    std::fs::write(&sketch_file, &bytes).unwrap();
+    assert!(read_sketch_file(&sketch_file).is_err());
+
+    let (header, legacy_sketch) = read_sketch_file("./test_files/e.coli-o157.fasta.sketch").unwrap();
+    assert!(header.format_version == LEGACY_FORMAT_VERSION);
+    assert!(header.sketch_params.c == legacy_sketch.c);
+    assert!(header.sketch_params.marker_c == legacy_sketch.marker_c);
+}
+
+#[test]
+fn fast_large_k_test(){
+    use skani::format::*;
+    let (command_params, _) = default_params(Mode::Dist);
+    assert!(SketchParams::new(1000, 125, 22, false, false).is_err());
+    assert!(!SketchParams::new(1000, 125, 16, false, false).unwrap().use_seed64);
+    let sketch_params = SketchParams::new(1000, 125, 19, false, false).unwrap();
+    assert!(sketch_params.use_seed64);
+
+    let file = "./test_files/o157_plasmid.fasta".to_string();
+    let sketch = fastx_to_sketches(&vec![file.clone()], &sketch_params, true).unwrap()[0].clone();
+    assert!(sketch.kmer_seeds_k.is_none());
+    assert!(sketch.num_seeds().unwrap() > 0);
+    assert!(sketch.kmer_seeds_k64.as_ref().unwrap().keys().any(|x| *x > u32::MAX as u64));
+
+    let map_params = map_params_from_sketch(&sketch, false, &command_params, &None);
+    let ani_res = chain_seeds(&sketch, &sketch, map_params);
+    assert!(ani_res.ani >= 0.999);
+    assert!(ani_res.align_fraction_query >= 0.99);
+    //32-bit and 64-bit seeds share no anchors.
+    let default_sketch = fastx_to_sketches(&vec![file.clone()], &default_params(Mode::Dist).1, true).unwrap()[0].clone();
+    let map_params = map_params_from_sketch(&sketch, false, &command_params, &None);
+    assert!(chain_seeds(&sketch, &default_sketch, map_params).align_fraction_query == 0.);
+
+    let sketch_file = temp_path("large_k_test.sketch");
+    write_sketch_file(&sketch_file, &sketch_params, &sketch).unwrap();
+    let (header, read_sketch) = read_sketch_file(&sketch_file).unwrap();
+    assert!(header.sketch_params == sketch_params);
+    assert!(read_sketch == sketch);
+}
+
+#[test]
+fn fast_syncmer_test(){
+    let (command_params, fmh_params) = default_params(Mode::Dist);
+    assert!(SketchParams::new(1000, 15, 6, true, true).is_err());
+    let mut sketch_params = SketchParams::new(1000, 125, 15, true, false).unwrap();
+    assert!(sketch_params.sync_s == 11);
+    assert!(sketch_params.set_syncmers(15, false).is_err());
+
+    let seq = random_seq(7, 4 * 5000 + K_MARKER_DNA - 1);
+    let file = vec!["./test_files/e.coli-W.fasta.gz".to_string()];
+    let fmh_sketch = fastx_to_sketches(&file, &fmh_params, true).unwrap()[0].clone();
+    for closed in [false, true]{
+        sketch_params.set_syncmers(9, closed).unwrap();
+        let mut scalar_sketch = Sketch::default();
+        let mut avx2_sketch = Sketch::default();
+        fmh_seeds(&seq, &sketch_params, 0, &mut scalar_sketch, true);
+        unsafe{
+            avx2_fmh_seeds(&seq, &sketch_params, 0, &mut avx2_sketch, true);
+        }
+        assert!(scalar_sketch == avx2_sketch);
+
+        let sketch = fastx_to_sketches(&file, &sketch_params, true).unwrap()[0].clone();
+        let seeds = sketch.kmer_seeds_k.as_ref().unwrap();
+        assert!(seeds.keys().all(|x| is_syncmer(*x as u64, &sketch_params)));
+        let density = seeds.len() as f64 / fmh_sketch.num_seeds().unwrap() as f64;
+        assert!(density > 0.8 && density < 1.2);
+        assert!(sketch.marker_seeds == fmh_sketch.marker_seeds);
+
+        let map_params = map_params_from_sketch(&sketch, false, &command_params, &None);
+        let ani_res = chain_seeds(&sketch, &sketch, map_params);
+        assert!(ani_res.ani >= 0.999);
+    }
+}
+
+#[test]
+fn fast_spaced_seed_test(){
+    let (command_params, fmh_params) = default_params(Mode::Dist);
+    let mut sketch_params = fmh_params.clone();
+    assert!(sketch_params.set_seed_pattern("0110111").is_err());
+    assert!(sketch_params.set_seed_pattern("1120111").is_err());
+    assert!(SketchParams::new(1000, 125, 15, true, false).unwrap().set_seed_pattern("1101").is_err());
+    sketch_params.set_seed_pattern("1101101101101111").unwrap();
+    assert!(sketch_params.k == 16);
+    assert!(sketch_params.seed_weight() == 12);
+    assert!(sketch_params.seed_mask().count_ones() == 24);
+
+    let seq = random_seq(11, 1_000_000);
+    //About 88% identity.
+    let mut state: u64 = 12;
+    let mut mutated = seq.clone();
+    for base in mutated.iter_mut(){
+        let random = next_random(&mut state);
+        if (random >> 32) % 100 < 12{
+            *base = b"ACGT"[((random >> 62) as usize % 3 + 1 + b"ACGT".iter().position(|x| x == base).unwrap()) % 4];
+        }
+    }
+
+    let mut scalar_sketch = Sketch::default();
+    let mut avx2_sketch = Sketch::default();
+    fmh_seeds(&seq, &sketch_params, 0, &mut scalar_sketch, true);
+    unsafe{
+        avx2_fmh_seeds(&seq, &sketch_params, 0, &mut avx2_sketch, true);
+    }
+    assert!(sorted_seeds(&scalar_sketch) == sorted_seeds(&avx2_sketch));
+    assert!(scalar_sketch.marker_seeds == avx2_sketch.marker_seeds);
+    let seeds = scalar_sketch.kmer_seeds_k.as_ref().unwrap();
+    assert!(seeds.keys().all(|x| *x as u64 & !sketch_params.seed_mask() == 0));
+    assert!(scalar_sketch.seed_weight == 12);
+
+    let files = vec![write_temp_fasta("spaced_seed_ref.fa", &seq), write_temp_fasta("spaced_seed_query.fa", &mutated)];
+    let mut results = vec![];
+    for params in [&fmh_params, &sketch_params]{
+        let sketches = fastx_to_sketches(&files, params, true).unwrap();
+        let map_params = map_params_from_sketch(&sketches[0], false, &command_params, &None);
+        results.push(chain_seeds(&sketches[0], &sketches[1], map_params));
+    }
+    assert!((results[1].ani - 0.88).abs() < (results[0].ani - 0.88).abs());
+    assert!(results[1].align_fraction_query > results[0].align_fraction_query);
+}
+
+#[test]
+fn fast_soft_mask_test(){
+    let (command_params, mut sketch_params) = default_params(Mode::Dist);
+    sketch_params.c = 10;
+    //A soft-masked repeat between and after two unique regions.
+    let repeat = random_seq(13, 20_000).to_ascii_lowercase();
+    let seq = [random_seq(14, 100_000), repeat.clone(), random_seq(15, 100_000), repeat].concat();
+    let masked_start = 100_000;
+    let masked_end = 120_000;
+
+    let mut unmasked_sketch = Sketch::default();
+    let mut upper_sketch = Sketch::default();
+    fmh_seeds(&seq, &sketch_params, 0, &mut unmasked_sketch, true);
+    fmh_seeds(&seq.to_ascii_uppercase(), &sketch_params, 0, &mut upper_sketch, true);
+    assert!(unmasked_sketch == upper_sketch);
+    assert!(masked_bases(&seq, &sketch_params) == 0);
+
+    sketch_params.set_mask_lowercase(true).unwrap();
+    assert!(masked_bases(&seq, &sketch_params) == 40_000);
+    let mut scalar_sketch = Sketch::default();
+    let mut avx2_sketch = Sketch::default();
+    fmh_seeds(&seq, &sketch_params, 0, &mut scalar_sketch, true);
+    unsafe{
+        avx2_fmh_seeds(&seq, &sketch_params, 0, &mut avx2_sketch, true);
+    }
+    assert!(sorted_seeds(&scalar_sketch) == sorted_seeds(&avx2_sketch));
+    assert!(scalar_sketch.marker_seeds == avx2_sketch.marker_seeds);
+    assert!(scalar_sketch.marker_seeds.len() < unmasked_sketch.marker_seeds.len());
+    let k = sketch_params.k as u32;
+    for (_, positions) in sorted_seeds(&scalar_sketch){
+        for pos in positions{
+            assert!(pos.pos < masked_start || (pos.pos >= masked_end + k - 1 && pos.pos < 220_000));
+        }
+    }
+
+    let fasta = write_temp_fasta("soft_mask_test.fa", &seq);
+    let sketch = fastx_to_sketches(&vec![fasta], &sketch_params, true).unwrap()[0].clone();
+    assert!(sketch.masked_length == 40_000);
+    assert!(sketch.unmasked_length() == 200_000);
+    let map_params = map_params_from_sketch(&sketch, false, &command_params, &None);
+    let ani_res = chain_seeds(&sketch, &sketch, map_params);
+    assert!(ani_res.ani >= 0.999);
+    assert!(ani_res.align_fraction_query > 0.95);
+    assert!(SketchParams::new(1000, 15, 6, false, true).unwrap().set_mask_lowercase(true).is_err());
+}
+
+#[test]
+fn fast_low_complexity_mask_test(){
+    let (_command_params, mut sketch_params) = default_params(Mode::Dist);
+    sketch_params.c = 10;
+    //A homopolymer and a dinucleotide repeat between unique regions.
+    let homopolymer = vec![b'A'; 200];
+    let dinucleotide = b"AC".repeat(150);
+    let seq = [random_seq(19, 50_000), homopolymer, random_seq(20, 50_000), dinucleotide, random_seq(21, 50_000)].concat();
+    let low_complexity = [(50_000, 50_200), (100_200, 100_500)];
+
+    assert!(matches!(mask_low_complexity(&seq, &sketch_params), std::borrow::Cow::Borrowed(_)));
+    let random = random_seq(22, 100_000);
+    sketch_params.set_dust_level(Some(D_DUST_LEVEL)).unwrap();
+    assert!(matches!(mask_low_complexity(&random, &sketch_params), std::borrow::Cow::Borrowed(_)));
+    let masked = mask_low_complexity(&seq, &sketch_params);
+    for (start, end) in low_complexity{
+        assert!(masked[start..end].iter().all(|x| *x == b'N'));
+    }
+    let num_masked = masked.iter().filter(|x| **x == b'N').count();
+    assert!(num_masked >= 500 && num_masked < 500 + 4 * D_DUST_WINDOW);
+
+    //Scalar, AVX2 and windowed seeding all see the masked contig.
+    let mut scalar_sketch = Sketch::default();
+    let mut avx2_sketch = Sketch::default();
+    fmh_seeds(&masked, &sketch_params, 0, &mut scalar_sketch, true);
+    unsafe{
+        avx2_fmh_seeds(&masked, &sketch_params, 0, &mut avx2_sketch, true);
+    }
+    let mut contig_sketch = Sketch::default();
+    seed_contig(&seq, &sketch_params, 0, &mut contig_sketch, true);
+    let mut parallel_sketch = Sketch::default();
+    seed_contigs_parallel(&[(0, seq.as_slice())], &sketch_params, &mut parallel_sketch, true, 1_000);
+    assert!(sorted_seeds(&scalar_sketch) == sorted_seeds(&avx2_sketch));
+    assert!(sorted_seeds(&scalar_sketch) == sorted_seeds(&contig_sketch));
+    assert!(sorted_seeds(&scalar_sketch) == sorted_seeds(&parallel_sketch));
+    assert!(scalar_sketch.marker_seeds == parallel_sketch.marker_seeds);
+
+    let mut unmasked_sketch = Sketch::default();
+    sketch_params.set_dust_level(None).unwrap();
+    seed_contig(&seq, &sketch_params, 0, &mut unmasked_sketch, true);
+    assert!(contig_sketch.kmer_seeds_k.as_ref().unwrap().len() < unmasked_sketch.kmer_seeds_k.as_ref().unwrap().len());
+
+    assert!(sketch_params.set_dust_level(Some(0.)).is_err());
+    assert!(SketchParams::new(1000, 15, 6, false, true).unwrap().set_dust_level(Some(D_DUST_LEVEL)).is_err());
+}
+
+#[test]
+fn fast_downsample_test(){
+    let file = vec!["./test_files/e.coli-W.fasta.gz".to_string()];
+    let mut syncmer_params = SketchParams::new(200, 30, 15, true, false).unwrap();
+    syncmer_params.set_syncmers(9, true).unwrap();
+    let mut spaced_params = SketchParams::new(200, 30, 15, false, false).unwrap();
+    spaced_params.set_seed_pattern("1101101101101111").unwrap();
+    for fine_params in [SketchParams::new(200, 30, 15, false, false).unwrap(), syncmer_params, spaced_params]{
+        let coarse_params = fine_params.downsampled(125, 1000).unwrap();
+        let mut direct_params = fine_params.clone();
+        direct_params.c = 125;
+        direct_params.marker_c = 1000;
+        assert!(coarse_params == direct_params);
+
+        let mut downsampled = fastx_to_sketches(&file, &fine_params, true).unwrap()[0].clone();
+        downsample_sketch(&mut downsampled, &coarse_params);
+        let direct = fastx_to_sketches(&file, &direct_params, true).unwrap()[0].clone();
+        assert!(sorted_seeds(&downsampled) == sorted_seeds(&direct));
+        assert!(downsampled.marker_seeds == direct.marker_seeds);
+        assert!(downsampled.c == 125 && downsampled.marker_c == 1000);
+        assert!(downsampled.repetitive_kmers == direct.repetitive_kmers);
+    }
+
+    let fine_params = SketchParams::new(200, 30, 15, false, false).unwrap();
+    assert!(fine_params.downsampled(20, 1000).is_err());
+    assert!(fine_params.downsampled(125, 100).is_err());
+    assert!(fine_params.downsampled(300, 200).is_err());
+    assert!(SketchParams::new(200, 15, 6, false, true).unwrap().downsampled(30, 200).is_err());
+    let coarse_params = SketchParams::new(1000, 125, 15, false, false).unwrap();
+    assert!(fine_params.common_downsampled(&coarse_params) == Some(coarse_params.clone()));
+    assert!(coarse_params.common_downsampled(&fine_params) == Some(coarse_params));
+    assert!(fine_params.common_downsampled(&SketchParams::new(1000, 125, 14, false, false).unwrap()).is_none());
+}
+
+#[test]
+fn fast_reads_test(){
+    use skani::reads::*;
+    let (_command_params, sketch_params) = default_params(Mode::Dist);
+    assert!((estimate_coverage(&[20; 100], 2) - 20.).abs() < 0.1);
+    assert!(estimate_coverage(&[], 2) == 0.);
+
+    let mut reader = needletail::parse_fastx_file("./test_files/e.coli-W.fasta.gz").unwrap();
+    let genome = reader.next().unwrap().unwrap().seq()[..1_000_000].to_vec();
+    let genome_file = write_temp_fasta("reads_test_genome.fa", &genome);
+    let ref_sketch = fastx_to_sketches(&vec![genome_file], &sketch_params, true).unwrap()[0].clone();
+
+    //150 bp reads with 1% substitution errors.
+    let mut state: u64 = 23;
+    let mut next = || next_random(&mut state) >> 33;
+    let read_length = 150;
+    let mut write_reads = |coverage: usize, name: &str| {
+        let mut fastq = vec![];
+        for i in 0..coverage * genome.len() / read_length{
+            let start = next() as usize % (genome.len() - read_length);
+            let mut read = genome[start..start + read_length].to_vec();
+            for base in read.iter_mut(){
+                if next() % 100 == 0{
+                    *base = b"ACGT"[(next() % 4) as usize];
+                }
+            }
+            fastq.extend(format!("@read{}\n", i).bytes());
+            fastq.extend(read);
+            fastq.extend(b"\n+\n");
+            fastq.extend(vec![b'I'; read_length]);
+            fastq.push(b'\n');
+        }
+        let file = temp_path(name);
+        std::fs::write(&file, fastq).unwrap();
+        GenomeGroup{ name: file.clone(), files: vec![file] }
+    };
+
+    let high_coverage = write_reads(10, "reads_test_10x.fq");
+    let read_sketch = reads_to_sketch(&high_coverage, &sketch_params, 2).unwrap();
+    assert!(read_sketch.num_reads == 10 * 1_000_000 / read_length);
+    let ani_res = containment_ani(&read_sketch, &ref_sketch).unwrap();
+    assert!(ani_res.ani > 0.995);
+    assert!(ani_res.align_fraction_ref > 0.95);
+    //Seeds start after the first marker k-mer of a read and are lost to errors.
+    assert!(ani_res.effective_coverage > 6. && ani_res.effective_coverage < 9.);
+
+    //Without the coverage correction, half of the seeds would be missing at 2x.
+    let low_coverage = write_reads(2, "reads_test_2x.fq");
+    let read_sketch = reads_to_sketch(&low_coverage, &sketch_params, 2).unwrap();
+    let ani_res = containment_ani(&read_sketch, &ref_sketch).unwrap();
+    assert!(ani_res.ani > 0.99);
+    assert!(ani_res.effective_coverage > 1. && ani_res.effective_coverage < 2.);
+
+    let unrelated = fastx_to_sketches(&vec!["./test_files/o157_plasmid.fasta".to_string()], &sketch_params, true).unwrap()[0].clone();
+    assert!(containment_ani(&read_sketch, &unrelated).map_or(true, |x| x.ani < 0.8));
+
+    let missing = GenomeGroup{name: "missing".to_string(), files: vec!["./test_files/does_not_exist.fq".to_string()]};
+    assert!(matches!(reads_to_sketch(&missing, &sketch_params, 2), Err(skani::error::SkaniError::InvalidFasta(_))));
+}
+
+#[test]
+fn fast_ambiguous_bases_test(){
+    let (_command_params, mut sketch_params) = default_params(Mode::Dist);
+    sketch_params.c = 10;
+    let mut seq = random_seq(17, 100_000);
+    let codes = b"RYKMSWBDHVNrykmswbdhvn";
+    let ambiguous_positions = (0..codes.len()).map(|i| 1000 + 4000 * i).collect::<Vec<usize>>();
+    for (i, pos) in ambiguous_positions.iter().enumerate(){
+        seq[*pos] = codes[i];
+    }
+    assert!(ambiguous_bases(&seq) == codes.len());
+
+    let mut scalar_sketch = Sketch::default();
+    let mut avx2_sketch = Sketch::default();
+    fmh_seeds(&seq, &sketch_params, 0, &mut scalar_sketch, true);
+    unsafe{
+        avx2_fmh_seeds(&seq, &sketch_params, 0, &mut avx2_sketch, true);
+    }
+    assert!(sorted_seeds(&scalar_sketch) == sorted_seeds(&avx2_sketch));
+    assert!(scalar_sketch.marker_seeds == avx2_sketch.marker_seeds);
+    let k = sketch_params.k;
+    for (_, positions) in sorted_seeds(&scalar_sketch){
+        for pos in positions{
+            let end = pos.pos as usize;
+            assert!(ambiguous_positions.iter().all(|x| *x > end || *x + k <= end));
+        }
+    }
+
+    //Ambiguity codes used to be read as A.
+    let as_a = seq.iter().map(|x| if BYTE_IS_AMBIGUOUS[*x as usize] { b'A' } else { *x }).collect::<Vec<u8>>();
+    let mut as_a_sketch = Sketch::default();
+    fmh_seeds(&as_a, &sketch_params, 0, &mut as_a_sketch, true);
+    assert!(scalar_sketch.num_seeds().unwrap() < as_a_sketch.num_seeds().unwrap());
+    assert!(scalar_sketch.marker_seeds.is_subset(&as_a_sketch.marker_seeds));
+
+    let fasta = write_temp_fasta("ambiguous_bases_test.fa", &seq);
+    let sketch = fastx_to_sketches(&vec![fasta], &sketch_params, true).unwrap()[0].clone();
+    assert!(sketch.ambiguous_bases == codes.len());
+}
+
+#[test]
+fn fast_repetitive_kmers_test(){
+    let (command_params, mut sketch_params) = default_params(Mode::Dist);
+    sketch_params.c = 30;
+    assert!(get_repetitive_kmers(&Sketch::default()) == 0);
+    //An IS-like element with 30 copies and a shorter one with 60 copies.
+    let element = random_seq(19, 3_000);
+    let short_element = random_seq(20, 100);
+    let mut seq = vec![];
+    for i in 0..60{
+        seq.extend(random_seq(100 + i, 30_000));
+        if i % 2 == 0{
+            seq.extend(&element);
+        }
+        seq.extend(&short_element);
+    }
+    let fasta = write_temp_fasta("repetitive_kmers_test.fa", &seq);
+    let sketch = fastx_to_sketches(&vec![fasta], &sketch_params, true).unwrap()[0].clone();
+    //Only the seeds of the 60-copy element are above the cutoff.
+    assert!(sketch.repetitive_kmers == 30);
+    let kmer_seeds = sketch.kmer_seeds_k.as_ref().unwrap();
+    let masked = count_repetitive_seeds(kmer_seeds, sketch.repetitive_kmers);
+    assert!(masked > 0);
+    assert!(masked == count_repetitive_seeds(kmer_seeds, 59));
+
+    let map_params = map_params_from_sketch(&sketch, false, &command_params, &None);
+    let mut unset_sketch = sketch.clone();
+    unset_sketch.repetitive_kmers = 0;
+    assert!(pair_repetitive_cutoff(&sketch, &unset_sketch, &map_params) == 30);
+    assert!(pair_repetitive_cutoff(&unset_sketch, &unset_sketch, &map_params) == BP_CHAIN_BAND / 30);
+    let ani_res = chain_seeds(&sketch, &sketch, map_params);
+    assert!(ani_res.ani >= 0.999);
+}
+
+#[test]
+fn fast_marker_index_test(){
+    use skani::format::*;
+    use skani::marker_index::*;
+    use skani::screen::*;
+    let (_, sketch_params) = default_params(Mode::Sketch);
+    let files = vec!["./test_files/o157_plasmid.fasta".to_string(), "./test_files/e.coli-W.fasta.gz".to_string(), "./test_files/viruses.fna".to_string()];
+    let markers = fastx_to_sketches(&files, &sketch_params, true).unwrap().iter().map(Sketch::get_markers_only).collect::<Vec<Sketch>>();
+    let marker_file = temp_path("marker_index_test_markers.bin");
+    let index_file = temp_path("marker_index_test.index");
+    let markers_header = write_marker_file(&marker_file, &sketch_params, &markers).unwrap();
+    write_marker_index(&index_file, &markers_header, &markers).unwrap();
+
+    let (header, stored) = StoredMarkerIndex::open(&index_file).unwrap();
+    assert!(header.markers_checksum == Some(markers_header.checksum));
+    assert!(header.num_sketches == 3);
+    let kmer_to_sketch = kmer_to_sketch_from_refs(&markers);
+    assert!(stored.num_kmers() == kmer_to_sketch.len());
+    for (kmer, ids) in kmer_to_sketch.iter(){
+        let mut stored_ids = vec![];
+        stored.for_each_ref(kmer, &mut |x| stored_ids.push(x));
+        assert!(stored_ids == ids.to_vec());
+    }
+    let mut stored_ids = vec![];
+    let missing = (0..u64::MAX).find(|x| !kmer_to_sketch.contains_key(x)).unwrap();
+    stored.for_each_ref(&missing, &mut |x| stored_ids.push(x));
+    assert!(stored_ids.is_empty());
+}
+
+#[test]
+fn fast_ref_cache_test(){
+    use skani::cache::*;
+    let (_, sketch_params) = default_params(Mode::Sketch);
+    let files = vec!["./test_files/o157_plasmid.fasta".to_string(), "./test_files/viruses.fna".to_string()];
+    let sketches = fastx_to_sketches(&files, &sketch_params, true).unwrap();
+    let max_bytes = sketches.iter().map(sketch_mem_bytes).max().unwrap();
+    let cache = RefCache::new(max_bytes);
+
+    let loaded = cache.get_or_load("a", || Ok(sketches[0].clone())).unwrap();
+    assert!(*loaded == sketches[0]);
+    let loaded = cache.get_or_load("a", || panic!("cached sketch loaded again")).unwrap();
+    assert!(*loaded == sketches[0]);
+    cache.get_or_load("b", || Ok(sketches[1].clone())).unwrap();
+    cache.get_or_load("a", || Ok(sketches[0].clone())).unwrap();
+    let stats = cache.stats();
+    assert!(stats.hits == 1);
+    assert!(stats.misses == 3);
+    assert!(stats.evictions == 2);
+    assert!(stats.entries == 1);
+    assert!(stats.bytes <= max_bytes);
+
+    let no_cache = RefCache::new(0);
+    no_cache.get_or_load("a", || Ok(sketches[0].clone())).unwrap();
+    no_cache.get_or_load("a", || Ok(sketches[0].clone())).unwrap();
+    assert!(no_cache.stats().misses == 2);
+    assert!(no_cache.stats().entries == 0);
+}
+
+#[test]
+fn fast_manifest_test(){
+    use skani::db::*;
+    let mut manifest = Manifest::default();
+    assert!(manifest.assign("sampleA/bin.1.fa") == "bin.1.fa.sketch");
+    assert!(manifest.assign("sampleB/bin.1.fa") == "sampleB_bin.1.fa.sketch");
+    assert!(manifest.assign("/data/sampleB/bin.1.fa") == "data_sampleB_bin.1.fa.sketch");
+    assert!(manifest.assign("sampleB_bin.1.fa") == "sampleB_bin.1.fa.2.sketch");
+    assert!(manifest.assign("sampleB/bin.1.fa") == "sampleB_bin.1.fa.sketch");
+    assert!(manifest.len() == 4);
+
+    let dir = std::path::PathBuf::from(temp_path("manifest_test"));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    manifest.write(&dir).unwrap();
+    let read = Manifest::open(&dir, &[]).unwrap();
+    assert!(read == manifest);
+    manifest.retain(|x| x.starts_with("sampleB"));
+    assert!(manifest.entries().len() == 2);
+    assert!(manifest.sketch_name("sampleA/bin.1.fa") == "bin.1.fa.sketch");
+
+    let (_, sketch_params) = default_params(Mode::Sketch);
+    let markers = fastx_to_sketches(&vec!["./test_files/o157_plasmid.fasta".to_string()], &sketch_params, true).unwrap();
+    let legacy = Manifest::open(&dir.join("missing"), &markers).unwrap();
+    assert!(legacy.sketch_name("./test_files/o157_plasmid.fasta") == "o157_plasmid.fasta.sketch");
+}
+
+#[test]
+fn fast_genome_groups_test(){
+    let (_, sketch_params) = default_params(Mode::Sketch);
+    let files = vec!["./test_files/e.coli-W.fasta.gz".to_string(), "./test_files/o157_plasmid.fasta".to_string()];
+    let separate = fastx_to_sketches(&files, &sketch_params, true).unwrap();
+    let groups = vec![GenomeGroup{name: "W_with_plasmid".to_string(), files: files.clone()}];
+    let grouped = groups_to_sketches(&groups, &sketch_params, true).unwrap();
+    assert!(grouped.len() == 1);
+    let grouped = &grouped[0];
+    assert!(grouped.file_name == "W_with_plasmid");
+    let contigs = separate[0].contigs.iter().chain(separate[1].contigs.iter()).cloned().collect::<Vec<String>>();
+    assert!(grouped.contigs == contigs);
+    assert!(grouped.total_sequence_length == separate[0].total_sequence_length + separate[1].total_sequence_length);
+    let max_contig = grouped.kmer_seeds_k.as_ref().unwrap().values().flat_map(|x| x.iter()).map(|x| x.contig_index).max().unwrap();
+    assert!(max_contig as usize == contigs.len() - 1);
+
+    let missing = vec![GenomeGroup{name: "missing".to_string(), files: vec!["./test_files/o157_plasmid.fasta".to_string(), "./test_files/does_not_exist.fa".to_string()]}];
+    assert!(groups_to_sketches(&missing, &sketch_params, true).unwrap().is_empty());
+}
+
+#[test]
+fn fast_invalid_fasta_test(){
+    let (_, sketch_params) = default_params(Mode::Sketch);
+    let not_fasta = temp_path("invalid_fasta.txt");
+    std::fs::write(&not_fasta, "not a fasta file\n").unwrap();
+    //The second record's quality line is shorter than its sequence.
+    let bad_record = temp_path("invalid_fasta.fq");
+    let plasmid = std::fs::read_to_string("./test_files/o157_plasmid.fasta").unwrap();
+    let seq = plasmid.lines().skip(1).collect::<String>();
+    std::fs::write(&bad_record, format!("@read1\n{}\n+\n{}\n@read2\n{}\n+\nII\n", &seq[..1000], "I".repeat(1000), &seq[..1000])).unwrap();
+
+    //Invalid files are skipped instead of failing the whole run.
+    for file in [&not_fasta, &bad_record]{
+        let files = vec![file.clone()];
+        assert!(fastx_to_sketches(&files, &sketch_params, true).unwrap().is_empty());
+        assert!(fastx_to_split_sketches(&files, &sketch_params, &GenomeSplit::Separator("|".to_string()), true).unwrap().is_empty());
+        let contigs = fastx_to_multiple_sketch_rewrite(&files, &sketch_params, true).unwrap();
+        assert!(contigs.iter().all(|x| x.contigs == vec!["read1"]));
+    }
+    std::fs::remove_file(not_fasta).unwrap();
+    std::fs::remove_file(bad_record).unwrap();
+}
+
+fn write_catalog(file: &str){
+    //Two viruses and the o157 plasmid in two records, as one multi-genome fasta.
+    let viruses = std::fs::read_to_string("./test_files/viruses.fna").unwrap();
+    let plasmid = std::fs::read_to_string("./test_files/o157_plasmid.fasta").unwrap();
+    let plasmid_seq = plasmid.lines().skip(1).collect::<String>();
+    let half = plasmid_seq.len() / 2;
+    let mut catalog = String::new();
+    for (i, record) in viruses.split('>').skip(1).take(2).enumerate(){
+        let (_, seq) = record.split_once('\n').unwrap();
+        catalog.push_str(&format!(">virus{}|contig_1 some description\n{}", i, seq));
+    }
+    catalog.push_str(&format!(">plasmid|contig_1\n{}\n>plasmid|contig_2\n{}\n", &plasmid_seq[..half], &plasmid_seq[half..]));
+    std::fs::write(file, catalog).unwrap();
+}
+
+#[test]
+fn fast_genome_split_test(){
+    let split = GenomeSplit::Separator("|".to_string());
+    assert!(split.genome_name("genomeA|contig_1") == Some("genomeA"));
+    assert!(split.genome_name("contig_1").is_none());
+    let split = GenomeSplit::Separator("_".to_string());
+    assert!(split.genome_name("bin_3_12") == Some("bin_3"));
+    let split = GenomeSplit::Regex(regex::Regex::new("^([^_]+)_").unwrap());
+    assert!(split.genome_name("bin_3_12") == Some("bin"));
+    assert!(split.genome_name("bin3").is_none());
+    let split = GenomeSplit::Regex(regex::Regex::new("^bin[0-9]").unwrap());
+    assert!(split.genome_name("bin3_12") == Some("bin3"));
+
+    let mixed = temp_path("split_test_mixed.fa");
+    std::fs::write(&mixed, std::fs::read_to_string("./test_files/o157_plasmid.fasta").unwrap()).unwrap();
+
+    let catalog = temp_path("split_test_catalog.fa");
+    write_catalog(&catalog);
+    let (_, sketch_params) = default_params(Mode::Sketch);
+    let sketches = fastx_to_split_sketches(&vec![catalog.clone()], &sketch_params, &GenomeSplit::Separator("|".to_string()), true).unwrap();
+    assert!(sketches.iter().map(|x| x.file_name.as_str()).collect::<Vec<&str>>() == vec!["plasmid", "virus0", "virus1"]);
+    let plasmid = &sketches[0];
+    assert!(plasmid.contigs == vec!["plasmid|contig_1", "plasmid|contig_2"]);
+    let whole = fastx_to_sketches(&vec!["./test_files/o157_plasmid.fasta".to_string()], &sketch_params, true).unwrap();
+    assert!(plasmid.total_sequence_length == whole[0].total_sequence_length);
+    let max_contig = plasmid.kmer_seeds_k.as_ref().unwrap().values().flat_map(|x| x.iter()).map(|x| x.contig_index).max().unwrap();
+    assert!(max_contig == 1);
+    assert!(sketches[1].contigs.len() == 1);
+
+    let sketches = fastx_to_split_sketches(&vec![mixed.clone()], &sketch_params, &GenomeSplit::Separator("|".to_string()), true).unwrap();
+    assert!(sketches.len() == 1);
+    assert!(sketches[0].file_name == mixed);
+}